@@ -0,0 +1,80 @@
+use std::hint::black_box;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use either::Either;
+
+use whoisthere::app::NameTracker;
+use whoisthere::data::{update_db_batch, Ipv4StatsKey, NameStats, Stats, StatsKey, StatsUpdate};
+use whoisthere::icmp::{self, IcmpStats};
+use whoisthere::packet::proc_packet;
+use whoisthere::replay::process_frames;
+use whoisthere::runtime::Runtime;
+
+const IPV4_TCP: &[u8] = include_bytes!("fixtures/ipv4_tcp.bin");
+/// Opens the connection IPV4_TCP sends its request on
+const IPV4_TCP_SYN: &[u8] = include_bytes!("fixtures/ipv4_tcp_syn.bin");
+const IPV4_ICMP: &[u8] = include_bytes!("fixtures/ipv4_icmp.bin");
+const IPV6_UDP: &[u8] = include_bytes!("fixtures/ipv6_udp.bin");
+const VLAN_IPV4_TCP: &[u8] = include_bytes!("fixtures/vlan_ipv4_tcp.bin");
+const MALFORMED: &[u8] = include_bytes!("fixtures/malformed.bin");
+
+const FLOWS: u32 = 10_000;
+
+fn update(i: u32) -> StatsUpdate {
+    let key = StatsKey(Either::Left(Ipv4StatsKey { source: Ipv4Addr::from(i), dest: Ipv4Addr::from(0x0a000001) }), None);
+    StatsUpdate { key, length: 64, ip_length: 64, wire_length: 78, retransmission: false, opening: false, empty: false, arrived: None, window: None, app_protocol: None }
+}
+
+// Dropped frames log to stderr, bench with 2>/dev/null
+fn parse(c: &mut Criterion) {
+    let runtime = Runtime::default();
+    let mut group = c.benchmark_group("proc_packet");
+    for (name, frame) in [("ipv4 tcp", IPV4_TCP), ("ipv6 udp", IPV6_UDP), ("vlan", VLAN_IPV4_TCP), ("malformed", MALFORMED)] {
+        group.bench_function(name, |b| b.iter(|| proc_packet(black_box(frame), &runtime, &[]).map(|p| p.length)));
+    }
+    group.finish();
+}
+
+fn update_db(c: &mut Criterion) {
+    let db = Mutex::new(Stats::new());
+    update_db_batch(db.lock().unwrap(), (0..FLOWS).map(update), 0, false);
+
+    let mut group = c.benchmark_group("update_db_batch");
+    group.bench_function("1 update", |b| b.iter(|| update_db_batch(db.lock().unwrap(), [update(black_box(42))], 0, false)));
+    group.bench_function("64 updates", |b| b.iter(|| update_db_batch(db.lock().unwrap(), (0..64).map(|i| update(black_box(i * 100))), 0, false)));
+    group.finish();
+}
+
+// What each packet goes through on its way to the queue, on traffic they have seen before
+fn observe(c: &mut Criterion) {
+    let runtime = Runtime::default();
+    let mut group = c.benchmark_group("observe");
+
+    let icmp_stats = Mutex::new(IcmpStats::default());
+    let echo = proc_packet(IPV4_ICMP, &runtime, &[]).unwrap();
+    icmp::observe(&echo, &icmp_stats);
+    group.bench_function("icmp", |b| b.iter(|| icmp::observe(black_box(&echo), &icmp_stats)));
+
+    let names = Mutex::new(NameStats::default());
+    let mut http_host = NameTracker::http_host(2048);
+    let (syn, request) = (proc_packet(IPV4_TCP_SYN, &runtime, &[]).unwrap(), proc_packet(IPV4_TCP, &runtime, &[]).unwrap());
+    http_host.observe(&syn, &names);
+    http_host.observe(&request, &names);
+    group.bench_function("http host", |b| b.iter(|| http_host.observe(black_box(&request), &names)));
+    group.finish();
+}
+
+// Parser and storage together, a fresh map every round
+fn replay(c: &mut Criterion) {
+    let frames = [IPV4_TCP_SYN, IPV4_TCP, IPV4_ICMP, IPV6_UDP, VLAN_IPV4_TCP, MALFORMED];
+    c.bench_function("process_frames", |b| b.iter(|| {
+        let mut stats = Stats::new();
+        process_frames(&mut stats, black_box(frames));
+        stats.0.len()
+    }));
+}
+
+criterion_group!(benches, parse, update_db, observe, replay);
+criterion_main!(benches);
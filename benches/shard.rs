@@ -0,0 +1,48 @@
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use either::Either;
+
+use whoisthere::data::{update_db_batch, Ipv4StatsKey, Stats, StatsKey, StatsUpdate};
+use whoisthere::shard::ShardedStats;
+
+// Only tells anything with at least THREADS cores
+const THREADS: u32 = 4;
+const UPDATES: u32 = 10_000;
+const BATCH: u32 = 64;
+
+fn update(i: u32) -> StatsUpdate {
+    let key = StatsKey(Either::Left(Ipv4StatsKey { source: Ipv4Addr::from(i % 1024), dest: Ipv4Addr::from(0x0a000001) }), None);
+    StatsUpdate { key, length: 64, ip_length: 64, wire_length: 78, retransmission: false, opening: false, empty: false, arrived: None, window: None, app_protocol: None }
+}
+
+fn contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("concurrent updates");
+
+    let single = Mutex::new(Stats::new());
+    group.bench_function("single mutex", |b| b.iter(|| thread::scope(|s| {
+        for t in 0..THREADS {
+            let single = &single;
+            s.spawn(move || for i in (0..UPDATES).step_by(BATCH as usize) {
+                update_db_batch(single.lock().unwrap(), (i..i + BATCH).map(|j| update(t * UPDATES + j)), 0, false);
+            });
+        }
+    })));
+
+    let sharded = ShardedStats::new(Stats::new(), 16, false);
+    group.bench_function("16 shards", |b| b.iter(|| thread::scope(|s| {
+        for t in 0..THREADS {
+            let sharded = &sharded;
+            s.spawn(move || for i in (0..UPDATES).step_by(BATCH as usize) {
+                sharded.update_batch((i..i + BATCH).map(|j| update(t * UPDATES + j)));
+            });
+        }
+    })));
+
+    group.finish();
+}
+
+criterion_group!(benches, contention);
+criterion_main!(benches);
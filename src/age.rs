@@ -0,0 +1,86 @@
+use std::net::IpAddr;
+
+use pnet::packet::ip::IpNextHeaderProtocols;
+use serde::Serialize;
+
+use crate::data::{unix_now, Stats, StatsKey, StatsValue};
+
+#[derive(Serialize)]
+pub struct AgedFlow<'a> {
+    pub flow: &'a StatsKey,
+    /// Seconds since first_seen
+    pub age: u64,
+    /// Seconds since last_seen
+    pub idle: u64,
+    #[serde(flatten)]
+    pub value: &'a StatsValue,
+}
+
+fn aged(stats: &Stats, now: u64, mut keep: impl FnMut(&AgedFlow) -> bool) -> Vec<AgedFlow<'_>> {
+    stats.0.iter()
+        .map(|(flow, value)| AgedFlow {
+            flow,
+            age: now.saturating_sub(value.first_seen),
+            idle: now.saturating_sub(value.last_seen),
+            value,
+        })
+        .filter(|f| keep(f))
+        .collect()
+}
+
+/// Flows seen in the last `within` seconds, most recent first
+pub fn active(stats: &Stats, within: u64) -> Vec<AgedFlow<'_>> {
+    let mut flows = aged(stats, unix_now(), |f| f.idle <= within);
+    flows.sort_by_key(|f| f.idle);
+    flows
+}
+
+/// Flows quiet for more than `older` seconds, the longest quiet first
+pub fn idle(stats: &Stats, older: u64) -> Vec<AgedFlow<'_>> {
+    let mut flows = aged(stats, unix_now(), |f| f.idle > older);
+    flows.sort_by_key(|f| std::cmp::Reverse(f.idle));
+    flows
+}
+
+/// Flows first seen in the last `since` seconds, the most bytes first
+pub fn new_flows(stats: &Stats, since: u64) -> Vec<AgedFlow<'_>> {
+    let mut flows = aged(stats, unix_now(), |f| f.age <= since);
+    flows.sort_by_key(|f| std::cmp::Reverse(f.value.total_length));
+    flows
+}
+
+/// UDP flows nothing came back on, see `oneway`
+#[derive(Serialize)]
+pub struct OneWay<'a> {
+    /// Those old enough to have had an answer by now
+    pub udp_flows: usize,
+    pub oneway_flows: usize,
+    /// oneway_flows / udp_flows, 0 without UDP flows
+    pub ratio: f64,
+    pub flows: Vec<AgedFlow<'a>>,
+}
+
+pub(crate) fn is_group(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_multicast() || v4.is_broadcast(),
+        IpAddr::V6(v6) => v6.is_multicast()
+    }
+}
+
+/// UDP flows at least `after` seconds old the reversed 5-tuple never answered, the
+/// most packets first. Multicast and broadcast expect no answer and are left out.
+/// Host keys don't know the transport, it takes --granularity port.
+pub fn oneway(stats: &Stats, after: u64) -> OneWay<'_> {
+    let mut udp_flows = 0;
+    let mut flows = aged(stats, unix_now(), |f| {
+        let udp = f.flow.1.is_some_and(|t| t.protocol == IpNextHeaderProtocols::Udp.0);
+        if !udp || f.age < after || is_group(f.flow.addrs().1) {
+            return false;
+        }
+        udp_flows += 1;
+        !stats.0.contains_key(&f.flow.reversed())
+    });
+    flows.sort_by_key(|f| std::cmp::Reverse(f.value.total_count));
+    let ratio = if udp_flows == 0 { 0.0 } else { flows.len() as f64 / udp_flows as f64 };
+    OneWay { udp_flows, oneway_flows: flows.len(), ratio, flows }
+}
@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+use log::warn;
+use serde::Serialize;
+
+use crate::data::unix_now;
+
+/// Alerts kept for the HTTP side
+const RECENT_ALERTS: usize = 1024;
+/// Alerts waiting on a slow webhook, more are dropped
+const WEBHOOK_QUEUE: usize = 256;
+
+#[derive(Serialize, Clone)]
+pub struct Alert {
+    /// What fired it, e.g. scan or syn_flood
+    pub kind: &'static str,
+    /// Host it is about
+    pub subject: String,
+    pub message: String,
+    /// Unix seconds
+    pub at: u64,
+    /// Kind specific
+    pub details: serde_json::Value,
+}
+
+impl Alert {
+    pub fn new(kind: &'static str, subject: String, message: String, details: serde_json::Value) -> Self {
+        Alert { kind, subject, message, at: unix_now(), details }
+    }
+}
+
+/// Logs alerts, keeps the recent ones and POSTs each as JSON to the webhook
+pub struct Alerter {
+    recent: Mutex<VecDeque<Alert>>,
+    webhook: Option<SyncSender<Alert>>,
+}
+
+impl Alerter {
+    pub fn new(webhook: Option<String>) -> Self {
+        let webhook = webhook.map(|url| {
+            let (tx, rx) = mpsc::sync_channel::<Alert>(WEBHOOK_QUEUE);
+            thread::spawn(move || {
+                for alert in rx {
+                    if let Err(e) = ureq::post(&url).send_json(&alert) {
+                        warn!("Fail to deliver {} alert to {}: {}", alert.kind, url, e);
+                    }
+                }
+            });
+            tx
+        });
+        Alerter { recent: Mutex::new(VecDeque::new()), webhook }
+    }
+
+    /// Never waits on the webhook
+    pub fn fire(&self, alert: Alert) {
+        warn!(alert = alert.kind, subject = alert.subject.as_str(); "{}", alert.message);
+        if let Some(tx) = &self.webhook {
+            if tx.try_send(alert.clone()).is_err() {
+                warn!("Webhook queue full, {} alert not delivered", alert.kind);
+            }
+        }
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_ALERTS {
+            recent.pop_front();
+        }
+        recent.push_back(alert);
+    }
+
+    /// Oldest first, all kinds for None
+    pub fn recent(&self, kind: Option<&str>) -> Vec<Alert> {
+        self.recent.lock().unwrap().iter().filter(|a| kind.is_none_or(|k| a.kind == k)).cloned().collect()
+    }
+}
@@ -0,0 +1,286 @@
+use std::io::{self, BufWriter, Write};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::thread;
+
+use rouille::{router, Request, Response, ResponseBody};
+use serde::{Serialize, Serializer};
+
+use crate::age::AgedFlow;
+use crate::cidr::Cidr;
+use crate::config::parse_duration;
+use crate::data::{parse_protocol, Stats, StatsKey};
+use crate::ntop::NtopFlows;
+use crate::query::{Query, Sort};
+use crate::state::State;
+use crate::top_hosts::{self, By};
+use crate::{age, conversation, history, iface, key_format, matrix, metrics, protocols, runtime, schema, services, summary};
+
+pub fn handle(request: &Request, state: &State) -> Response {
+    router!(request,
+        (GET) ["/"] => { stats(request, state) },
+        (GET) ["/stats"] => { stats(request, state) },
+        (GET) ["/runtime"] => {
+            let kernel = state.runtime.kernel.poll();
+            Response::json(&runtime::Report {
+                counters: &state.runtime,
+                flows: state.db.len(),
+                update_sequence: state.db.sequence(),
+                flow_map_bytes: state.db.footprint(),
+                rss_bytes: runtime::rss_bytes(),
+                link: &state.link,
+                kernel_packets: kernel.map(|(packets, _)| packets),
+                kernel_dropped: kernel.map(|(_, dropped)| dropped),
+                interface_dropped: iface::dropped(&state.link),
+            })
+        },
+        (GET) ["/rate-history"] => {
+            match seconds(request, "range", 900) {
+                Ok(range) => Response::json(&history::rate_history(&state.history.lock().unwrap(), range.saturating_mul(1000))),
+                Err(response) => response
+            }
+        },
+        (GET) ["/summary"] => { Response::json(&summary::summary(&state.published(), &state.runtime, &state.link, state.bps_1m(), state.cps(), state.db.peak())) },
+        (GET) ["/text"] => {
+            let stats = state.published();
+            let summary = summary::summary(&stats, &state.runtime, &state.link, state.bps_1m(), state.cps(), state.db.peak());
+            Response::text(summary::Text { summary, pps: summary::pps(&stats) }.to_string())
+        },
+        (GET) ["/active"] => { aged(request, state, "within", 60, age::active) },
+        (GET) ["/idle"] => { aged(request, state, "older", 300, age::idle) },
+        (GET) ["/new"] => { aged(request, state, "since", 300, age::new_flows) },
+        (GET) ["/oneway"] => {
+            match seconds(request, "after", 30) {
+                Ok(after) => Response::json(&age::oneway(&state.published(), after)),
+                Err(response) => response
+            }
+        },
+        (GET) ["/asymmetric"] => {
+            match seconds(request, "after", 30) {
+                Ok(after) => Response::json(&conversation::asymmetric(&state.published(), after)),
+                Err(response) => response
+            }
+        },
+        (GET) ["/export/ntopng"] => { streamed_json(NtopFlows(state.published())) },
+        (GET) ["/top"] => { top_flows(request, state) },
+        (GET) ["/query"] => { query(request, state) },
+        (GET) ["/top-hosts"] => { top(request, state) },
+        (GET) ["/matrix.csv"] => { matrix(request, state) },
+        (GET) ["/conversations"] => { Response::json(&conversation::conversations(&state.published())) },
+        (GET) ["/protocols"] => { Response::json(&protocols::protocols(&state.published())) },
+        (GET) ["/services"] => { Response::json(&services::services(&state.published())) },
+        (GET) ["/hosts"] => { Response::json(state.fanout.lock().unwrap().deref()) },
+        (GET) ["/scans"] => { Response::json(&state.alerts.recent(Some("scan"))) },
+        (GET) ["/alerts"] => { Response::json(&state.alerts.recent(request.get_param("kind").as_deref())) },
+        (GET) ["/metrics"] => {
+            let openmetrics = metrics::wants_openmetrics(request.header("Accept"));
+            let content_type = if openmetrics { metrics::OPENMETRICS_TYPE } else { metrics::PROMETHEUS_TYPE };
+            let by_protocol = request.get_param("by").as_deref() == Some("protocol");
+            Response::from_data(content_type, metrics::render(state, openmetrics, by_protocol))
+        },
+        (GET) ["/schema"] => { Response::json(&schema::schema()) },
+        (GET) ["/sni"] => { Response::json(state.sni.lock().unwrap().deref()) },
+        (GET) ["/http"] => { Response::json(state.http.lock().unwrap().deref()) },
+        (GET) ["/neighbors"] => { Response::json(state.neighbors.lock().unwrap().deref()) },
+        (GET) ["/icmp"] => { Response::json(state.icmp.lock().unwrap().deref()) },
+        _ => Response::empty_404()
+    )
+}
+
+/// Flows of a snapshot, those with an end inside `cidr` when given
+struct Flows {
+    snapshot: Arc<Stats>,
+    cidr: Option<Cidr>,
+}
+
+impl Serialize for Flows {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer {
+        let flows = self.snapshot.0.iter().filter(|(k, _)| self.cidr.as_ref().is_none_or(|c| c.matches(k)));
+        key_format::serialize_flows(flows, serializer)
+    }
+}
+
+/// Written to the socket as it serializes, so the response never sits whole in
+/// memory. The thread holds on to what it writes until the client has it all.
+fn streamed_json(value: impl Serialize + Send + 'static) -> Response {
+    let (reader, writer) = match io::pipe() {
+        Ok(pipe) => pipe,
+        Err(e) => return Response::text(format!("Fail to open a pipe: {}", e)).with_status_code(500)
+    };
+    thread::spawn(move || {
+        let mut writer = BufWriter::new(writer);
+        // Fails when the client goes away, nothing to do about that
+        if serde_json::to_writer(&mut writer, &value).is_ok() {
+            let _ = writer.flush();
+        }
+    });
+    Response {
+        status_code: 200,
+        headers: vec![("Content-Type".into(), "application/json; charset=utf-8".into())],
+        data: ResponseBody::from_reader(reader),
+        upgrade: None,
+    }
+}
+
+/// All flows, or with `?cidr=` those with an end inside the network
+fn stats(request: &Request, state: &State) -> Response {
+    let cidr = match request.get_param("cidr").map(|c| c.parse::<Cidr>()).transpose() {
+        Ok(cidr) => cidr,
+        Err(e) => return Response::text(e).with_status_code(400)
+    };
+    streamed_json(Flows { snapshot: state.published(), cidr })
+}
+
+/// `?n=`, 20 by default
+fn count(request: &Request) -> Result<usize, Response> {
+    match request.get_param("n").map(|n| n.parse::<usize>()) {
+        None => Ok(20),
+        Some(Ok(n)) => Ok(n),
+        Some(Err(e)) => Err(Response::text(format!("Invalid n: {}", e)).with_status_code(400))
+    }
+}
+
+/// `?n=` hosts ranked by `?by=` bytes, packets or flows
+fn top(request: &Request, state: &State) -> Response {
+    let n = match count(request) {
+        Ok(n) => n,
+        Err(response) => return response
+    };
+    let by = match request.get_param("by").map(|b| b.parse::<By>()).unwrap_or(Ok(By::Bytes)) {
+        Ok(by) => by,
+        Err(e) => return Response::text(e).with_status_code(400)
+    };
+    Response::json(&top_hosts::top_hosts(&state.published(), n, by))
+}
+
+#[derive(Serialize)]
+struct TopFlow<'a> {
+    flow: &'a StatsKey,
+    total_length: u128,
+    total_count: u128,
+}
+
+/// Packets a flow needs for `?by=smallpackets`, fewer say nothing about it
+const SMALL_PACKETS_MIN_COUNT: u128 = 100;
+
+/// The `?n=` busiest flows, `?proto=` ones only, the estimated heavy hitters with --sketch.
+/// `?by=smallpackets` ranks those of `?min_count=` packets by average packet size instead.
+fn top_flows(request: &Request, state: &State) -> Response {
+    let n = match count(request) {
+        Ok(n) => n,
+        Err(response) => return response
+    };
+    let small_packets = match request.get_param("by").as_deref() {
+        None | Some("bytes") => None,
+        Some("smallpackets") => match request.get_param("min_count").map(|c| c.parse::<u128>()) {
+            None => Some(SMALL_PACKETS_MIN_COUNT),
+            Some(Ok(min_count)) => Some(min_count),
+            Some(Err(e)) => return Response::text(format!("Invalid min_count: {}", e)).with_status_code(400)
+        },
+        Some(by) => return Response::text(format!("Unknown ranking: {}, expected bytes or smallpackets", by)).with_status_code(400)
+    };
+    let protocol = match request.get_param("proto").map(|p| parse_protocol(&p).ok_or(p)).transpose() {
+        Ok(protocol) => protocol,
+        Err(p) => return Response::text(format!("Unknown protocol: {}", p)).with_status_code(400)
+    };
+    // Flows without a transport key, all of them with --granularity host, have no protocol
+    let wanted = |k: &StatsKey| protocol.is_none_or(|p| k.1.is_some_and(|t| t.protocol == p));
+    if let Some(sketch) = &state.sketch {
+        let mut flows = sketch.lock().unwrap().heavy_hitters();
+        flows.retain(|h| wanted(&h.flow));
+        if let Some(min_count) = small_packets {
+            flows.retain(|h| h.total_count as u128 >= min_count.max(1));
+            flows.sort_by(|a, b| (a.total_length as f64 / a.total_count as f64).total_cmp(&(b.total_length as f64 / b.total_count as f64))
+                .then(b.total_count.cmp(&a.total_count)));
+        }
+        flows.truncate(n);
+        return Response::json(&flows);
+    }
+    let stats = state.published();
+    let flows = match small_packets {
+        Some(min_count) => stats.smallest_packets(n, min_count, wanted),
+        None => stats.top_where(n, wanted)
+    };
+    let flows: Vec<_> = flows.into_iter()
+        .map(|(k, v)| TopFlow { flow: k, total_length: v.total_length, total_count: v.total_count })
+        .collect();
+    Response::json(&flows)
+}
+
+/// `?<param>=` parsed, None without
+fn param<T: std::str::FromStr>(request: &Request, name: &str) -> Result<Option<T>, Response>
+    where T::Err: std::fmt::Display {
+    match request.get_param(name).map(|v| v.parse::<T>()) {
+        None => Ok(None),
+        Some(Ok(v)) => Ok(Some(v)),
+        Some(Err(e)) => Err(Response::text(format!("Invalid {}: {}", name, e)).with_status_code(400))
+    }
+}
+
+/// The flows `?host=`, `?cidr=`, `?proto=`, `?port=` and `?min_bytes=` all match, sorted by `?sort=`
+/// in `?order=`, `?limit=` of them from `?offset=`
+fn query(request: &Request, state: &State) -> Response {
+    let parsed = || -> Result<Query, Response> {
+        let defaults = Query::default();
+        let sort = request.get_param("sort").map(|s| s.parse::<Sort>()).transpose()
+            .map_err(|e| Response::text(e).with_status_code(400))?.unwrap_or(defaults.sort);
+        let descending = match request.get_param("order").as_deref() {
+            // Biggest and latest first, flow keys in alphabetical order
+            None => sort != Sort::Flow,
+            Some("desc") => true,
+            Some("asc") => false,
+            Some(o) => return Err(Response::text(format!("Unknown order: {}, expected asc or desc", o)).with_status_code(400))
+        };
+        let protocol = match request.get_param("proto") {
+            None => None,
+            Some(p) => Some(parse_protocol(&p).ok_or(Response::text(format!("Unknown protocol: {}", p)).with_status_code(400))?)
+        };
+        Ok(Query {
+            host: param(request, "host")?,
+            cidr: param(request, "cidr")?,
+            protocol,
+            port: param(request, "port")?,
+            min_bytes: param(request, "min_bytes")?.unwrap_or(defaults.min_bytes),
+            sort,
+            descending,
+            limit: param(request, "limit")?.unwrap_or(defaults.limit),
+            offset: param(request, "offset")?.unwrap_or(defaults.offset),
+        })
+    };
+    match parsed() {
+        Ok(query) => Response::json(&query.run(&state.published())),
+        Err(response) => response
+    }
+}
+
+/// Bytes between the `?n=` busiest hosts (up to matrix::MAX_HOSTS), a grid or with `?format=long` a row per pair
+fn matrix(request: &Request, state: &State) -> Response {
+    let n = match count(request) {
+        Ok(n) if n > matrix::MAX_HOSTS => return Response::text(format!("n is at most {}", matrix::MAX_HOSTS)).with_status_code(400),
+        Ok(n) => n,
+        Err(response) => return response
+    };
+    let csv = match request.get_param("format").as_deref() {
+        None | Some("grid") => matrix::grid(&state.published(), n),
+        Some("long") => matrix::long(&state.published(), n),
+        Some(f) => return Response::text(format!("Unknown format: {}, expected grid or long", f)).with_status_code(400)
+    };
+    Response::from_data("text/csv; charset=utf-8", csv)
+}
+
+/// `?<param>=` as a duration in seconds, e.g. 60s or 5m, `default` without
+fn seconds(request: &Request, param: &str, default: u64) -> Result<u64, Response> {
+    match request.get_param(param).map(|d| parse_duration(&d)) {
+        None => Ok(default),
+        Some(Ok(d)) => Ok(d.as_secs()),
+        Some(Err(e)) => Err(Response::text(e).with_status_code(400))
+    }
+}
+
+fn aged(request: &Request, state: &State, param: &str, default: u64, f: fn(&Stats, u64) -> Vec<AgedFlow<'_>>) -> Response {
+    match seconds(request, param, default) {
+        Ok(secs) => Response::json(&f(&state.published(), secs)),
+        Err(response) => response
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::data::{NameStats, NameValue, StatsKey};
+use crate::packet::PacketInfo;
+use crate::reader::Reader;
+use crate::table::IdleTable;
+
+const HTTPS_PORT: u16 = 443;
+const HTTP_PORT: u16 = 80;
+/// Give up naming a connection not done after this many client segments or bytes
+const MAX_SEGMENTS: u8 = 4;
+const MAX_HEAD_LEN: usize = 16 * 1024;
+/// HTTP request heads are only looked for this far
+const MAX_HTTP_SCAN: usize = 2048;
+const MAX_CONNECTIONS: usize = 65536;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+const HTTP_METHODS: [&[u8]; 9] = [b"GET ", b"POST ", b"HEAD ", b"PUT ", b"DELETE ", b"OPTIONS ", b"PATCH ", b"CONNECT ", b"TRACE "];
+
+enum Conn {
+    Pending { head: Vec<u8>, segments: u8 },
+    Named(String),
+    Unnamed,
+}
+
+enum Parse {
+    Incomplete,
+    Done(Option<String>),
+}
+
+/// Attributes a TCP service's traffic to the name found at the start of each connection
+pub struct NameTracker {
+    port: u16,
+    parse: fn(&[u8]) -> Parse,
+    // Another request on an already named connection?
+    is_hit: fn(&[u8]) -> bool,
+    // Keyed client to server
+    conns: IdleTable<(StatsKey, u16, u16), Conn>,
+    /// --deep-inspect-bytes, most bytes of any one segment looked at
+    inspect: usize,
+}
+
+fn parse_hello(handshake: &[u8]) -> Option<String> {
+    let mut r = Reader(handshake);
+    // ClientHello
+    if r.u8()? != 1 {
+        return None;
+    }
+    // Length, version and random
+    r.take(3 + 2 + 32)?;
+    let session_id = r.u8()? as usize;
+    r.take(session_id)?;
+    let cipher_suites = r.u16()? as usize;
+    r.take(cipher_suites)?;
+    let compression = r.u8()? as usize;
+    r.take(compression)?;
+    let extensions = r.u16()? as usize;
+    let mut extensions = Reader(r.take(extensions)?);
+    while let (Some(kind), Some(len)) = (extensions.u16(), extensions.u16()) {
+        let mut ext = Reader(extensions.take(len as usize)?);
+        // server_name
+        if kind != 0 {
+            continue;
+        }
+        let list = ext.u16()? as usize;
+        let mut list = Reader(ext.take(list)?);
+        while let Some(name_type) = list.u8() {
+            let len = list.u16()? as usize;
+            let name = list.take(len)?;
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok().map(|s| s.to_ascii_lowercase());
+            }
+        }
+    }
+    None
+}
+
+fn client_hello_sni(data: &[u8]) -> Parse {
+    if data.len() < 5 {
+        return Parse::Incomplete;
+    }
+    // Handshake record of some TLS version
+    if data[0] != 0x16 || data[1] != 0x03 {
+        return Parse::Done(None);
+    }
+    let end = 5 + u16::from_be_bytes([data[3], data[4]]) as usize;
+    if data.len() < end {
+        return Parse::Incomplete;
+    }
+    Parse::Done(parse_hello(&data[5..end]))
+}
+
+fn is_http_request(data: &[u8]) -> bool {
+    HTTP_METHODS.iter().any(|m| data.starts_with(m))
+}
+
+fn http_host(data: &[u8]) -> Parse {
+    if !is_http_request(data) {
+        // Could still be a method cut short by the segment
+        return if data.len() < 8 { Parse::Incomplete } else { Parse::Done(None) };
+    }
+    let data = &data[..data.len().min(MAX_HTTP_SCAN)];
+    let head_done = data.windows(4).any(|w| w == b"\r\n\r\n");
+    let host = data.split(|&b| b == b'\n')
+        .skip(1)
+        .filter_map(|line| std::str::from_utf8(line).ok())
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("host").then(|| value.trim().to_ascii_lowercase())
+        });
+    match host {
+        Some(host) => Parse::Done(Some(host.strip_suffix(":80").map(str::to_string).unwrap_or(host))),
+        None if head_done || data.len() == MAX_HTTP_SCAN => Parse::Done(None),
+        None => Parse::Incomplete
+    }
+}
+
+impl NameTracker {
+    /// By TLS ClientHello server name on port 443
+    pub fn sni(inspect: usize) -> Self {
+        NameTracker::new(HTTPS_PORT, client_hello_sni, |_| false, inspect)
+    }
+
+    /// By HTTP Host header on port 80, counting every request of keep-alive connections
+    pub fn http_host(inspect: usize) -> Self {
+        NameTracker::new(HTTP_PORT, http_host, is_http_request, inspect)
+    }
+
+    fn new(port: u16, parse: fn(&[u8]) -> Parse, is_hit: fn(&[u8]) -> bool, inspect: usize) -> Self {
+        NameTracker { port, parse, is_hit, conns: IdleTable::new(MAX_CONNECTIONS, IDLE_TIMEOUT), inspect }
+    }
+
+    pub fn observe(&mut self, info: &PacketInfo, names: &Mutex<NameStats>) {
+        let tcp = match &info.tcp {
+            Some(tcp) => tcp,
+            None => return
+        };
+        let to_server = tcp.dest_port == self.port;
+        let conn = if to_server {
+            (info.key, tcp.source_port, tcp.dest_port)
+        } else if tcp.source_port == self.port {
+            (info.key.reversed(), tcp.dest_port, tcp.source_port)
+        } else {
+            return;
+        };
+
+        let state = match self.conns.get_mut(&conn) {
+            Some(state) => state,
+            None => {
+                // Mid-connection when we started listening, the interesting part is long gone
+                let state = if info.payload.is_empty() {
+                    Conn::Pending { head: Vec::new(), segments: 0 }
+                } else {
+                    Conn::Unnamed
+                };
+                self.conns.insert(conn, state);
+                return;
+            }
+        };
+
+        let payload = &info.payload[..info.payload.len().min(self.inspect)];
+        let mut hit = false;
+        if let Conn::Pending { head, segments } = state {
+            if !to_server || payload.is_empty() {
+                return;
+            }
+            head.extend_from_slice(payload);
+            *segments += 1;
+            *state = match (self.parse)(head) {
+                Parse::Done(Some(name)) => {
+                    hit = true;
+                    Conn::Named(name)
+                }
+                Parse::Done(None) => Conn::Unnamed,
+                Parse::Incomplete if *segments >= MAX_SEGMENTS || head.len() > MAX_HEAD_LEN => Conn::Unnamed,
+                Parse::Incomplete => return
+            };
+        } else if to_server {
+            hit = (self.is_hit)(payload);
+        }
+        if let Conn::Named(name) = state {
+            let mut names = names.lock().unwrap();
+            // Not entry(), that wants the name cloned for every packet
+            if !names.0.contains_key(name) {
+                names.0.insert(name.clone(), NameValue::default());
+            }
+            let entry = names.0.get_mut(name).unwrap();
+            entry.total_count += 1;
+            entry.total_length += info.length;
+            if hit {
+                entry.hits += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::conversation::ConversationKey;
+use crate::data::StatsKey;
+use crate::packet::PacketInfo;
+use crate::table::IdleTable;
+
+const DNS_PORTS: [u16; 2] = [53, 5353];
+const HTTP_PORTS: [u16; 2] = [80, 8080];
+const TLS_PORTS: [u16; 1] = [443];
+const SSH_PORTS: [u16; 1] = [22];
+const DNS_HEADER_LEN: usize = 12;
+const MAX_CONNECTIONS: usize = 65536;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Packets with payload looked at per connection for --guess-protocol
+const GUESS_PACKETS: u8 = 4;
+
+/// Application protocols --app-protocol and --guess-protocol know
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AppProtocol {
+    /// DNS and mDNS, over UDP or TCP
+    Dns,
+    Http,
+    Tls,
+    Ssh,
+}
+
+impl FromStr for AppProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dns" => Ok(AppProtocol::Dns),
+            "http" => Ok(AppProtocol::Http),
+            "tls" => Ok(AppProtocol::Tls),
+            "ssh" => Ok(AppProtocol::Ssh),
+            _ => Err(format!("Unknown application protocol: {}", s))
+        }
+    }
+}
+
+fn is_dns(payload: &[u8]) -> bool {
+    // One question is all anyone sends, answers echo it
+    payload.len() >= DNS_HEADER_LEN && u16::from_be_bytes([payload[4], payload[5]]) == 1
+        // Opcode QUERY, or NOTIFY / UPDATE
+        && matches!((payload[2] >> 3) & 0x0f, 0 | 4 | 5)
+}
+
+fn is_http(payload: &[u8]) -> bool {
+    [&b"GET "[..], b"POST ", b"HEAD ", b"PUT ", b"DELETE ", b"OPTIONS ", b"PATCH ", b"HTTP/1."]
+        .iter().any(|m| payload.starts_with(m))
+}
+
+fn is_tls(payload: &[u8]) -> bool {
+    // Record header: change_cipher_spec to application_data, then version 3.x
+    matches!(payload, [0x14..=0x17, 0x03, 0x00..=0x04, ..])
+}
+
+fn is_ssh(payload: &[u8]) -> bool {
+    // Both ends open with their version banner
+    payload.starts_with(b"SSH-2.0-") || payload.starts_with(b"SSH-1.99-")
+}
+
+impl AppProtocol {
+    pub fn name(self) -> &'static str {
+        match self {
+            AppProtocol::Dns => "dns",
+            AppProtocol::Http => "http",
+            AppProtocol::Tls => "tls",
+            AppProtocol::Ssh => "ssh"
+        }
+    }
+
+    fn ports(self) -> &'static [u16] {
+        match self {
+            AppProtocol::Dns => &DNS_PORTS,
+            AppProtocol::Http => &HTTP_PORTS,
+            AppProtocol::Tls => &TLS_PORTS,
+            AppProtocol::Ssh => &SSH_PORTS
+        }
+    }
+
+    /// (source, dest) ports where the protocol can run, None otherwise
+    fn transport_ports(self, info: &PacketInfo) -> Option<(u16, u16)> {
+        match (&info.tcp, &info.udp, self) {
+            (Some(tcp), _, _) => Some((tcp.source_port, tcp.dest_port)),
+            (None, Some(udp), AppProtocol::Dns) => Some((udp.source_port, udp.dest_port)),
+            _ => None
+        }
+    }
+
+    fn sniff(self, payload: &[u8]) -> bool {
+        match self {
+            AppProtocol::Dns => is_dns(payload),
+            AppProtocol::Http => is_http(payload),
+            AppProtocol::Tls => is_tls(payload),
+            AppProtocol::Ssh => is_ssh(payload)
+        }
+    }
+}
+
+/// What --app-protocol lets through: packets to or from the protocol's well known
+/// ports, and TCP connections elsewhere once their payload looks like it
+pub struct AppFilter {
+    protocols: Vec<AppProtocol>,
+    // Recognized by payload, both directions
+    sniffed: IdleTable<ConversationKey, ()>,
+}
+
+impl AppFilter {
+    pub fn new(protocols: Vec<AppProtocol>) -> Self {
+        AppFilter { protocols, sniffed: IdleTable::new(MAX_CONNECTIONS, IDLE_TIMEOUT) }
+    }
+
+    pub fn matches(&mut self, info: &PacketInfo) -> bool {
+        let mut sniffed = false;
+        for protocol in &self.protocols {
+            let (source, dest) = match protocol.transport_ports(info) {
+                Some(ports) => ports,
+                None => continue
+            };
+            if protocol.ports().iter().any(|p| *p == source || *p == dest) {
+                return true;
+            }
+            sniffed |= protocol.sniff(info.payload);
+        }
+        if info.tcp.is_none() {
+            return sniffed;
+        }
+        let (conversation, _) = ConversationKey::of(&info.key.with_transport(info.transport_key()));
+        if sniffed {
+            self.sniffed.insert(conversation, ());
+        }
+        sniffed || self.sniffed.get_mut(&conversation).is_some()
+    }
+}
+
+/// --guess-protocol: what a connection speaks by its first few payloads, whatever the ports
+pub struct ProtocolGuesser {
+    // Payloads looked at so far, GUESS_PACKETS once done
+    connections: IdleTable<StatsKey, u8>,
+}
+
+impl Default for ProtocolGuesser {
+    fn default() -> Self {
+        ProtocolGuesser { connections: IdleTable::new(MAX_CONNECTIONS, IDLE_TIMEOUT) }
+    }
+}
+
+impl ProtocolGuesser {
+    /// Some the one time a connection is recognized
+    pub fn observe(&mut self, info: &PacketInfo) -> Option<AppProtocol> {
+        if info.payload.is_empty() || (info.tcp.is_none() && info.udp.is_none()) {
+            return None;
+        }
+        let connection = info.key.with_transport(info.transport_key());
+        let seen = match self.connections.get_mut(&connection) {
+            Some(seen) => seen,
+            None => {
+                self.connections.insert(connection, 0);
+                self.connections.get_mut(&connection)?
+            }
+        };
+        if *seen >= GUESS_PACKETS {
+            return None;
+        }
+        *seen += 1;
+        // The DNS header is too plain to tell apart in a TCP stream, with its length prefix too
+        let guess = [AppProtocol::Http, AppProtocol::Tls, AppProtocol::Ssh, AppProtocol::Dns].into_iter()
+            .filter(|p| *p != AppProtocol::Dns || info.udp.is_some())
+            .find(|p| p.sniff(info.payload));
+        if guess.is_some() {
+            *seen = GUESS_PACKETS;
+        }
+        guess
+    }
+}
@@ -0,0 +1,92 @@
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::alert::Alert;
+use crate::cidr::Cidr;
+use crate::data::Stats;
+use crate::packet::PacketInfo;
+use crate::table::IdleTable;
+
+/// Reserved ranges with no business on a real network. Private, link-local and
+/// multicast ranges are not in, and neither are 0.0.0.0 and :: which DHCP and
+/// NDP use on purpose.
+const BOGONS: [&str; 16] = [
+    // "This network", loopback
+    "0.0.0.0/8", "127.0.0.0/8",
+    // Documentation, benchmarking
+    "192.0.2.0/24", "198.51.100.0/24", "203.0.113.0/24", "198.18.0.0/15",
+    // IETF protocol assignments, reserved for future use
+    "192.0.0.0/24", "240.0.0.0/4",
+    // IPv4-compatible with loopback among them, IPv4 mapped, discard only
+    "::/96", "::ffff:0:0/96", "100::/64",
+    // Documentation, both of them
+    "2001:db8::/32", "3fff::/20",
+    // ORCHID, deprecated 6bone and site-local
+    "2001:10::/28", "3ffe::/16", "fec0::/10",
+];
+
+fn bogons() -> &'static [Cidr] {
+    static PARSED: OnceLock<Vec<Cidr>> = OnceLock::new();
+    PARSED.get_or_init(|| BOGONS.iter().map(|b| b.parse().unwrap()).collect())
+}
+
+pub fn is_bogon(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) if v4.is_broadcast() || v4.is_unspecified() => false,
+        IpAddr::V6(v6) if v6.is_unspecified() => false,
+        _ => bogons().iter().any(|b| b.contains(ip))
+    }
+}
+
+/// Flows with a bogon at either end
+#[derive(Serialize, Default)]
+pub struct Bogons {
+    pub flows: usize,
+    pub total_length: u128,
+    pub total_count: u128,
+}
+
+pub fn bogons_of(stats: &Stats) -> Bogons {
+    let mut bogons = Bogons::default();
+    for (k, v) in &stats.0 {
+        let (source, dest) = k.addrs();
+        if is_bogon(source) || is_bogon(dest) {
+            bogons.flows += 1;
+            bogons.total_length += v.total_length;
+            bogons.total_count += v.total_count;
+        }
+    }
+    bogons
+}
+
+/// Addresses alerted on at most
+const MAX_ALERTED: usize = 4096;
+/// Quiet that long and the address gets alerted on again
+const REALERT_AFTER: Duration = Duration::from_secs(3600);
+
+/// --flag-bogons, one alert per bogon address while it keeps showing up
+pub struct BogonWatcher {
+    alerted: IdleTable<IpAddr, ()>,
+}
+
+impl Default for BogonWatcher {
+    fn default() -> Self {
+        BogonWatcher { alerted: IdleTable::new(MAX_ALERTED, REALERT_AFTER) }
+    }
+}
+
+impl BogonWatcher {
+    pub fn observe(&mut self, info: &PacketInfo) -> Option<Alert> {
+        let (source, dest) = info.key.addrs();
+        let bogon = [source, dest].into_iter().find(|ip| is_bogon(*ip))?;
+        if self.alerted.get_mut(&bogon).is_some() || !self.alerted.insert(bogon, ()) {
+            return None;
+        }
+        Some(Alert::new("bogon", bogon.to_string(), format!("Bogon address {} in {}", bogon, info.key),
+                        json!({ "flow": info.key.to_string(), "source": source == bogon })))
+    }
+}
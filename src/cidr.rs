@@ -0,0 +1,127 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::data::StatsKey;
+
+/// An IPv4 or IPv6 network, the mask is built once at parse time
+#[derive(Debug, Clone, Copy)]
+pub struct Cidr {
+    v6: bool,
+    network: u128,
+    mask: u128,
+}
+
+fn bits(ip: IpAddr) -> (bool, u128) {
+    match ip {
+        IpAddr::V4(ip) => (false, u32::from(ip) as u128),
+        IpAddr::V6(ip) => (true, u128::from(ip))
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (v6, ip) = bits(ip);
+        v6 == self.v6 && ip & self.mask == self.network
+    }
+
+    /// Either end of the flow is inside
+    pub fn matches(&self, key: &StatsKey) -> bool {
+        let (source, dest) = key.addrs();
+        self.contains(source) || self.contains(dest)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, prefix) = match s.split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (s, None)
+        };
+        let ip: IpAddr = ip.parse().map_err(|_| format!("Invalid network address: {}", ip))?;
+        let (v6, ip) = bits(ip);
+        let width = if v6 { 128 } else { 32 };
+        let prefix: u32 = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| format!("Invalid prefix length: {}", prefix))?,
+            None => width
+        };
+        if prefix > width {
+            return Err(format!("Prefix length {} is longer than {} bits", prefix, width));
+        }
+        // Ones in the top `prefix` bits of the address width
+        let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0) >> (128 - width);
+        Ok(Cidr { v6, network: ip & mask, mask })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(cidr: &str, ip: &str) -> bool {
+        cidr.parse::<Cidr>().unwrap().contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn matches_at_the_prefix_edges() {
+        let cases = [
+            ("0.0.0.0/0", "255.255.255.255", true),
+            ("0.0.0.0/0", "::1", false),
+            ("::/0", "ffff::1", true),
+            ("::/0", "10.0.0.1", false),
+            ("10.0.0.1/32", "10.0.0.1", true),
+            ("10.0.0.1/32", "10.0.0.2", false),
+            ("10.0.0.1", "10.0.0.1", true),
+            ("10.0.0.1", "10.0.0.0", false),
+            ("fd00::1/128", "fd00::1", true),
+            ("fd00::1/128", "fd00::2", false),
+            ("fd00::1", "fd00::1", true),
+            ("10.0.0.0/31", "10.0.0.1", true),
+            ("10.0.0.0/31", "10.0.0.2", false),
+            ("10.0.0.0/1", "127.255.255.255", true),
+            ("10.0.0.0/1", "128.0.0.0", false),
+            ("fd00::/127", "fd00::1", true),
+            ("fd00::/127", "fd00::2", false),
+        ];
+        for (cidr, ip, expected) in cases {
+            assert_eq!(contains(cidr, ip), expected, "{} contains {}", cidr, ip);
+        }
+    }
+
+    #[test]
+    fn ignores_host_bits() {
+        assert!(contains("10.1.2.3/8", "10.200.0.1"));
+        assert!(!contains("10.1.2.3/8", "11.0.0.1"));
+        assert!(contains("fd00::abcd/64", "fd00::1"));
+        assert!(!contains("fd00::abcd/64", "fd00:0:0:1::1"));
+    }
+
+    #[test]
+    fn keeps_families_apart() {
+        // The same bits either way
+        assert!(!contains("0.0.0.1/32", "::1"));
+        assert!(!contains("::1/128", "0.0.0.1"));
+        assert!(!contains("::ffff:10.0.0.1/128", "10.0.0.1"));
+    }
+
+    #[test]
+    fn refuses_malformed_networks() {
+        let cases = [
+            ("", "Invalid network address: "),
+            ("/8", "Invalid network address: "),
+            ("10.0.0/8", "Invalid network address: 10.0.0"),
+            ("10.0.0.256/8", "Invalid network address: 10.0.0.256"),
+            ("fd00:::1/64", "Invalid network address: fd00:::1"),
+            ("10.0.0.0/", "Invalid prefix length: "),
+            ("10.0.0.0/x", "Invalid prefix length: x"),
+            ("10.0.0.0/-1", "Invalid prefix length: -1"),
+            ("10.0.0.0/8/8", "Invalid prefix length: 8/8"),
+            ("10.0.0.0/33", "Prefix length 33 is longer than 32 bits"),
+            ("fd00::/129", "Prefix length 129 is longer than 128 bits"),
+        ];
+        for (cidr, error) in cases {
+            assert_eq!(cidr.parse::<Cidr>().unwrap_err(), error, "{}", cidr);
+        }
+    }
+}
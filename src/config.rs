@@ -0,0 +1,205 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use pnet::util::MacAddr;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::filter::Filter;
+use crate::packet::PacketInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueFull {
+    Drop,
+    Block,
+}
+
+impl FromStr for QueueFull {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(QueueFull::Drop),
+            "block" => Ok(QueueFull::Block),
+            _ => Err(format!("Unknown queue full policy: {}", s))
+        }
+    }
+}
+
+/// What tells flows apart
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    /// Source and destination address
+    Host,
+    /// Addresses, IP protocol and ports
+    Port,
+}
+
+impl FromStr for Granularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(Granularity::Host),
+            "port" => Ok(Granularity::Port),
+            _ => Err(format!("Unknown granularity: {}", s))
+        }
+    }
+}
+
+/// What capture does once --interface is gone
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InterfaceLoss {
+    /// Until one by the same name shows up, then capture on it
+    Wait,
+    /// Save and stop, as at the end of --pcap
+    Exit,
+}
+
+impl FromStr for InterfaceLoss {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wait" => Ok(InterfaceLoss::Wait),
+            "exit" => Ok(InterfaceLoss::Exit),
+            _ => Err(format!("Unknown interface loss policy: {}", s))
+        }
+    }
+}
+
+/// Plain seconds, or a number suffixed with s, m, h or d
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (n, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s")
+    };
+    let n: u64 = n.parse().map_err(|_| format!("Invalid duration: {}", s))?;
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return Err(format!("Invalid duration unit: {}", unit))
+    };
+    // Query parameters end up here too, no overflow panic for what a client sends
+    let seconds = n.checked_mul(scale).ok_or("Duration too large")?;
+    Ok(Duration::from_secs(seconds))
+}
+
+/// `parse_duration` for how often something is done, which can't be 0
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        Duration::ZERO => Err(format!("Invalid interval: {}, it has to be more than 0", s)),
+        d => Ok(d)
+    }
+}
+
+/// Settings that can change under a running capture, reapplied on SIGHUP
+#[derive(Debug, Clone)]
+pub struct Live {
+    pub filter: Option<Filter>,
+    /// Empty for any
+    pub macs: Vec<MacAddr>,
+    /// Empty for any, None for untagged frames
+    pub vlans: Vec<Option<u16>>,
+    pub queue_full: QueueFull,
+    pub unicast_only: bool,
+    pub stdout_interval: Option<Duration>,
+    pub stdout_top: usize,
+}
+
+impl Live {
+    /// Option names as they appear in the config file
+    pub const OPTIONS: &'static [&'static str] = &["filter", "filter_file", "mac", "vlan", "queue_full", "unicast_only", "stdout_interval", "stdout_top"];
+
+    /// Either end of the frame is one of `macs`
+    pub fn mac_matches(&self, info: &PacketInfo) -> bool {
+        self.macs.is_empty() || self.macs.iter().any(|m| *m == info.source_mac || *m == info.dest_mac)
+    }
+
+    /// Either tag is one of `vlans`, or there is none and untagged was asked for
+    pub fn vlan_matches(&self, info: &PacketInfo) -> bool {
+        self.vlans.is_empty() || self.vlans.iter().any(|v| match v {
+            Some(id) => info.vlans.outer == Some(*id) || info.vlans.inner == Some(*id),
+            None => info.vlans.outer.is_none()
+        })
+    }
+}
+
+/// Turn a JSON object of `"long-option": value` into command line arguments.
+/// `true` is a bare flag, `false` and `null` leave the option out, arrays repeat it.
+pub fn file_args(path: &Path) -> Result<Vec<OsString>, String> {
+    let s = fs::read_to_string(path)
+        .map_err(|e| format!("Fail to read config {}: {}", path.display(), e))?;
+    let options = match serde_json::from_str(&s) {
+        Ok(Value::Object(o)) => o,
+        Ok(_) => return Err(format!("Config {} is not a JSON object", path.display())),
+        Err(e) => return Err(format!("Fail to parse config {}: {}", path.display(), e))
+    };
+
+    let mut args = Vec::new();
+    for (name, value) in options {
+        let flag = format!("--{}", name.replace('_', "-"));
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Bool(true) => args.push(flag.clone().into()),
+                Value::Bool(false) | Value::Null => (),
+                Value::String(s) => args.extend([flag.clone().into(), s.into()]),
+                Value::Number(n) => args.extend([flag.clone().into(), n.to_string().into()]),
+                _ => return Err(format!("Config option {} must be a string, number or boolean", name))
+            }
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+    }
+
+    #[test]
+    fn refuses_zero_intervals() {
+        assert_eq!(parse_interval("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_interval("0"), Err("Invalid interval: 0, it has to be more than 0".to_string()));
+        assert_eq!(parse_interval("0m"), Err("Invalid interval: 0m, it has to be more than 0".to_string()));
+    }
+
+    #[test]
+    fn refuses_overflowing_durations() {
+        assert_eq!(parse_duration("999999999999999d"), Err("Duration too large".to_string()));
+        assert_eq!(parse_duration("18446744073709551615m"), Err("Duration too large".to_string()));
+        assert_eq!(parse_duration("18446744073709551615"), Ok(Duration::from_secs(u64::MAX)));
+        assert!(parse_duration("18446744073709551616").is_err());
+    }
+
+    #[test]
+    fn refuses_junk_durations() {
+        assert_eq!(parse_duration(""), Err("Invalid duration: ".to_string()));
+        assert_eq!(parse_duration("m"), Err("Invalid duration: m".to_string()));
+        assert_eq!(parse_duration("-5"), Err("Invalid duration: -5".to_string()));
+        assert_eq!(parse_duration("5w"), Err("Invalid duration unit: w".to_string()));
+        assert_eq!(parse_duration("5 m"), Err("Invalid duration unit:  m".to_string()));
+        assert_eq!(parse_duration("1.5h"), Err("Invalid duration unit: .5h".to_string()));
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs::OpenOptions;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use log::Level;
+use serde::Serialize;
+
+use crate::data::StatsKey;
+use crate::logging::WarnOnce;
+use crate::table::IdleTable;
+
+/// Flows remembered at most, past that new ones go unlogged until some go quiet
+const MAX_FLOWS: usize = 1 << 20;
+/// Quiet that long and a flow is logged again when it comes back
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    /// Unix seconds of the flow's first packet
+    at: f64,
+    flow: &'a StatsKey,
+}
+
+/// --connection-log: a line of JSON for each flow, by its 5-tuple, when it shows up.
+/// The packets after that are not counted.
+pub struct ConnectionLog {
+    seen: IdleTable<StatsKey, ()>,
+    out: Box<dyn Write + Send>,
+    failing: WarnOnce,
+}
+
+impl ConnectionLog {
+    /// - for standard output, a file is appended to
+    pub fn open(to: &Path) -> io::Result<Self> {
+        let out: Box<dyn Write + Send> = if to == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(LineWriter::new(OpenOptions::new().create(true).append(true).open(to)?))
+        };
+        Ok(ConnectionLog { seen: IdleTable::new(MAX_FLOWS, IDLE_TIMEOUT), out, failing: WarnOnce::new(Level::Warn, module_path!()) })
+    }
+
+    /// True for a flow's first packet, which is logged, false for the others
+    pub fn observe(&mut self, flow: StatsKey, at_micros: u64) -> bool {
+        if self.seen.get_mut(&flow).is_some() || !self.seen.insert(flow, ()) {
+            return false;
+        }
+        let entry = Entry { at: at_micros as f64 / 1e6, flow: &flow };
+        let mut line = serde_json::to_string(&entry).unwrap_or_else(|e| panic!("Fail to serialize connection: {}", e));
+        line.push('\n');
+        let written = self.out.write_all(line.as_bytes());
+        let _ = self.failing.report(written, "write connection log");
+        true
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+use pnet::packet::tcp::TcpFlags;
+
+use crate::conversation::ConversationKey;
+use crate::packet::{PacketInfo, TcpInfo};
+use crate::table::IdleTable;
+
+/// Connections followed at most by --connections-only
+const MAX_CONNECTIONS: usize = 65536;
+/// Quiet that long and a connection is taken for gone
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A SYN without ACK, the client opening a connection
+pub fn is_opening(tcp: &TcpInfo) -> bool {
+    tcp.flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN
+}
+
+/// --connections-only: TCP connections whose SYN went by while capturing, both
+/// directions, until they go quiet
+pub struct ConnectionFilter {
+    open: IdleTable<ConversationKey, ()>,
+}
+
+impl Default for ConnectionFilter {
+    fn default() -> Self {
+        ConnectionFilter { open: IdleTable::new(MAX_CONNECTIONS, IDLE_TIMEOUT) }
+    }
+}
+
+impl ConnectionFilter {
+    pub fn matches(&mut self, info: &PacketInfo) -> bool {
+        let tcp = match &info.tcp {
+            Some(tcp) => tcp,
+            None => return false
+        };
+        let (connection, _) = ConversationKey::of(&info.key.with_transport(info.transport_key()));
+        if is_opening(tcp) {
+            return self.open.insert(connection, ());
+        }
+        self.open.get_mut(&connection).is_some()
+    }
+}
+
+/// Connections opened per second, between publishes
+#[derive(Default)]
+pub struct ConnectionRate {
+    last: Option<(Instant, u128)>,
+    per_sec: f64,
+}
+
+impl ConnectionRate {
+    /// `opened` being the total over all flows. Going down, a reset, counts from zero.
+    pub fn observe(&mut self, opened: u128) {
+        let now = Instant::now();
+        if let Some((at, then)) = self.last {
+            let secs = now.duration_since(at).as_secs_f64();
+            if secs > 0.0 {
+                let delta = if opened >= then { opened - then } else { opened };
+                self.per_sec = delta as f64 / secs;
+            }
+        }
+        self.last = Some((now, opened));
+    }
+
+    pub fn per_sec(&self) -> f64 {
+        self.per_sec
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Serializer};
+
+use crate::age::is_group;
+use crate::data::{unix_now, Stats, StatsKey};
+use crate::key_format::{self, KeyFormat};
+
+/// Both directions of a flow, lower endpoint (address, then port) first
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub struct ConversationKey(pub StatsKey);
+
+impl ConversationKey {
+    pub fn of(key: &StatsKey) -> (Self, bool) {
+        let (source, dest) = key.addrs();
+        let ports = key.1.map(|t| (t.source_port, t.dest_port)).unwrap_or_default();
+        if (source, ports.0) <= (dest, ports.1) {
+            (ConversationKey(*key), true)
+        } else {
+            (ConversationKey(key.reversed()), false)
+        }
+    }
+}
+
+impl Serialize for ConversationKey {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: Serializer {
+        match key_format::get() {
+            KeyFormat::Arrow => serializer.collect_str(&self.0.to_string().replacen(" -> ", " <-> ", 1)),
+            _ => self.0.serialize(serializer)
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    AToB,
+    BToA,
+}
+
+#[derive(Serialize, Default)]
+pub struct Conversation {
+    pub total_length: u128,
+    pub total_count: u128,
+    /// a is the first endpoint of the key, b the second
+    pub a_to_b_bytes: u128,
+    pub b_to_a_bytes: u128,
+    pub a_to_b_packets: u128,
+    pub b_to_a_packets: u128,
+    /// 1 when only a sends, -1 when only b does
+    pub asymmetry: f64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// Whichever sent at last_seen, either when both did within that second
+    pub last_direction: Option<Direction>,
+}
+
+#[derive(Serialize, Default)]
+pub struct Conversations(#[serde(serialize_with = "key_format::flow_map")] pub HashMap<ConversationKey, Conversation>);
+
+/// Merge each flow with its opposite direction
+pub fn conversations(stats: &Stats) -> Conversations {
+    let mut conversations = Conversations::default();
+    for (k, v) in &stats.0 {
+        let (key, forward) = ConversationKey::of(k);
+        let c = conversations.0.entry(key).or_default();
+        c.total_length += v.total_length;
+        c.total_count += v.total_count;
+        let direction = if forward { Direction::AToB } else { Direction::BToA };
+        if c.last_direction.is_none() || v.last_seen > c.last_seen {
+            c.last_direction = Some(direction);
+        }
+        if forward {
+            c.a_to_b_bytes += v.total_length;
+            c.a_to_b_packets += v.total_count;
+        } else {
+            c.b_to_a_bytes += v.total_length;
+            c.b_to_a_packets += v.total_count;
+        }
+        if c.first_seen == 0 || (v.first_seen != 0 && v.first_seen < c.first_seen) {
+            c.first_seen = v.first_seen;
+        }
+        c.last_seen = c.last_seen.max(v.last_seen);
+    }
+    for c in conversations.0.values_mut().filter(|c| c.total_length > 0) {
+        c.asymmetry = (c.a_to_b_bytes as f64 - c.b_to_a_bytes as f64) / c.total_length as f64;
+    }
+    conversations
+}
+
+/// A conversation only one direction of was captured
+#[derive(Serialize)]
+pub struct OneSided {
+    /// The direction seen
+    pub flow: StatsKey,
+    /// Seconds since first_seen
+    pub age: u64,
+    pub total_length: u128,
+    pub total_count: u128,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Serialize)]
+pub struct Asymmetric {
+    /// Those old enough to have had an answer by now
+    pub conversations: usize,
+    pub one_sided: usize,
+    /// one_sided / conversations, 0 without conversations
+    pub ratio: f64,
+    pub flows: Vec<OneSided>,
+}
+
+/// Conversations at least `after` seconds old with nothing captured in one direction,
+/// the most bytes first. Asymmetric routing or a span port mirroring one way look like
+/// this. Multicast and broadcast expect no answer and are left out.
+pub fn asymmetric(stats: &Stats, after: u64) -> Asymmetric {
+    let now = unix_now();
+    let mut considered = 0;
+    let mut flows = Vec::new();
+    for (key, c) in conversations(stats).0 {
+        let age = now.saturating_sub(c.first_seen);
+        if age < after || is_group(key.0.addrs().0) || is_group(key.0.addrs().1) {
+            continue;
+        }
+        considered += 1;
+        let flow = match (c.a_to_b_packets, c.b_to_a_packets) {
+            (_, 0) => key.0,
+            (0, _) => key.0.reversed(),
+            _ => continue
+        };
+        flows.push(OneSided {
+            flow,
+            age,
+            total_length: c.total_length,
+            total_count: c.total_count,
+            first_seen: c.first_seen,
+            last_seen: c.last_seen,
+        });
+    }
+    flows.sort_by_key(|f| std::cmp::Reverse(f.total_length));
+    let ratio = if considered == 0 { 0.0 } else { flows.len() as f64 / considered as f64 };
+    Asymmetric { conversations: considered, one_sided: flows.len(), ratio, flows }
+}
@@ -0,0 +1,23 @@
+use std::fs;
+use std::path::Path;
+
+use log::info;
+
+use crate::db_format::{self, DbFormat};
+
+/// Write the database at `from` to `to` in another format, gzipped when `to` ends in .gz.
+/// Formats go by extension unless given, see `DbFormat::of`.
+pub fn run(from: &Path, to: &Path, in_format: Option<DbFormat>, out_format: Option<DbFormat>) {
+    let (in_format, out_format) = (DbFormat::of(from, in_format), DbFormat::of(to, out_format));
+    let data = fs::read(from)
+        .unwrap_or_else(|e| panic!("Fail to read database {}: {}", from.display(), e));
+    let stats = db_format::decode(&data, in_format)
+        .unwrap_or_else(|e| panic!("Fail to parse database {}: {}", from.display(), e));
+    let mut data = db_format::encode(&stats, out_format);
+    if db_format::is_gzip(to) {
+        data = db_format::gzip(&data);
+    }
+    fs::write(to, data).unwrap_or_else(|e| panic!("Fail to write {}: {}", to.display(), e));
+    info!(flows = stats.0.len(); "Converted {} flows from {} ({:?}) to {} ({:?})",
+          stats.0.len(), from.display(), in_format, to.display(), out_format);
+}
@@ -0,0 +1,68 @@
+use std::future::Future;
+use std::path::Path;
+use std::{fs, process};
+
+/// Fork into the background. Must run before any thread is spawned.
+#[cfg(unix)]
+pub fn daemonize(pidfile: Option<&Path>) {
+    // Keep relative paths (database...) meaning the same thing
+    let cwd = std::env::current_dir().unwrap_or_else(|e| panic!("Fail to get working directory: {}", e));
+    let mut daemon = daemonize::Daemonize::new().working_directory(cwd);
+    if let Some(p) = pidfile {
+        daemon = daemon.pid_file(p);
+    }
+    if let Err(e) = daemon.start() {
+        panic!("Fail to daemonize: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_pidfile: Option<&Path>) {
+    panic!("--daemon is only supported on Unix");
+}
+
+pub fn write_pidfile(path: &Path) {
+    if let Err(e) = fs::write(path, format!("{}\n", process::id())) {
+        panic!("Fail to write pidfile {}: {}", path.display(), e);
+    }
+}
+
+/// Call `on_hup` on SIGHUP. Needs a runtime.
+#[cfg(unix)]
+pub fn on_hangup<F>(mut on_hup: F)
+    where F: FnMut() + Send + 'static {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())
+        .unwrap_or_else(|e| panic!("Fail to install signal handlers: {}", e));
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            tokio::task::block_in_place(&mut on_hup);
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn on_hangup<F>(_on_hup: F)
+    where F: FnMut() + Send + 'static {}
+
+/// Resolves with the signal's name on SIGTERM or SIGINT. Handlers are installed
+/// right away, the default ones would kill the process before it is awaited.
+#[cfg(unix)]
+pub fn terminated() -> impl Future<Output = &'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let install = |kind| signal(kind).unwrap_or_else(|e| panic!("Fail to install signal handlers: {}", e));
+    let (mut term, mut int) = (install(SignalKind::terminate()), install(SignalKind::interrupt()));
+    async move {
+        tokio::select! {
+            _ = term.recv() => "SIGTERM",
+            _ = int.recv() => "SIGINT"
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn terminated() -> impl Future<Output = &'static str> {
+    std::future::pending()
+}
@@ -1,93 +1,528 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::fmt::{Display, Formatter};
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
 use std::sync::MutexGuard;
+use std::time::{SystemTime, UNIX_EPOCH};
 use either::Either;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
 
-#[derive(PartialEq, Eq, Hash)]
+use crate::app_protocol::AppProtocol;
+use crate::jitter::Jitter;
+use crate::window::WindowStats;
+use crate::key_format::{self, KeyFormat, KeyObject};
+use crate::term;
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Ipv4StatsKey {
     pub source: Ipv4Addr,
     pub dest: Ipv4Addr,
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Ipv6StatsKey {
     pub source: Ipv6Addr,
     pub dest: Ipv6Addr,
 }
 
+/// IP protocol and ports, 0 for protocols without ports. Only in keys with --granularity port.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub struct TransportKey {
+    pub protocol: u8,
+    pub source_port: u16,
+    pub dest_port: u16,
+}
+
+impl TransportKey {
+    /// Zero the higher port when it is `min` or above: the client's ephemeral port
+    /// in nearly every connection, both ways, while the server's stays
+    pub fn without_ephemeral(self, min: u16) -> Self {
+        match (self.source_port, self.dest_port) {
+            (source, dest) if source > dest && source >= min => TransportKey { source_port: 0, ..self },
+            (source, dest) if dest > source && dest >= min => TransportKey { dest_port: 0, ..self },
+            _ => self
+        }
+    }
+}
+
 // E0117 was in my way so workaround ╮( ╯_╰)╭
-#[derive(PartialEq, Eq, Hash)]
-pub struct StatsKey(pub Either<Ipv4StatsKey, Ipv6StatsKey>);
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub struct StatsKey(pub Either<Ipv4StatsKey, Ipv6StatsKey>, pub Option<TransportKey>);
+
+const PROTOCOL_NAMES: [(u8, &str); 7] = [(1, "icmp"), (6, "tcp"), (17, "udp"), (47, "gre"), (50, "esp"), (58, "icmpv6"), (132, "sctp")];
+
+pub fn protocol_name(protocol: u8) -> String {
+    match PROTOCOL_NAMES.iter().find(|(p, _)| *p == protocol) {
+        Some((_, name)) => name.to_string(),
+        None => protocol.to_string()
+    }
+}
+
+pub(crate) fn parse_protocol(s: &str) -> Option<u8> {
+    PROTOCOL_NAMES.iter().find(|(_, name)| *name == s).map(|(p, _)| *p).or_else(|| s.parse().ok())
+}
 
 impl Serialize for StatsKey {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
         where S: Serializer {
-        let s = match &self.0 {
-            Either::Left(v4) => format!("{} -> {}", v4.source, v4.dest),
-            Either::Right(v6) => format!("{} -> {}", v6.source, v6.dest)
-        };
-        serializer.serialize_str(&s)
+        match key_format::get() {
+            KeyFormat::Arrow => serializer.collect_str(self),
+            KeyFormat::Tuple => serializer.collect_str(&key_format::tuple(self)),
+            KeyFormat::Json => KeyObject::of(self).serialize(serializer)
+        }
+    }
+}
+
+struct StatsKeyVisitor;
+
+impl<'de> Visitor<'de> for StatsKeyVisitor {
+    type Value = StatsKey;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("a flow key, as a string or an object")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<StatsKey, E> {
+        s.parse().map_err(E::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<StatsKey, A::Error> {
+        let object = KeyObject::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        object.key().map_err(de::Error::custom)
     }
 }
 
+// Any key format goes
 impl<'de> Deserialize<'de> for StatsKey {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
-        let s = String::deserialize(deserializer)?;
-        let parts: Vec<&str> = s.split(" -> ").collect();
+        deserializer.deserialize_any(StatsKeyVisitor)
+    }
+}
+
+impl FromStr for StatsKey {
+    type Err = String;
+
+    /// "a -> b", or "a:port -> b:port/protocol" with ports, or the tuple key format
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.contains(" -> ") && s.contains(',') {
+            return key_format::parse_tuple(s);
+        }
+        let (hosts, protocol) = match s.rsplit_once('/') {
+            Some((hosts, protocol)) => (hosts, Some(parse_protocol(protocol).ok_or("Invalid StatsKey protocol")?)),
+            None => (s, None)
+        };
+        let parts: Vec<&str> = hosts.split(" -> ").collect();
         if parts.len() != 2 {
-            return Err(serde::de::Error::custom("Invalid StatsKey format"));
-        }
-        let (source, dest) = (parts[0], parts[1]);
-        if source.contains(':') {
-            // Ipv6
-            let source = source.parse::<Ipv6Addr>().map_err(serde::de::Error::custom)?;
-            let dest = dest.parse::<Ipv6Addr>().map_err(serde::de::Error::custom)?;
-            Ok(StatsKey(Either::Right(Ipv6StatsKey { source, dest })))
+            return Err("Invalid StatsKey format".to_string());
+        }
+        let (source, dest, transport) = match protocol {
+            Some(protocol) => {
+                let source = parts[0].parse::<SocketAddr>().map_err(|e| e.to_string())?;
+                let dest = parts[1].parse::<SocketAddr>().map_err(|e| e.to_string())?;
+                let transport = TransportKey { protocol, source_port: source.port(), dest_port: dest.port() };
+                (source.ip(), dest.ip(), Some(transport))
+            }
+            None => (
+                parts[0].parse::<IpAddr>().map_err(|e| e.to_string())?,
+                parts[1].parse::<IpAddr>().map_err(|e| e.to_string())?,
+                None,
+            )
+        };
+        match (source, dest) {
+            (IpAddr::V4(source), IpAddr::V4(dest)) => Ok(StatsKey(Either::Left(Ipv4StatsKey { source, dest }), transport)),
+            (IpAddr::V6(source), IpAddr::V6(dest)) => Ok(StatsKey(Either::Right(Ipv6StatsKey { source, dest }), transport)),
+            _ => Err("Invalid StatsKey: mixed IPv4 and IPv6".to_string())
+        }
+    }
+}
+
+/// Seconds per rate sampling window
+pub const RATE_WINDOW: u64 = 5;
+
+/// One-second buckets kept by `RateSamples`
+pub const RATE_SAMPLES: usize = 8;
+
+/// Bytes per second for the last few seconds, for a rate that doesn't lag a whole window behind
+#[derive(Default, Clone)]
+pub struct RateSamples {
+    // (unix second, bytes), oldest overwritten first
+    ring: [(u64, u128); RATE_SAMPLES],
+    last: usize,
+}
+
+impl RateSamples {
+    fn record(&mut self, now: u64, length: u128) {
+        if self.ring[self.last].0 != now {
+            self.last = (self.last + 1) % RATE_SAMPLES;
+            self.ring[self.last] = (now, 0);
+        }
+        self.ring[self.last].1 += length;
+    }
+
+    /// Exact average over the last RATE_SAMPLES complete seconds
+    pub fn bits_per_sec(&self, now: u64) -> f64 {
+        let since = now.saturating_sub(RATE_SAMPLES as u64);
+        let bytes: u128 = self.ring.iter()
+            .filter(|(t, _)| *t >= since && *t < now)
+            .map(|(_, b)| b)
+            .sum();
+        (bytes * 8) as f64 / RATE_SAMPLES as f64
+    }
+}
+
+/// Bytes and packets seen in the current and the previous fixed window, rates are derived on read
+#[derive(Default, Clone, Copy)]
+pub struct RateWindow {
+    window: u64,
+    current: u128,
+    previous: u128,
+    current_count: u64,
+    previous_count: u64,
+}
+
+impl RateWindow {
+    fn record(&mut self, now: u64, length: u128) {
+        let window = now / RATE_WINDOW;
+        if window != self.window {
+            let next = window == self.window + 1;
+            self.previous = if next { self.current } else { 0 };
+            self.previous_count = if next { self.current_count } else { 0 };
+            self.current = 0;
+            self.current_count = 0;
+            self.window = window;
+        }
+        self.current += length;
+        self.current_count += 1;
+    }
+
+    /// (bytes, packets) of the last complete window
+    fn complete(&self, now: u64) -> (u128, u64) {
+        let window = now / RATE_WINDOW;
+        if window == self.window {
+            (self.previous, self.previous_count)
+        } else if window == self.window + 1 {
+            (self.current, self.current_count)
         } else {
-            // Ipv4
-            let source = source.parse::<Ipv4Addr>().map_err(serde::de::Error::custom)?;
-            let dest = dest.parse::<Ipv4Addr>().map_err(serde::de::Error::custom)?;
-            Ok(StatsKey(Either::Left(Ipv4StatsKey { source, dest })))
+            (0, 0)
         }
     }
+
+    /// Over the last complete window, so at most RATE_WINDOW seconds stale
+    pub fn bits_per_sec(&self, now: u64) -> f64 {
+        (self.complete(now).0 * 8) as f64 / RATE_WINDOW as f64
+    }
+
+    /// Over the same window as `bits_per_sec`
+    pub fn packets_per_sec(&self, now: u64) -> f64 {
+        self.complete(now).1 as f64 / RATE_WINDOW as f64
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Deserialize, Clone, Default)]
 pub struct StatsValue {
     pub total_length: u128,
     pub total_count: u128,
+    /// Unix seconds
+    #[serde(default)]
+    pub first_seen: u64,
+    #[serde(default)]
+    pub last_seen: u64,
+    /// Whole IP datagrams, unlike `total_length` this has the IPv6 header too
+    #[serde(default)]
+    pub ip_bytes: u128,
+    /// Frames as they go over the link, see `PacketInfo::wire_length`
+    #[serde(default)]
+    pub wire_bytes: u128,
+    /// TCP segments seen more than once
+    #[serde(default)]
+    pub retransmissions: u128,
+    /// TCP connections opened, SYNs without ACK
+    #[serde(default)]
+    pub connections: u128,
+    /// TCP and UDP packets without payload, e.g. bare ACKs and keepalives
+    #[serde(default)]
+    pub empty_packets: u128,
+    /// Biggest single packet
+    #[serde(default)]
+    pub max_packet_size: u128,
+    /// Update sequence number of the batch that last touched it, see `ShardedStats::sequence`
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(skip)]
+    pub rate: RateWindow,
+    /// Only with --track-rates, boxed so flows without pay a pointer
+    #[serde(skip)]
+    pub samples: Option<Box<RateSamples>>,
+    /// Only for UDP flows with --track-jitter
+    #[serde(skip)]
+    pub jitter: Option<Box<Jitter>>,
+    /// Only for TCP flows with --track-windows
+    #[serde(skip)]
+    pub window: Option<WindowStats>,
+    /// `total_length` as of the last `ShardedStats::roll_rates`
+    #[serde(skip)]
+    pub rolled_length: u128,
+    /// Between the last two `ShardedStats::roll_rates`
+    #[serde(skip)]
+    pub bytes_per_sec: f64,
+    /// What the payload looked like, only with --guess-protocol
+    #[serde(default)]
+    pub app_protocol: Option<AppProtocol>,
+}
+
+// By hand for the derived avg_packet_size, which is not worth storing
+impl Serialize for StatsValue {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: Serializer {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("StatsValue", 19)?;
+        s.serialize_field("total_length", &self.total_length)?;
+        s.serialize_field("total_count", &self.total_count)?;
+        s.serialize_field("ip_bytes", &self.ip_bytes)?;
+        s.serialize_field("wire_bytes", &self.wire_bytes)?;
+        s.serialize_field("first_seen", &self.first_seen)?;
+        s.serialize_field("last_seen", &self.last_seen)?;
+        s.serialize_field("retransmissions", &self.retransmissions)?;
+        s.serialize_field("connections", &self.connections)?;
+        s.serialize_field("empty_packets", &self.empty_packets)?;
+        s.serialize_field("max_packet_size", &self.max_packet_size)?;
+        s.serialize_field("sequence", &self.sequence)?;
+        s.serialize_field("avg_packet_size", &self.avg_packet_size())?;
+        s.serialize_field("bytes_per_sec", &self.bytes_per_sec)?;
+        match &self.samples {
+            Some(samples) => s.serialize_field("rate_bps", &samples.bits_per_sec(unix_now()))?,
+            None => s.skip_field("rate_bps")?
+        }
+        match &self.jitter {
+            Some(jitter) => {
+                s.serialize_field("inter_arrival_ms", &jitter.inter_arrival_ms())?;
+                s.serialize_field("jitter_ms", &jitter.jitter_ms())?;
+            }
+            None => {
+                s.skip_field("inter_arrival_ms")?;
+                s.skip_field("jitter_ms")?;
+            }
+        }
+        match &self.window {
+            Some(window) => {
+                s.serialize_field("window", &window.latest)?;
+                s.serialize_field("min_window", &window.min)?;
+            }
+            None => {
+                s.skip_field("window")?;
+                s.skip_field("min_window")?;
+            }
+        }
+        match &self.app_protocol {
+            Some(app_protocol) => s.serialize_field("app_protocol", app_protocol)?,
+            None => s.skip_field("app_protocol")?
+        }
+        s.end()
+    }
 }
 
 // Stupid E0117
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Default)]
 pub struct Stats(pub HashMap<StatsKey, StatsValue>);
 
+impl Serialize for Stats {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: Serializer {
+        key_format::serialize_flows(&self.0, serializer)
+    }
+}
+
+struct StatsVisitor;
+
+impl<'de> Visitor<'de> for StatsVisitor {
+    type Value = Stats;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("flows, as a map by key or an array of entries")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Stats, A::Error> {
+        let mut stats = HashMap::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((k, v)) = map.next_entry()? {
+            stats.insert(k, v);
+        }
+        Ok(Stats(stats))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Stats, A::Error> {
+        let mut stats = HashMap::with_capacity(seq.size_hint().unwrap_or(0));
+        // Not #[serde(flatten)], that goes through serde's buffer, which has no u128
+        while let Some(mut entry) = seq.next_element::<serde_json::Map<String, serde_json::Value>>()? {
+            let flow = entry.remove("flow").ok_or(de::Error::missing_field("flow"))?;
+            let flow = StatsKey::deserialize(flow).map_err(de::Error::custom)?;
+            let value = StatsValue::deserialize(serde_json::Value::Object(entry)).map_err(de::Error::custom)?;
+            stats.insert(flow, value);
+        }
+        Ok(Stats(stats))
+    }
+}
+
+// Whatever the key format it was written with
+impl<'de> Deserialize<'de> for Stats {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_any(StatsVisitor)
+    }
+}
+
 impl Stats {
     pub fn new() -> Self {
         Stats(HashMap::new())
     }
+
+    /// Flows sorted by bytes, busiest first
+    pub fn top(&self, n: usize) -> Vec<(&StatsKey, &StatsValue)> {
+        self.top_where(n, |_| true)
+    }
+
+    /// Like top, among the flows `wanted` keeps
+    pub fn top_where<F>(&self, n: usize, wanted: F) -> Vec<(&StatsKey, &StatsValue)>
+        where F: Fn(&StatsKey) -> bool {
+        let mut flows: Vec<_> = self.0.iter().filter(|(k, _)| wanted(k)).collect();
+        flows.sort_by_key(|(_, v)| Reverse(v.total_length));
+        flows.truncate(n);
+        flows
+    }
+
+    /// Flows of at least `min_count` packets sorted by average packet size, smallest
+    /// first and then the most packets, among the ones `wanted` keeps
+    pub fn smallest_packets<F>(&self, n: usize, min_count: u128, wanted: F) -> Vec<(&StatsKey, &StatsValue)>
+        where F: Fn(&StatsKey) -> bool {
+        let mut flows: Vec<_> = self.0.iter().filter(|(k, v)| v.total_count >= min_count.max(1) && wanted(k)).collect();
+        flows.sort_by(|(_, a), (_, b)| a.avg_packet_size().total_cmp(&b.avg_packet_size())
+            .then(b.total_count.cmp(&a.total_count)));
+        flows.truncate(n);
+        flows
+    }
+
+    /// Add `other`'s flows to these, summing the counters of flows in both
+    pub fn merge(&mut self, other: Stats) {
+        for (k, v) in other.0 {
+            match self.0.entry(k) {
+                Entry::Occupied(mut e) => e.get_mut().merge(&v),
+                Entry::Vacant(e) => {
+                    e.insert(v);
+                }
+            }
+        }
+    }
+}
+
+/// Traffic attributed to a name (TLS server name, HTTP host...)
+#[derive(Serialize, Default)]
+pub struct NameValue {
+    pub total_length: u128,
+    pub total_count: u128,
+    /// TLS handshakes or HTTP requests naming it
+    pub hits: u128,
+}
+
+#[derive(Serialize, Default)]
+pub struct NameStats(pub HashMap<String, NameValue>);
+
+impl StatsKey {
+    /// Same hosts, the other way around
+    pub fn reversed(&self) -> Self {
+        let transport = self.1.map(|t| TransportKey { source_port: t.dest_port, dest_port: t.source_port, ..t });
+        match self.0 {
+            Either::Left(v4) => StatsKey(Either::Left(Ipv4StatsKey { source: v4.dest, dest: v4.source }), transport),
+            Either::Right(v6) => StatsKey(Either::Right(Ipv6StatsKey { source: v6.dest, dest: v6.source }), transport)
+        }
+    }
+
+    pub fn with_transport(self, transport: TransportKey) -> Self {
+        StatsKey(self.0, Some(transport))
+    }
+
+    /// (source, dest)
+    pub fn addrs(&self) -> (IpAddr, IpAddr) {
+        match self.0 {
+            Either::Left(v4) => (v4.source.into(), v4.dest.into()),
+            Either::Right(v6) => (v6.source.into(), v6.dest.into())
+        }
+    }
 }
 
 impl StatsValue {
+    /// The same flow counted elsewhere, 0 being unknown for first_seen
+    fn merge(&mut self, other: &StatsValue) {
+        self.total_length += other.total_length;
+        self.total_count += other.total_count;
+        self.ip_bytes += other.ip_bytes;
+        self.wire_bytes += other.wire_bytes;
+        self.first_seen = match (self.first_seen, other.first_seen) {
+            (0, t) | (t, 0) => t,
+            (a, b) => a.min(b)
+        };
+        self.last_seen = self.last_seen.max(other.last_seen);
+        self.retransmissions += other.retransmissions;
+        self.connections += other.connections;
+        self.empty_packets += other.empty_packets;
+        self.max_packet_size = self.max_packet_size.max(other.max_packet_size);
+        self.sequence = self.sequence.max(other.sequence);
+        self.app_protocol = self.app_protocol.or(other.app_protocol);
+    }
+
     pub fn new() -> Self {
-        StatsValue { total_length: 0, total_count: 0 }
+        StatsValue {
+            total_length: 0,
+            total_count: 0,
+            ip_bytes: 0,
+            wire_bytes: 0,
+            first_seen: 0,
+            last_seen: 0,
+            retransmissions: 0,
+            connections: 0,
+            empty_packets: 0,
+            max_packet_size: 0,
+            sequence: 0,
+            rate: RateWindow::default(),
+            samples: None,
+            jitter: None,
+            window: None,
+            rolled_length: 0,
+            bytes_per_sec: 0.0,
+            app_protocol: None,
+        }
+    }
+
+    pub fn avg_packet_size(&self) -> f64 {
+        if self.total_count == 0 {
+            0.0
+        } else {
+            self.total_length as f64 / self.total_count as f64
+        }
     }
 }
 
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
 impl Display for StatsKey {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match &self.0 {
-            Either::Left(v4) => write!(f, "{} -> {}", v4.source, v4.dest),
-            Either::Right(v6) => write!(f, "{} -> {}", v6.source, v6.dest)
+        let (source, dest) = self.addrs();
+        match self.1 {
+            None => write!(f, "{} -> {}", source, dest),
+            // SocketAddr brackets IPv6 so the port stays apart
+            Some(t) => write!(f, "{} -> {}/{}",
+                              SocketAddr::new(source, t.source_port), SocketAddr::new(dest, t.dest_port),
+                              protocol_name(t.protocol))
         }
     }
 }
 
 impl Display for StatsValue {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "count : {} size : {}", self.total_count, self.total_length)
+        write!(f, "count : {} size : {}", term::count(self.total_count as i128), term::bytes(self.total_length as i128))
     }
 }
 
@@ -99,9 +534,105 @@ impl Display for Stats {
     }
 }
 
-pub fn update_db(mut unlocked_db: MutexGuard<Stats>, stats: (StatsKey, u128)) {
-    let unlocked_db = &mut unlocked_db.0;
-    let entry = unlocked_db.entry(stats.0).or_insert(StatsValue::new());
+/// One packet's worth of change to a flow
+pub struct StatsUpdate {
+    pub key: StatsKey,
+    pub length: u128,
+    pub ip_length: u128,
+    pub wire_length: u128,
+    pub retransmission: bool,
+    /// A SYN without ACK
+    pub opening: bool,
+    /// TCP or UDP without payload
+    pub empty: bool,
+    /// Unix microseconds the packet came in, only with --track-jitter
+    pub arrived: Option<u64>,
+    /// Advertised TCP window, scaled, only with --track-windows
+    pub window: Option<u32>,
+    /// Set once per connection, by --guess-protocol
+    pub app_protocol: Option<AppProtocol>,
+}
+
+/// The flow with the most bytes yet, at the time it got there
+#[derive(Serialize, Clone, Copy)]
+pub struct PeakFlow {
+    pub flow: StatsKey,
+    pub total_length: u128,
+    /// Unix seconds
+    pub at: u64,
+}
+
+impl PeakFlow {
+    pub fn of(stats: &Stats) -> Option<Self> {
+        stats.0.iter()
+            .max_by_key(|(_, v)| v.total_length)
+            .map(|(k, v)| PeakFlow { flow: *k, total_length: v.total_length, at: v.last_seen })
+    }
+
+    /// Take `other` when it's bigger
+    pub fn max(peak: Option<Self>, other: Option<Self>) -> Option<Self> {
+        match (peak, other) {
+            (Some(p), Some(o)) if o.total_length > p.total_length => Some(o),
+            (None, o) => o,
+            (p, _) => p
+        }
+    }
+}
+
+/// Many updates under one lock, see `update_db`
+pub fn update_db_batch(mut unlocked_db: MutexGuard<Stats>, batch: impl IntoIterator<Item = StatsUpdate>,
+                       sequence: u64, track_rates: bool) -> Option<PeakFlow> {
+    update_db(&mut unlocked_db, batch, sequence, track_rates)
+}
+
+/// Each flow touched gets stamped with `sequence`. `track_rates` keeps
+/// `StatsValue::samples`. Gives back the biggest flow touched.
+pub fn update_db(db: &mut Stats, batch: impl IntoIterator<Item = StatsUpdate>, sequence: u64,
+                 track_rates: bool) -> Option<PeakFlow> {
+    let now = unix_now();
+    let mut peak = None;
+    for stats in batch {
+        let key = stats.key;
+        let total_length = apply(db, stats, now, sequence, track_rates);
+        peak = PeakFlow::max(peak, Some(PeakFlow { flow: key, total_length, at: now }));
+    }
+    peak
+}
+
+/// The flow's total_length after
+fn apply(db: &mut Stats, stats: StatsUpdate, now: u64, sequence: u64, track_rates: bool) -> u128 {
+    let entry = db.0.entry(stats.key).or_default();
+    if entry.first_seen == 0 {
+        entry.first_seen = now;
+    }
+    entry.last_seen = now;
     entry.total_count += 1;
-    entry.total_length += stats.1;
+    entry.total_length += stats.length;
+    entry.ip_bytes += stats.ip_length;
+    entry.wire_bytes += stats.wire_length;
+    entry.max_packet_size = entry.max_packet_size.max(stats.length);
+    entry.sequence = sequence;
+    if stats.retransmission {
+        entry.retransmissions += 1;
+    }
+    if stats.opening {
+        entry.connections += 1;
+    }
+    if stats.empty {
+        entry.empty_packets += 1;
+    }
+    if stats.app_protocol.is_some() {
+        entry.app_protocol = stats.app_protocol;
+    }
+    entry.rate.record(now, stats.length);
+    if let Some(at) = stats.arrived {
+        entry.jitter.get_or_insert_with(Box::default).record(at);
+    }
+    if let Some(window) = stats.window {
+        WindowStats::record(&mut entry.window, window);
+    }
+    if track_rates {
+        entry.samples.get_or_insert_with(Box::default).record(now, stats.length);
+    }
+    entry.total_length
 }
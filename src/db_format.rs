@@ -0,0 +1,281 @@
+use std::io::{Read, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use either::Either;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::app_protocol::AppProtocol;
+use crate::data::{Ipv4StatsKey, Ipv6StatsKey, Stats, StatsKey, StatsValue, TransportKey};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Starts bincode databases of `Record`s. Older ones of `LegacyRecord`s start with the
+/// record count, which is never this big.
+const BINCODE_V2: [u8; 8] = *b"witdb\0\0\x02";
+
+/// How the database is stored
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbFormat {
+    Json,
+    /// Addresses as bytes and numbers fixed size, no key format involved
+    Bincode,
+}
+
+impl FromStr for DbFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(DbFormat::Json),
+            "bincode" => Ok(DbFormat::Bincode),
+            _ => Err(format!("Unknown database format: {}", s))
+        }
+    }
+}
+
+impl DbFormat {
+    /// The one asked for, else bincode for .bin and .bincode files, else JSON. A .gz is looked through.
+    pub fn of(path: &Path, chosen: Option<DbFormat>) -> DbFormat {
+        let path = if is_gzip(path) { path.with_extension("") } else { path.to_path_buf() };
+        chosen.unwrap_or(match path.extension().and_then(|e| e.to_str()) {
+            Some("bin" | "bincode") => DbFormat::Bincode,
+            _ => DbFormat::Json
+        })
+    }
+}
+
+/// A flow as stored in bincode, what `StatsValue` keeps across restarts
+#[derive(Serialize, Deserialize)]
+struct Record {
+    source: IpAddr,
+    dest: IpAddr,
+    // (protocol, source port, dest port)
+    transport: Option<(u8, u16, u16)>,
+    total_length: u128,
+    total_count: u128,
+    ip_bytes: u128,
+    wire_bytes: u128,
+    first_seen: u64,
+    last_seen: u64,
+    retransmissions: u128,
+    max_packet_size: u128,
+    sequence: u64,
+    connections: u128,
+    empty_packets: u128,
+    app_protocol: Option<AppProtocol>,
+}
+
+/// A flow as bincode stored it before BINCODE_V2, without connections, empty_packets and app_protocol
+#[derive(Deserialize)]
+struct LegacyRecord {
+    source: IpAddr,
+    dest: IpAddr,
+    transport: Option<(u8, u16, u16)>,
+    total_length: u128,
+    total_count: u128,
+    ip_bytes: u128,
+    wire_bytes: u128,
+    first_seen: u64,
+    last_seen: u64,
+    retransmissions: u128,
+    max_packet_size: u128,
+    sequence: u64,
+}
+
+impl From<LegacyRecord> for Record {
+    fn from(r: LegacyRecord) -> Self {
+        Record {
+            source: r.source,
+            dest: r.dest,
+            transport: r.transport,
+            total_length: r.total_length,
+            total_count: r.total_count,
+            ip_bytes: r.ip_bytes,
+            wire_bytes: r.wire_bytes,
+            first_seen: r.first_seen,
+            last_seen: r.last_seen,
+            retransmissions: r.retransmissions,
+            max_packet_size: r.max_packet_size,
+            sequence: r.sequence,
+            connections: 0,
+            empty_packets: 0,
+            app_protocol: None,
+        }
+    }
+}
+
+impl Record {
+    fn of(k: &StatsKey, v: &StatsValue) -> Record {
+        let (source, dest) = match &k.0 {
+            Either::Left(k) => (IpAddr::V4(k.source), IpAddr::V4(k.dest)),
+            Either::Right(k) => (IpAddr::V6(k.source), IpAddr::V6(k.dest))
+        };
+        Record {
+            source,
+            dest,
+            transport: k.1.map(|t| (t.protocol, t.source_port, t.dest_port)),
+            total_length: v.total_length,
+            total_count: v.total_count,
+            ip_bytes: v.ip_bytes,
+            wire_bytes: v.wire_bytes,
+            first_seen: v.first_seen,
+            last_seen: v.last_seen,
+            retransmissions: v.retransmissions,
+            max_packet_size: v.max_packet_size,
+            sequence: v.sequence,
+            connections: v.connections,
+            empty_packets: v.empty_packets,
+            app_protocol: v.app_protocol,
+        }
+    }
+
+    fn flow(self) -> Result<(StatsKey, StatsValue), String> {
+        let hosts = match (self.source, self.dest) {
+            (IpAddr::V4(source), IpAddr::V4(dest)) => Either::Left(Ipv4StatsKey { source, dest }),
+            (IpAddr::V6(source), IpAddr::V6(dest)) => Either::Right(Ipv6StatsKey { source, dest }),
+            _ => return Err("Invalid StatsKey: mixed IPv4 and IPv6".to_string())
+        };
+        let transport = self.transport.map(|(protocol, source_port, dest_port)| TransportKey { protocol, source_port, dest_port });
+        let value = StatsValue {
+            total_length: self.total_length,
+            total_count: self.total_count,
+            ip_bytes: self.ip_bytes,
+            wire_bytes: self.wire_bytes,
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+            retransmissions: self.retransmissions,
+            max_packet_size: self.max_packet_size,
+            sequence: self.sequence,
+            connections: self.connections,
+            empty_packets: self.empty_packets,
+            app_protocol: self.app_protocol,
+            ..StatsValue::default()
+        };
+        Ok((StatsKey(hosts, transport), value))
+    }
+}
+
+pub fn encode(stats: &Stats, format: DbFormat) -> Vec<u8> {
+    match format {
+        DbFormat::Json => serde_json::to_vec(stats)
+            .unwrap_or_else(|e| panic!("Fail to serialize database: {}", e)),
+        DbFormat::Bincode => {
+            let records: Vec<Record> = stats.0.iter().map(|(k, v)| Record::of(k, v)).collect();
+            let mut data = BINCODE_V2.to_vec();
+            bincode::serialize_into(&mut data, &records).unwrap_or_else(|e| panic!("Fail to serialize database: {}", e));
+            data
+        }
+    }
+}
+
+/// Named for gzip, going by the extension
+pub fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "gz")
+}
+
+/// Either format gzipped, `decode` takes it as it is
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).and_then(|_| encoder.finish())
+        .unwrap_or_else(|e| panic!("Fail to gzip database: {}", e))
+}
+
+/// Empty or whitespace only is no flows in either format, older versions left databases empty.
+/// Gzipped is told by its magic and unpacked first.
+pub fn decode(data: &[u8], format: DbFormat) -> Result<Stats, String> {
+    if data.starts_with(&GZIP_MAGIC) {
+        let mut unpacked = Vec::new();
+        MultiGzDecoder::new(data).read_to_end(&mut unpacked).map_err(|e| e.to_string())?;
+        return decode(&unpacked, format);
+    }
+    if data.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(Stats::new());
+    }
+    match format {
+        DbFormat::Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+        DbFormat::Bincode => {
+            let records: Vec<Record> = match data.strip_prefix(&BINCODE_V2) {
+                Some(data) => bincode::deserialize(data).map_err(|e| e.to_string())?,
+                None => bincode::deserialize::<Vec<LegacyRecord>>(data).map_err(|e| e.to_string())?
+                    .into_iter().map(Record::from).collect()
+            };
+            records.into_iter().map(Record::flow).collect::<Result<_, _>>().map(Stats)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn stats() -> Stats {
+        let mut stats = Stats::new();
+        let hosts = Either::Left(Ipv4StatsKey { source: Ipv4Addr::new(10, 0, 0, 1), dest: Ipv4Addr::new(10, 0, 0, 2) });
+        let value = StatsValue {
+            total_length: 1500,
+            total_count: 3,
+            ip_bytes: 1560,
+            wire_bytes: 1602,
+            first_seen: 1700000000,
+            last_seen: 1700000060,
+            retransmissions: 1,
+            connections: 2,
+            empty_packets: 1,
+            max_packet_size: 1000,
+            sequence: 7,
+            app_protocol: Some(AppProtocol::Tls),
+            ..StatsValue::default()
+        };
+        stats.0.insert(StatsKey(hosts, Some(TransportKey { protocol: 6, source_port: 40000, dest_port: 443 })), value.clone());
+        stats.0.insert(StatsKey(hosts, None), StatsValue { app_protocol: None, ..value });
+        stats
+    }
+
+    fn round_trip(format: DbFormat) {
+        let stats = stats();
+        let decoded = decode(&encode(&stats, format), format).unwrap();
+        assert_eq!(decoded.0.len(), stats.0.len());
+        for (k, v) in &stats.0 {
+            let d = &decoded.0[k];
+            assert_eq!((d.total_length, d.total_count, d.ip_bytes, d.wire_bytes), (v.total_length, v.total_count, v.ip_bytes, v.wire_bytes));
+            assert_eq!((d.first_seen, d.last_seen, d.sequence), (v.first_seen, v.last_seen, v.sequence));
+            assert_eq!((d.retransmissions, d.connections, d.empty_packets, d.max_packet_size),
+                       (v.retransmissions, v.connections, v.empty_packets, v.max_packet_size));
+            assert_eq!(d.app_protocol, v.app_protocol);
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip(DbFormat::Json);
+    }
+
+    #[test]
+    fn bincode_round_trip() {
+        round_trip(DbFormat::Bincode);
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let stats = stats();
+        assert_eq!(decode(&gzip(&encode(&stats, DbFormat::Bincode)), DbFormat::Bincode).unwrap().0.len(), 2);
+    }
+
+    #[test]
+    fn reads_legacy_bincode() {
+        let (k, v) = stats().0.into_iter().find(|(k, _)| k.1.is_some()).unwrap();
+        let r = Record::of(&k, &v);
+        let legacy = (r.source, r.dest, r.transport, r.total_length, r.total_count, r.ip_bytes, r.wire_bytes,
+                      r.first_seen, r.last_seen, r.retransmissions, r.max_packet_size, r.sequence);
+        let decoded = decode(&bincode::serialize(&vec![legacy]).unwrap(), DbFormat::Bincode).unwrap();
+        let d = &decoded.0[&k];
+        assert_eq!((d.total_length, d.sequence, d.connections, d.app_protocol), (1500, 7, 0, None));
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::Read;
+use std::sync::atomic::Ordering;
+
+use rouille::{Request, Response};
+use serde_json::{json, Value};
+
+use crate::data::protocol_name;
+use crate::packet::{proc_packet, PacketInfo};
+use crate::runtime::Runtime;
+
+// POST /debug/packet, only with --debug: what proc_packet makes of a frame
+
+/// Hex, any case, maybe with spaces, colons or newlines, is twice that
+const MAX_BODY: u64 = 2 * 65536 + 4096;
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace() && *b != b':').collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()).collect()
+}
+
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let mut bits = 0u32;
+    let mut nbits = 0;
+    let mut out = Vec::new();
+    for b in s.bytes().filter(|b| !b.is_ascii_whitespace()).take_while(|b| *b != b'=') {
+        let v = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None
+        };
+        bits = (bits << 6) | v as u32;
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            out.push((bits >> nbits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Hex when it only has hex digits, base64 otherwise
+fn decode(s: &str) -> Option<Vec<u8>> {
+    let is_hex = s.bytes().all(|b| b.is_ascii_hexdigit() || b.is_ascii_whitespace() || b == b':');
+    if is_hex { decode_hex(s) } else { decode_base64(s) }
+}
+
+fn describe(info: &PacketInfo) -> Value {
+    json!({
+        "key": info.key.to_string(),
+        "length": info.length as u64,
+        "ip_length": info.ip_length as u64,
+        "wire_length": info.wire_length as u64,
+        "missing": info.missing as u64,
+        "cast": format!("{:?}", info.cast).to_lowercase(),
+        "source_mac": info.source_mac.to_string(),
+        "dest_mac": info.dest_mac.to_string(),
+        "vlans": { "outer": info.vlans.outer, "inner": info.vlans.inner },
+        "protocol": protocol_name(info.protocol.0),
+        "tcp": info.tcp.as_ref().map(|t| json!({
+            "source_port": t.source_port,
+            "dest_port": t.dest_port,
+            "sequence": t.sequence,
+            "flags": t.flags,
+            "window": t.window,
+            "window_scale": t.window_scale,
+        })),
+        "udp": info.udp.as_ref().map(|u| json!({ "source_port": u.source_port, "dest_port": u.dest_port })),
+        "icmp": info.icmp.as_ref().map(|i| json!({ "type": i.icmp_type, "code": i.code })),
+        "payload_length": info.payload.len(),
+    })
+}
+
+/// What the counters bumped on the way say about the frame
+fn findings(runtime: &Runtime) -> Vec<&'static str> {
+    [
+        (&runtime.malformed, "malformed: too short for the headers it claims"),
+        (&runtime.truncated, "truncated: the IP header claims more than the frame carries"),
+        (&runtime.padded, "padded: the frame carries more than the IP header claims"),
+        (&runtime.jumbo_frames, "jumbo: over the standard 1500 bytes MTU"),
+    ].into_iter().filter(|(c, _)| c.load(Ordering::Relaxed) > 0).map(|(_, what)| what).collect()
+}
+
+pub fn handle(request: &Request) -> Response {
+    if request.method() != "POST" || request.url() != "/debug/packet" {
+        return Response::empty_404();
+    }
+    let mut body = String::new();
+    let read = request.data().map(|d| d.take(MAX_BODY).read_to_string(&mut body));
+    if !matches!(read, Some(Ok(_))) {
+        return Response::text("Expected a hex or base64 Ethernet frame").with_status_code(400);
+    }
+    let frame = match decode(&body) {
+        Some(frame) => frame,
+        None => return Response::text("Invalid hex or base64").with_status_code(400)
+    };
+    // Its own counters, the real ones only count real traffic
+    let runtime = Runtime::default();
+    let info = proc_packet(&frame, &runtime, &[]);
+    let findings = findings(&runtime);
+    Response::json(&match info {
+        Some(info) => json!({ "counted": true, "packet": describe(&info), "findings": findings }),
+        None => json!({
+            "counted": false,
+            "reason": findings.first().copied().unwrap_or("not IPv4 or IPv6"),
+            "findings": findings,
+        })
+    })
+}
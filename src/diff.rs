@@ -0,0 +1,84 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::data::{Stats, StatsKey, StatsValue};
+use crate::db_format::{self, DbFormat};
+use crate::{key_format, term};
+
+#[derive(Serialize)]
+struct Delta {
+    total_length: i128,
+    total_count: i128,
+}
+
+#[derive(Serialize)]
+struct StatsDiff<'a> {
+    #[serde(serialize_with = "key_format::flow_map")]
+    changed: HashMap<&'a StatsKey, Delta>,
+    #[serde(serialize_with = "key_format::flow_map")]
+    appeared: HashMap<&'a StatsKey, &'a StatsValue>,
+    #[serde(serialize_with = "key_format::flow_map")]
+    disappeared: HashMap<&'a StatsKey, &'a StatsValue>,
+}
+
+fn load(path: &Path) -> Stats {
+    let data = fs::read(path)
+        .unwrap_or_else(|e| panic!("Fail to read database {}: {}", path.display(), e));
+    db_format::decode(&data, DbFormat::of(path, None))
+        .unwrap_or_else(|e| panic!("Fail to parse database {}: {}", path.display(), e))
+}
+
+fn diff<'a>(old: &'a Stats, new: &'a Stats) -> StatsDiff<'a> {
+    let mut changed = HashMap::new();
+    let mut appeared = HashMap::new();
+    for (k, v) in &new.0 {
+        match old.0.get(k) {
+            Some(o) if o.total_length != v.total_length || o.total_count != v.total_count => {
+                changed.insert(k, Delta {
+                    total_length: v.total_length as i128 - o.total_length as i128,
+                    total_count: v.total_count as i128 - o.total_count as i128,
+                });
+            }
+            Some(_) => (),
+            None => {
+                appeared.insert(k, v);
+            }
+        }
+    }
+    let disappeared = old.0.iter().filter(|(k, _)| !new.0.contains_key(k)).collect();
+    StatsDiff { changed, appeared, disappeared }
+}
+
+fn signed(n: String) -> String {
+    if n.starts_with('-') { n } else { format!("+{}", n) }
+}
+
+/// Print what changed going from snapshot `old` to snapshot `new`
+pub fn run(old: &Path, new: &Path, json: bool) {
+    let (old, new) = (load(old), load(new));
+    let d = diff(&old, &new);
+    if json {
+        println!("{}", serde_json::to_string(&d).unwrap());
+        return;
+    }
+
+    let mut changed: Vec<_> = d.changed.iter().collect();
+    changed.sort_by_key(|(_, v)| Reverse(v.total_length));
+    for (k, v) in changed {
+        println!("  {} --- count : {} size : {}", k, signed(term::count(v.total_count)), signed(term::bytes(v.total_length)));
+    }
+    let mut appeared: Vec<_> = d.appeared.iter().collect();
+    appeared.sort_by_key(|(_, v)| Reverse(v.total_length));
+    for (k, v) in appeared {
+        println!("+ {} --- {}", k, v);
+    }
+    let mut disappeared: Vec<_> = d.disappeared.iter().collect();
+    disappeared.sort_by_key(|(_, v)| Reverse(v.total_length));
+    for (k, v) in disappeared {
+        println!("- {} --- {}", k, v);
+    }
+}
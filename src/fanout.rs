@@ -0,0 +1,157 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashSet};
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use pnet::packet::tcp::TcpFlags;
+use serde::{Serialize, Serializer};
+
+use crate::data::protocol_name;
+use crate::packet::PacketInfo;
+use crate::table::IdleTable;
+
+/// Sources tracked at most, new ones are ignored while full
+const MAX_HOSTS: usize = 65536;
+/// A source quiet that long is forgotten, making room for new ones
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+/// Exact sets stop growing here
+const MAX_EXACT: usize = 4096;
+/// 2^10 one byte registers, about 3% standard error
+const HLL_BITS: u32 = 10;
+const HLL_REGISTERS: usize = 1 << HLL_BITS;
+/// Protocols listed per source at most
+const MAX_PROTOCOLS: usize = 32;
+
+/// Distinct count of what went in, exact up to `MAX_EXACT` or estimated
+enum Cardinality {
+    Exact(HashSet<u64>),
+    Estimated(Box<[u8; HLL_REGISTERS]>),
+}
+
+fn hash_of(value: impl Hash) -> u64 {
+    // Fixed keys, the same value always lands in the same register
+    let mut h = DefaultHasher::new();
+    value.hash(&mut h);
+    h.finish()
+}
+
+impl Cardinality {
+    fn new(hll: bool) -> Self {
+        if hll { Cardinality::Estimated(Box::new([0; HLL_REGISTERS])) } else { Cardinality::Exact(HashSet::new()) }
+    }
+
+    fn insert(&mut self, value: impl Hash) {
+        let hash = hash_of(value);
+        match self {
+            Cardinality::Exact(set) => if set.len() < MAX_EXACT {
+                set.insert(hash);
+            },
+            Cardinality::Estimated(registers) => {
+                let index = (hash >> (64 - HLL_BITS)) as usize;
+                // Leading zeros of what's left, plus one
+                let rank = ((hash << HLL_BITS) | (1 << (HLL_BITS - 1))).leading_zeros() as u8 + 1;
+                registers[index] = registers[index].max(rank);
+            }
+        }
+    }
+
+    fn count(&self) -> u64 {
+        match self {
+            Cardinality::Exact(set) => set.len() as u64,
+            Cardinality::Estimated(registers) => {
+                let m = HLL_REGISTERS as f64;
+                let alpha = 0.7213 / (1.0 + 1.079 / m);
+                let sum: f64 = registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+                let estimate = alpha * m * m / sum;
+                let zeros = registers.iter().filter(|r| **r == 0).count();
+                // Linear counting does better while registers are still empty
+                if estimate <= 2.5 * m && zeros > 0 {
+                    (m * (m / zeros as f64).ln()).round() as u64
+                } else {
+                    estimate.round() as u64
+                }
+            }
+        }
+    }
+
+    /// The exact set is full, the count is a floor
+    fn saturated(&self) -> bool {
+        matches!(self, Cardinality::Exact(set) if set.len() >= MAX_EXACT)
+    }
+}
+
+impl Serialize for Cardinality {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: Serializer {
+        serializer.serialize_u64(self.count())
+    }
+}
+
+#[derive(Serialize)]
+pub struct HostFanout {
+    /// Destination ports of TCP connection attempts and UDP datagrams
+    ports: Cardinality,
+    /// Destination addresses of anything it sent
+    hosts: Cardinality,
+    /// Counts are estimates (--fanout-hll) or floors (exact set full), not exact
+    approximate: bool,
+    /// IP protocol and, for TCP connection attempts and UDP, destination port.
+    /// The first `MAX_PROTOCOLS` seen.
+    #[serde(serialize_with = "protocols")]
+    protocols: BTreeSet<(u8, Option<u16>)>,
+}
+
+fn protocols<S>(protocols: &BTreeSet<(u8, Option<u16>)>, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where S: Serializer {
+    serializer.collect_seq(protocols.iter().map(|(protocol, port)| match port {
+        Some(port) => format!("{}/{}", protocol_name(*protocol), port),
+        None => protocol_name(*protocol)
+    }))
+}
+
+/// Per source address, how many ports and hosts it reached out to
+pub struct Fanout {
+    hll: bool,
+    hosts: IdleTable<IpAddr, HostFanout>,
+}
+
+impl Fanout {
+    pub fn new(hll: bool) -> Self {
+        Fanout { hll, hosts: IdleTable::new(MAX_HOSTS, IDLE_TIMEOUT) }
+    }
+
+    pub fn observe(&mut self, info: &PacketInfo) {
+        let (source, dest) = info.key.addrs();
+        let hll = self.hll;
+        let Some(host) = self.hosts.get_or_insert_with(source, || HostFanout {
+            ports: Cardinality::new(hll),
+            hosts: Cardinality::new(hll),
+            approximate: hll,
+            protocols: BTreeSet::new(),
+        }) else {
+            return;
+        };
+        host.hosts.insert(dest);
+        let port = match (&info.tcp, &info.udp) {
+            (Some(tcp), _) if tcp.flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN => Some(tcp.dest_port),
+            (None, Some(udp)) => Some(udp.dest_port),
+            _ => None
+        };
+        if let Some(port) = port {
+            host.ports.insert(port);
+        }
+        // The rest of a TCP connection says nothing its SYN didn't
+        if (port.is_some() || info.tcp.is_none()) && host.protocols.len() < MAX_PROTOCOLS {
+            host.protocols.insert((info.protocol.0, port));
+        }
+        host.approximate = hll || host.ports.saturated() || host.hosts.saturated();
+    }
+}
+
+impl Serialize for Fanout {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: Serializer {
+        serializer.collect_map(self.hosts.iter())
+    }
+}
@@ -0,0 +1,351 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cidr::Cidr;
+use crate::packet::PacketInfo;
+
+/// Which end of the packet a qualifier looks at
+#[derive(Debug, Clone, Copy)]
+enum Dir {
+    Src,
+    Dst,
+    Either,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Host(Dir, IpAddr),
+    Net(Dir, Cidr),
+    Port(Dir, u16, u16),
+    Protocol(u8),
+    Ipv4,
+    Ipv6,
+    Vlan(Option<u16>),
+    Less(u128),
+    Greater(u128),
+}
+
+/// The pcap-filter(7) primitives that make sense on parsed IP packets:
+/// `[src|dst] host|net|port|portrange`, `ip`, `ip6`, `tcp`, `udp`, `icmp`, `icmp6`,
+/// `proto N`, `vlan [ID]`, `less N`, `greater N`, joined by `and`, `or`, `not` and parentheses.
+/// As in pcap-filter, `and` and `or` bind alike from left to right, primitives side by side are
+/// joined by `and`, and a protocol goes with the primitive it qualifies: `not tcp port 80`.
+/// Evaluated in userspace, so it filters what gets counted rather than what gets captured.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    root: Node,
+}
+
+impl Filter {
+    /// Like `--filter`, lines starting with `#` are comments
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let s = fs::read_to_string(path)
+            .map_err(|e| format!("Fail to read filter {}: {}", path.display(), e))?;
+        let expr: Vec<&str> = s.lines().filter(|l| !l.trim_start().starts_with('#')).collect();
+        expr.join(" ").parse()
+    }
+
+    pub fn matches(&self, info: &PacketInfo) -> bool {
+        eval(&self.root, info)
+    }
+}
+
+fn by_dir(dir: Dir, f: impl Fn(bool) -> bool) -> bool {
+    match dir {
+        Dir::Src => f(true),
+        Dir::Dst => f(false),
+        Dir::Either => f(true) || f(false)
+    }
+}
+
+fn eval(node: &Node, info: &PacketInfo) -> bool {
+    let (source, dest) = info.key.addrs();
+    let addr = |src: bool| if src { source } else { dest };
+    match node {
+        Node::And(a, b) => eval(a, info) && eval(b, info),
+        Node::Or(a, b) => eval(a, info) || eval(b, info),
+        Node::Not(a) => !eval(a, info),
+        Node::Host(dir, ip) => by_dir(*dir, |src| addr(src) == *ip),
+        Node::Net(dir, cidr) => by_dir(*dir, |src| cidr.contains(addr(src))),
+        Node::Port(dir, low, high) => {
+            if info.tcp.is_none() && info.udp.is_none() {
+                return false;
+            }
+            let t = info.transport_key();
+            by_dir(*dir, |src| (*low..=*high).contains(if src { &t.source_port } else { &t.dest_port }))
+        }
+        Node::Protocol(p) => info.protocol.0 == *p,
+        Node::Ipv4 => source.is_ipv4(),
+        Node::Ipv6 => source.is_ipv6(),
+        Node::Vlan(None) => info.vlans.outer.is_some(),
+        Node::Vlan(Some(id)) => info.vlans.outer == Some(*id) || info.vlans.inner == Some(*id),
+        Node::Less(n) => info.wire_length <= *n,
+        Node::Greater(n) => info.wire_length >= *n
+    }
+}
+
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in s.chars() {
+        if c.is_whitespace() || c == '(' || c == ')' || c == '!' {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.at).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.get(self.at).cloned().ok_or("Unexpected end of filter")?;
+        self.at += 1;
+        Ok(token)
+    }
+
+    fn expr(&mut self) -> Result<Node, String> {
+        let mut node = self.not()?;
+        loop {
+            node = match self.peek() {
+                Some("and") | Some("&&") => {
+                    self.at += 1;
+                    Node::And(Box::new(node), Box::new(self.not()?))
+                }
+                Some("or") | Some("||") => {
+                    self.at += 1;
+                    Node::Or(Box::new(node), Box::new(self.not()?))
+                }
+                Some(token) if token != ")" => Node::And(Box::new(node), Box::new(self.not()?)),
+                _ => return Ok(node)
+            };
+        }
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        if matches!(self.peek(), Some("not") | Some("!")) {
+            self.at += 1;
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T, String> {
+        let token = self.next()?;
+        token.parse().map_err(|_| format!("Expected a number, got {}", token))
+    }
+
+    /// `protocol` and the primitive right after it if that's one it can qualify, `tcp port 80`
+    fn qualified(&mut self, protocol: Node) -> Result<Node, String> {
+        match self.peek() {
+            Some("src" | "dst" | "host" | "net" | "port" | "portrange") =>
+                Ok(Node::And(Box::new(protocol), Box::new(self.primary()?))),
+            _ => Ok(protocol)
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self.next()?;
+        let (dir, token) = match token.as_str() {
+            "src" => (Dir::Src, self.next()?),
+            "dst" => (Dir::Dst, self.next()?),
+            _ => (Dir::Either, token)
+        };
+        let node = match token.as_str() {
+            "(" => {
+                let node = self.expr()?;
+                if self.next()? != ")" {
+                    return Err("Expected )".to_string());
+                }
+                return Ok(node);
+            }
+            "host" => {
+                let ip = self.next()?;
+                Node::Host(dir, ip.parse().map_err(|_| format!("Invalid host address: {}", ip))?)
+            }
+            "net" => Node::Net(dir, self.next()?.parse()?),
+            "port" => {
+                let port = self.number()?;
+                Node::Port(dir, port, port)
+            }
+            "portrange" => {
+                let range = self.next()?;
+                let (low, high) = range.split_once('-')
+                    .and_then(|(l, h)| Some((l.parse().ok()?, h.parse().ok()?)))
+                    .ok_or(format!("Invalid port range: {}", range))?;
+                Node::Port(dir, low, high)
+            }
+            _ if !matches!(dir, Dir::Either) => return Err(format!("Expected host, net, port or portrange after src/dst, got {}", token)),
+            "ip" => return self.qualified(Node::Ipv4),
+            "ip6" => return self.qualified(Node::Ipv6),
+            "tcp" => return self.qualified(Node::Protocol(6)),
+            "udp" => return self.qualified(Node::Protocol(17)),
+            "icmp" => Node::Protocol(1),
+            "icmp6" => Node::Protocol(58),
+            "proto" => Node::Protocol(self.number()?),
+            "vlan" => match self.peek().map(|t| t.parse::<u16>()) {
+                Some(Ok(id)) => {
+                    self.at += 1;
+                    Node::Vlan(Some(id))
+                }
+                _ => Node::Vlan(None)
+            },
+            "less" => Node::Less(self.number()?),
+            "greater" => Node::Greater(self.number()?),
+            _ => return Err(format!("Unknown filter primitive: {}", token))
+        };
+        Ok(node)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s), at: 0 };
+        let root = parser.expr()?;
+        match parser.peek() {
+            None => Ok(Filter { root }),
+            Some(token) => Err(format!("Unexpected {} in filter", token))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::proc_packet;
+    use crate::runtime::Runtime;
+
+    use super::*;
+
+    /// 10.0.0.1 to 10.0.0.2 over protocol 6 or 17 between the ports, with 4 bytes of payload
+    fn frame(protocol: u8, source_port: u16, dest_port: u16) -> Vec<u8> {
+        let mut transport = [source_port, dest_port].iter().flat_map(|p| p.to_be_bytes()).collect::<Vec<_>>();
+        match protocol {
+            6 => transport.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x10, 0xff, 0xff, 0, 0, 0, 0]),
+            _ => transport.extend_from_slice(&[0, 12, 0, 0])
+        }
+        transport.extend_from_slice(b"abcd");
+        let mut frame = vec![2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1, 0x08, 0, 0x45, 0];
+        frame.extend_from_slice(&(20 + transport.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0, 64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(&transport);
+        frame
+    }
+
+    fn matches(filter: &str, frame: &[u8]) -> bool {
+        let filter: Filter = filter.parse().unwrap_or_else(|e| panic!("{}: {}", filter, e));
+        filter.matches(&proc_packet(frame, &Runtime::default(), &[]).unwrap())
+    }
+
+    fn error(filter: &str) -> String {
+        filter.parse::<Filter>().unwrap_err()
+    }
+
+    #[test]
+    fn joins_primitives_side_by_side() {
+        assert!(matches("tcp port 80", &frame(6, 40000, 80)));
+        assert!(!matches("tcp port 80", &frame(17, 40000, 80)));
+        assert!(!matches("tcp port 80", &frame(6, 40000, 443)));
+        assert!(matches("host 10.0.0.1 and tcp port 443", &frame(6, 40000, 443)));
+        assert!(!matches("host 10.0.0.3 and tcp port 443", &frame(6, 40000, 443)));
+        assert!(matches("host 10.0.0.2 port 53", &frame(17, 40000, 53)));
+        assert!(matches("udp src port 40000", &frame(17, 40000, 53)));
+        assert!(!matches("udp dst port 40000", &frame(17, 40000, 53)));
+    }
+
+    #[test]
+    fn binds_and_and_or_alike_left_to_right() {
+        // (udp or tcp) and port 80
+        assert!(!matches("udp or tcp and port 80", &frame(17, 40000, 53)));
+        assert!(matches("udp or tcp and port 80", &frame(17, 40000, 80)));
+        // (tcp and port 80) or udp
+        assert!(matches("tcp and port 80 or udp", &frame(17, 40000, 53)));
+        assert!(matches("udp or (tcp and port 80)", &frame(17, 40000, 53)));
+        assert!(!matches("(udp or tcp) and (port 80 || port 443)", &frame(6, 40000, 22)));
+        assert!(matches("(udp or tcp) && (port 80 || port 443)", &frame(6, 40000, 443)));
+    }
+
+    #[test]
+    fn negates_the_qualified_primitive() {
+        assert!(!matches("not tcp port 80", &frame(6, 40000, 80)));
+        assert!(matches("not tcp port 80", &frame(6, 40000, 443)));
+        assert!(matches("not tcp port 80", &frame(17, 40000, 80)));
+        assert!(matches("! tcp", &frame(17, 40000, 80)));
+        assert!(matches("not not udp", &frame(17, 40000, 80)));
+        assert!(matches("tcp and not port 22", &frame(6, 40000, 80)));
+        assert!(!matches("not (udp or tcp)", &frame(6, 40000, 80)));
+    }
+
+    #[test]
+    fn matches_the_other_primitives() {
+        let udp = frame(17, 40000, 53);
+        assert!(matches("ip", &udp));
+        assert!(!matches("ip6", &udp));
+        assert!(matches("net 10.0.0.0/8", &udp));
+        assert!(matches("src net 10.0.0.1/32", &udp));
+        assert!(!matches("dst net 10.0.0.1/32", &udp));
+        assert!(matches("portrange 50-60", &udp));
+        assert!(!matches("portrange 54-60", &udp));
+        assert!(matches("proto 17", &udp));
+        assert!(!matches("vlan", &udp));
+        assert!(!matches("icmp", &udp));
+    }
+
+    #[test]
+    fn sizes_by_the_frame() {
+        // 14 + 20 + 8 + 4, the IP datagram is 32
+        let udp = frame(17, 40000, 53);
+        assert!(matches("less 46", &udp));
+        assert!(!matches("less 45", &udp));
+        assert!(matches("greater 46", &udp));
+        assert!(!matches("greater 47", &udp));
+    }
+
+    #[test]
+    fn says_what_is_wrong() {
+        assert_eq!(error(""), "Unexpected end of filter");
+        assert_eq!(error("tcp port"), "Unexpected end of filter");
+        assert_eq!(error("(tcp or udp"), "Unexpected end of filter");
+        assert_eq!(error("tcp)"), "Unexpected ) in filter");
+        assert_eq!(error("(tcp udp"), "Unexpected end of filter");
+        assert_eq!(error("port http"), "Expected a number, got http");
+        assert_eq!(error("port 70000"), "Expected a number, got 70000");
+        assert_eq!(error("src tcp"), "Expected host, net, port or portrange after src/dst, got tcp");
+        assert_eq!(error("host nope"), "Invalid host address: nope");
+        assert_eq!(error("portrange 5"), "Invalid port range: 5");
+        assert_eq!(error("tcp or bogus"), "Unknown filter primitive: bogus");
+        assert_eq!(error("tcp and"), "Unexpected end of filter");
+    }
+
+    #[test]
+    fn skips_comment_lines_in_files() {
+        let path = std::env::temp_dir().join(format!("whoisthere-filter-{}", std::process::id()));
+        fs::write(&path, "# web only\ntcp\n  # not ssh\nand not port 22\n").unwrap();
+        let filter = Filter::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(filter.unwrap().matches(&proc_packet(&frame(6, 40000, 80), &Runtime::default(), &[]).unwrap()));
+    }
+}
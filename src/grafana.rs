@@ -0,0 +1,164 @@
+use rouille::{router, Request, Response};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::data::unix_now;
+use crate::history::{self, Sample};
+use crate::state::State;
+
+// The Grafana SimpleJSON datasource protocol, mounted under /grafana
+
+/// Series over the whole network, the per flow ones are "bps <flow>"
+const SERIES: [&str; 5] = ["bps", "pps", "flows", "total_bytes", "total_packets"];
+const TOP_TALKERS: &str = "top_talkers";
+const FLOW_PREFIX: &str = "bps ";
+/// Rows of the top_talkers table
+const TOP_ROWS: usize = 10;
+
+#[derive(Deserialize)]
+struct Search {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Deserialize)]
+struct Range {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    target: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Query {
+    range: Range,
+    targets: Vec<Target>,
+    max_data_points: Option<usize>,
+}
+
+pub fn handle(request: &Request, state: &State) -> Response {
+    router!(request,
+        // Grafana's "Test connection"
+        (GET) ["/grafana"] => { Response::text("OK") },
+        (GET) ["/grafana/"] => { Response::text("OK") },
+        (POST) ["/grafana/search"] => {
+            match rouille::input::json_input::<Search>(request) {
+                Ok(search) => Response::json(&search_metrics(&search.target, state)),
+                Err(e) => Response::text(e.to_string()).with_status_code(400)
+            }
+        },
+        (POST) ["/grafana/query"] => {
+            match rouille::input::json_input::<Query>(request) {
+                Ok(query) => query_metrics(&query, state),
+                Err(e) => Response::text(e.to_string()).with_status_code(400)
+            }
+        },
+        _ => Response::empty_404()
+    )
+}
+
+fn search_metrics(filter: &str, state: &State) -> Vec<String> {
+    let flows = state.history.lock().unwrap().top_flows();
+    SERIES.iter().map(|s| s.to_string())
+        .chain([TOP_TALKERS.to_string()])
+        .chain(flows.iter().map(|k| format!("{}{}", FLOW_PREFIX, k)))
+        .filter(|m| m.contains(filter))
+        .collect()
+}
+
+fn query_metrics(query: &Query, state: &State) -> Response {
+    let (from, to) = match (parse_time(&query.range.from), parse_time(&query.range.to)) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return Response::text("Invalid range, expected RFC 3339 times").with_status_code(400)
+    };
+    let history = state.history.lock().unwrap();
+    let samples: Vec<&Sample> = history.range(from, to).collect();
+    let results: Vec<Value> = query.targets.iter().map(|t| {
+        if t.target == TOP_TALKERS {
+            return top_talkers(state);
+        }
+        let mut points = series(&t.target, &samples);
+        if let Some(max) = query.max_data_points.filter(|m| *m > 0) {
+            let stride = points.len().div_ceil(max).max(1);
+            points = points.into_iter().step_by(stride).collect();
+        }
+        json!({ "target": t.target, "datapoints": points })
+    }).collect();
+    Response::json(&results)
+}
+
+/// [value, unix milliseconds] pairs, empty for unknown targets
+fn series(target: &str, samples: &[&Sample]) -> Vec<(f64, u64)> {
+    match target {
+        "bps" => history::rates(samples.iter().copied()).into_iter().map(|(at, bytes, _)| (bytes * 8.0, at)).collect(),
+        "pps" => history::rates(samples.iter().copied()).into_iter().map(|(at, _, packets)| (packets, at)).collect(),
+        "flows" => samples.iter().map(|s| (s.flows as f64, s.at)).collect(),
+        "total_bytes" => samples.iter().map(|s| (s.total_length as f64, s.at)).collect(),
+        "total_packets" => samples.iter().map(|s| (s.total_count as f64, s.at)).collect(),
+        _ => match target.strip_prefix(FLOW_PREFIX) {
+            // Only where the flow made the sample's top
+            Some(flow) => samples.iter()
+                .filter_map(|s| s.top.iter().find(|(k, _)| k.to_string() == flow).map(|(_, bps)| (*bps, s.at)))
+                .collect(),
+            None => vec![]
+        }
+    }
+}
+
+/// Flows with the highest bps right now
+fn top_talkers(state: &State) -> Value {
+    let stats = state.published();
+    let now = unix_now();
+    let mut flows: Vec<_> = stats.0.iter().map(|(k, v)| (k, v.rate.bits_per_sec(now), v)).collect();
+    flows.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let rows: Vec<Value> = flows.iter().take(TOP_ROWS)
+        .map(|(k, bps, v)| json!([k.to_string(), bps, v.total_length as f64, v.total_count as f64]))
+        .collect();
+    json!({
+        "type": "table",
+        "columns": [
+            { "text": "Flow", "type": "string" },
+            { "text": "bps", "type": "number" },
+            { "text": "Bytes", "type": "number" },
+            { "text": "Packets", "type": "number" },
+        ],
+        "rows": rows,
+    })
+}
+
+/// Days from 1970-01-01 to a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// RFC 3339 as Grafana sends it, e.g. 2016-10-31T06:33:44.866Z, into unix milliseconds
+fn parse_time(s: &str) -> Option<u64> {
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, offset) = match time.strip_suffix('Z').or_else(|| time.strip_suffix('z')) {
+        Some(time) => (time, 0),
+        None => {
+            let at = time.rfind(['+', '-'])?;
+            let (hours, minutes) = time[at + 1..].split_once(':')?;
+            let offset = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+            (&time[..at], if &time[at..at + 1] == "-" { -offset } else { offset })
+        }
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut hms = time.splitn(3, ':').map(|p| p.parse::<i64>().ok());
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+    // Milliseconds, padded or cut to three digits
+    let millis = format!("{:0<3}", fraction.get(..3).unwrap_or(fraction)).parse::<i64>().ok()?;
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    u64::try_from(secs * 1000 + millis).ok()
+}
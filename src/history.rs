@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::data::{unix_now, Stats, StatsKey};
+
+/// Flows kept per sample, those with the highest bps
+const TOP_FLOWS: usize = 10;
+
+pub struct Sample {
+    /// Unix milliseconds
+    pub at: u64,
+    pub total_length: u128,
+    pub total_count: u128,
+    pub flows: usize,
+    pub top: Vec<(StatsKey, f64)>,
+}
+
+impl Sample {
+    /// Just the totals, no top flows
+    pub fn totals(stats: &Stats) -> Self {
+        Sample {
+            at: unix_millis(),
+            total_length: stats.0.values().map(|v| v.total_length).sum(),
+            total_count: stats.0.values().map(|v| v.total_count).sum(),
+            flows: stats.0.len(),
+            top: vec![],
+        }
+    }
+}
+
+/// Totals sampled once per --history-step, the oldest dropped past `capacity`
+pub struct History {
+    samples: VecDeque<Sample>,
+    capacity: usize,
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History { samples: VecDeque::new(), capacity }
+    }
+
+    pub fn record(&mut self, stats: &Stats) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = unix_now();
+        let mut top: Vec<_> = stats.0.iter()
+            .map(|(k, v)| (*k, v.rate.bits_per_sec(now)))
+            .filter(|(_, bps)| *bps > 0.0)
+            .collect();
+        top.sort_by(|a, b| b.1.total_cmp(&a.1));
+        top.truncate(TOP_FLOWS);
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { top, ..Sample::totals(stats) });
+    }
+
+    /// Oldest first, `from` and `to` in unix milliseconds, both inclusive
+    pub fn range(&self, from: u64, to: u64) -> impl Iterator<Item = &Sample> {
+        self.samples.iter().filter(move |s| (from..=to).contains(&s.at))
+    }
+
+    /// Every flow in some sample's top
+    pub fn top_flows(&self) -> Vec<StatsKey> {
+        let mut flows: Vec<_> = self.samples.iter().flat_map(|s| s.top.iter().map(|(k, _)| *k)).collect();
+        flows.sort_by_key(|k| k.to_string());
+        flows.dedup();
+        flows
+    }
+}
+
+/// Per second rates between consecutive samples as (at, bytes, packets), skipping
+/// the first one. Totals going down (a reset) count as a fresh start from zero.
+pub fn rates<'a>(samples: impl Iterator<Item = &'a Sample>) -> Vec<(u64, f64, f64)> {
+    let mut rates = Vec::new();
+    let mut previous: Option<&Sample> = None;
+    for s in samples {
+        if let Some(p) = previous.filter(|p| s.at > p.at) {
+            let secs = (s.at - p.at) as f64 / 1000.0;
+            let delta = |now: u128, then: u128| if now >= then { now - then } else { now } as f64 / secs;
+            rates.push((s.at, delta(s.total_length, p.total_length), delta(s.total_count, p.total_count)));
+        }
+        previous = Some(s);
+    }
+    rates
+}
+
+/// One point of /rate-history
+#[derive(Serialize)]
+pub struct RatePoint {
+    /// Unix milliseconds
+    pub at: u64,
+    pub pps: f64,
+    pub bps: f64,
+}
+
+/// Aggregate rates over the samples of the last `range` milliseconds, oldest first
+pub fn rate_history(history: &History, range: u64) -> Vec<RatePoint> {
+    let from = unix_millis().saturating_sub(range);
+    rates(history.range(from, u64::MAX)).into_iter()
+        .map(|(at, bytes, packets)| RatePoint { at, pps: packets, bps: bytes * 8.0 })
+        .collect()
+}
@@ -0,0 +1,162 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::{fs, thread};
+
+use log::warn;
+use rouille::{Request, Response};
+
+#[derive(Clone)]
+pub struct Tls {
+    pub certificate: Vec<u8>,
+    pub private_key: Vec<u8>,
+}
+
+impl Tls {
+    /// Both files are PEM encoded
+    pub fn load(certificate: &Path, private_key: &Path) -> Self {
+        let read = |p: &Path| fs::read(p)
+            .unwrap_or_else(|e| panic!("Fail to read {}: {}", p.display(), e));
+        Tls { certificate: read(certificate), private_key: read(private_key) }
+    }
+}
+
+/// Serve `handler` on `bind`, which is either a TCP `host:port` or `unix:/path/to.sock`.
+/// `listening` gets the address once bound, with the port the OS picked for port 0.
+/// Only comes back when it can't bind, with why.
+pub fn serve<F, L>(bind: &str, socket_mode: u32, tls: Option<Tls>, handler: F, listening: L) -> String
+    where F: Send + Sync + 'static + Fn(&Request) -> Response, L: FnOnce(String) {
+    match (bind.strip_prefix("unix:"), tls) {
+        (Some(_), Some(_)) => panic!("TLS is not supported on Unix domain sockets"),
+        (Some(path), None) => serve_unix(Path::new(path), socket_mode, handler, || listening(bind.to_string())),
+        (None, Some(tls)) => {
+            let server = match rouille::Server::new_ssl(bind, handler, tls.certificate, tls.private_key) {
+                Ok(server) => server,
+                Err(e) => return format!("Fail to start HTTPS server on {}: {}", bind, e)
+            };
+            listening(server.server_addr().to_string());
+            server.run();
+            panic!("HTTPS server stopped");
+        }
+        (None, None) => {
+            let server = match rouille::Server::new(bind, handler) {
+                Ok(server) => server,
+                Err(e) => return format!("Fail to start HTTP server on {}: {}", bind, e)
+            };
+            listening(server.server_addr().to_string());
+            server.run();
+            panic!("HTTP server stopped");
+        }
+    }
+}
+
+#[cfg(unix)]
+fn serve_unix<F>(path: &Path, socket_mode: u32, handler: F, listening: impl FnOnce()) -> String
+    where F: Send + Sync + 'static + Fn(&Request) -> Response {
+    use std::os::unix::fs::PermissionsExt;
+
+    // A leftover socket from a previous run would make the bind fail
+    if path.exists() {
+        if let Err(e) = fs::remove_file(path) {
+            return format!("Fail to remove stale socket {}: {}", path.display(), e);
+        }
+    }
+    let server = match tiny_http::Server::http_unix(path) {
+        Ok(server) => server,
+        Err(e) => return format!("Fail to bind unix socket {}: {}", path.display(), e)
+    };
+    fs::set_permissions(path, fs::Permissions::from_mode(socket_mode))
+        .unwrap_or_else(|e| panic!("Fail to set socket permissions: {}", e));
+    listening();
+
+    let handler = Arc::new(handler);
+    for request in server.incoming_requests() {
+        let handler = handler.clone();
+        thread::spawn(move || respond_unix(handler.as_ref(), request));
+    }
+    panic!("Unix socket listener closed");
+}
+
+#[cfg(not(unix))]
+fn serve_unix<F>(_path: &Path, _socket_mode: u32, _handler: F, _listening: impl FnOnce()) -> String
+    where F: Send + Sync + 'static + Fn(&Request) -> Response {
+    panic!("Unix domain sockets are not supported on this platform");
+}
+
+// rouille only knows TCP, so do its request/response translation by hand
+fn respond_unix<F>(handler: &F, mut request: tiny_http::Request)
+    where F: Fn(&Request) -> Response {
+    let mut body = Vec::new();
+    if let Err(e) = request.as_reader().read_to_end(&mut body) {
+        warn!("Fail to read request body: {}", e);
+        return;
+    }
+    let headers = request.headers().iter()
+        .map(|h| (h.field.to_string(), h.value.to_string()))
+        .collect();
+    // Unix peers have no address but rouille wants one
+    let from = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+    let rouille_request = Request::fake_http_from(from, request.method().as_str(), request.url(), headers, body);
+
+    let rouille_response = handler(&rouille_request);
+    let (data, length) = rouille_response.data.into_reader_and_size();
+    let mut response = tiny_http::Response::empty(rouille_response.status_code).with_data(data, length);
+    for (key, value) in rouille_response.headers {
+        if key.eq_ignore_ascii_case("Content-Length") {
+            continue;
+        }
+        if let Ok(header) = tiny_http::Header::from_bytes(key.as_bytes(), value.as_bytes()) {
+            response.add_header(header);
+        }
+    }
+    let _ = request.respond(response);
+}
+
+pub enum Auth {
+    None,
+    Token(String),
+    Basic { user: String, password: String },
+}
+
+impl Auth {
+    pub fn new(token: Option<String>, basic: Option<(String, String)>) -> Self {
+        match (token, basic) {
+            (Some(token), _) => Auth::Token(token),
+            (None, Some((user, password))) => Auth::Basic { user, password },
+            (None, None) => Auth::None
+        }
+    }
+
+    pub fn allows(&self, request: &Request) -> bool {
+        match self {
+            Auth::None => true,
+            Auth::Token(token) => request.header("Authorization")
+                .and_then(|h| h.strip_prefix("Bearer "))
+                .is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes())),
+            Auth::Basic { user, password } => rouille::input::basic_http_auth(request)
+                .is_some_and(|c| constant_time_eq(c.login.as_bytes(), user.as_bytes())
+                    & constant_time_eq(c.password.as_bytes(), password.as_bytes()))
+        }
+    }
+
+    pub fn reject(&self) -> Response {
+        match self {
+            Auth::Basic { .. } => Response::basic_http_auth_login_required("whoisthere"),
+            _ => Response::text("Unauthorized")
+                .with_status_code(401)
+                .with_unique_header("WWW-Authenticate", "Bearer")
+        }
+    }
+}
+
+// Don't leak how much of the secret matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn parse_basic_auth(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((user, password)) => Ok((user.to_string(), password.to_string())),
+        None => Err("Expected user:pass".to_string())
+    }
+}
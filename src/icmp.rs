@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use pnet::packet::ip::IpNextHeaderProtocols;
+use serde::{Serialize, Serializer};
+
+use crate::packet::PacketInfo;
+
+const ICMP_TYPES: [(u8, &str); 14] = [
+    (0, "echo-reply"), (3, "destination-unreachable"), (4, "source-quench"), (5, "redirect"),
+    (8, "echo-request"), (9, "router-advertisement"), (10, "router-solicitation"), (11, "time-exceeded"),
+    (12, "parameter-problem"), (13, "timestamp"), (14, "timestamp-reply"), (40, "photuris"),
+    (42, "extended-echo-request"), (43, "extended-echo-reply"),
+];
+
+/// (type, code, name)
+const ICMP_CODES: [(u8, u8, &str); 18] = [
+    (3, 0, "net-unreachable"), (3, 1, "host-unreachable"), (3, 2, "protocol-unreachable"),
+    (3, 3, "port-unreachable"), (3, 4, "fragmentation-needed"), (3, 5, "source-route-failed"),
+    (3, 6, "net-unknown"), (3, 7, "host-unknown"), (3, 9, "net-prohibited"), (3, 10, "host-prohibited"),
+    (3, 13, "communication-prohibited"), (5, 0, "net"), (5, 1, "host"), (5, 2, "tos-net"), (5, 3, "tos-host"),
+    (11, 0, "ttl-exceeded"), (11, 1, "reassembly-time-exceeded"), (12, 1, "missing-option"),
+];
+
+const ICMPV6_TYPES: [(u8, &str); 16] = [
+    (1, "destination-unreachable"), (2, "packet-too-big"), (3, "time-exceeded"), (4, "parameter-problem"),
+    (128, "echo-request"), (129, "echo-reply"), (130, "mld-query"), (131, "mld-report"), (132, "mld-done"),
+    (133, "router-solicitation"), (134, "router-advertisement"), (135, "neighbor-solicitation"),
+    (136, "neighbor-advertisement"), (137, "redirect"), (143, "mldv2-report"), (160, "extended-echo-request"),
+];
+
+const ICMPV6_CODES: [(u8, u8, &str); 12] = [
+    (1, 0, "no-route"), (1, 1, "admin-prohibited"), (1, 2, "beyond-scope"), (1, 3, "address-unreachable"),
+    (1, 4, "port-unreachable"), (1, 5, "source-policy-failed"), (1, 6, "reject-route"),
+    (3, 0, "hop-limit-exceeded"), (3, 1, "reassembly-time-exceeded"),
+    (4, 0, "erroneous-header"), (4, 1, "unrecognized-next-header"), (4, 2, "unrecognized-option"),
+];
+
+#[derive(Serialize)]
+pub struct IcmpValue {
+    #[serde(rename = "type")]
+    pub icmp_type: u8,
+    pub code: u8,
+    pub packets: u128,
+    pub bytes: u128,
+}
+
+/// Keyed by (ICMPv6, type, code), serialized keyed by a readable name, e.g.
+/// "icmp destination-unreachable port-unreachable", falling back to numbers for what has none
+#[derive(Default)]
+pub struct IcmpStats(pub HashMap<(bool, u8, u8), IcmpValue>);
+
+// Named here rather than per packet
+impl Serialize for IcmpStats {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer {
+        serializer.collect_map(self.0.iter().map(|((v6, icmp_type, code), v)| (name(*v6, *icmp_type, *code), v)))
+    }
+}
+
+fn name(v6: bool, icmp_type: u8, code: u8) -> String {
+    let (protocol, types, codes) = if v6 {
+        ("icmpv6", &ICMPV6_TYPES[..], &ICMPV6_CODES[..])
+    } else {
+        ("icmp", &ICMP_TYPES[..], &ICMP_CODES[..])
+    };
+    let code_name = codes.iter().find(|(t, c, _)| *t == icmp_type && *c == code).map(|(_, _, n)| *n);
+    match (types.iter().find(|(t, _)| *t == icmp_type).map(|(_, n)| *n), code_name) {
+        (Some(t), Some(c)) => format!("{} {} {}", protocol, t, c),
+        // For most types 0 is the only code
+        (Some(t), None) if code == 0 => format!("{} {}", protocol, t),
+        (Some(t), None) => format!("{} {} code {}", protocol, t, code),
+        (None, _) => format!("{} type {} code {}", protocol, icmp_type, code)
+    }
+}
+
+/// Count ICMP and ICMPv6 messages by type and code
+pub fn observe(info: &PacketInfo, stats: &Mutex<IcmpStats>) {
+    let icmp = match &info.icmp {
+        Some(icmp) => icmp,
+        None => return
+    };
+    let v6 = info.protocol == IpNextHeaderProtocols::Icmpv6;
+    let mut stats = stats.lock().unwrap();
+    let entry = stats.0.entry((v6, icmp.icmp_type, icmp.code))
+        .or_insert(IcmpValue { icmp_type: icmp.icmp_type, code: icmp.code, packets: 0, bytes: 0 });
+    entry.packets += 1;
+    entry.bytes += info.length;
+}
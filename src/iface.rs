@@ -0,0 +1,86 @@
+use pnet::datalink::{self, NetworkInterface};
+use pnet::util::MacAddr;
+use serde::Serialize;
+
+/// By `#<index>`, by MAC address, or by name. On Windows where names are `\Device\NPF_{GUID}`,
+/// by the adapter's description too. Err naming the candidates when more than one fits.
+pub fn find(spec: &str) -> Result<NetworkInterface, String> {
+    let interfaces = datalink::interfaces();
+    let matches: Vec<_> = if let Some(index) = spec.strip_prefix('#') {
+        let index: u32 = index.parse().map_err(|_| format!("Invalid interface index {}", spec))?;
+        interfaces.into_iter().filter(|iface| iface.index == index).collect()
+    } else if let Ok(mac) = spec.parse::<MacAddr>() {
+        // Bonds, bridges and VLANs share their port's
+        interfaces.into_iter().filter(|iface| iface.mac == Some(mac)).collect()
+    } else if let Some(iface) = interfaces.iter().find(|iface| iface.name == spec) {
+        vec![iface.clone()]
+    } else {
+        interfaces.into_iter().filter(|iface| !iface.description.is_empty() && iface.description.eq_ignore_ascii_case(spec)).collect()
+    };
+    match &matches[..] {
+        [] => Err(format!("No interface {}, see list-interfaces", spec)),
+        [iface] => Ok(iface.clone()),
+        _ => Err(format!("Interface {} is ambiguous, it could be any of {}", spec,
+                         matches.iter().map(|i| format!("{} (#{})", i.name, i.index)).collect::<Vec<_>>().join(", ")))
+    }
+}
+
+/// What `--interface` accepts, with enough around it to tell adapters apart
+pub fn list() {
+    for iface in datalink::interfaces() {
+        let state = if iface.is_up() { "up" } else { "down" };
+        print!("#{} {} ({})", iface.index, iface.name, state);
+        if !iface.description.is_empty() {
+            print!(" \"{}\"", iface.description);
+        }
+        if let Some(mac) = iface.mac {
+            print!(" {}", mac);
+        }
+        println!();
+        for ip in &iface.ips {
+            println!("    {}", ip);
+        }
+    }
+}
+
+/// What the interface reports about itself, null where it can't tell
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Link {
+    pub speed_bps: Option<u64>,
+    pub mtu: Option<u32>,
+    #[serde(skip)]
+    pub interface: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+fn sysfs(name: &str, attr: &str) -> Option<u64> {
+    // Virtual and downed links give -1 or EINVAL
+    std::fs::read_to_string(format!("/sys/class/net/{}/{}", name, attr)).ok()?.trim().parse().ok()
+}
+
+#[cfg(target_os = "linux")]
+pub fn link(name: &str) -> Link {
+    Link {
+        // Mb/s
+        speed_bps: sysfs(name, "speed").filter(|s| *s > 0).map(|s| s * 1_000_000),
+        mtu: sysfs(name, "mtu").map(|m| m as u32),
+        interface: Some(name.to_string()),
+    }
+}
+
+/// Received packets the interface dropped, the driver's share and the NIC's missed ones
+#[cfg(target_os = "linux")]
+pub fn dropped(link: &Link) -> Option<u64> {
+    let name = link.interface.as_deref()?;
+    Some(sysfs(name, "statistics/rx_dropped")? + sysfs(name, "statistics/rx_missed_errors").unwrap_or(0))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn dropped(_link: &Link) -> Option<u64> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn link(_name: &str) -> Link {
+    Link::default()
+}
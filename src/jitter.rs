@@ -0,0 +1,45 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How far each new gap moves the jitter, RFC 3550's 1/16
+const GAIN: f64 = 1.0 / 16.0;
+
+pub fn unix_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or(0)
+}
+
+/// --track-jitter: gaps between a flow's packets, without RTP's timestamps to go by.
+/// The jitter is RFC 3550's running average of how much each gap differs from the last.
+#[derive(Default, Clone)]
+pub struct Jitter {
+    /// Unix microseconds of the last packet
+    last: Option<u64>,
+    gap: Option<u64>,
+    gaps: u64,
+    gap_total: u64,
+    /// Microseconds
+    jitter: f64,
+}
+
+impl Jitter {
+    pub fn record(&mut self, at: u64) {
+        if let Some(last) = self.last {
+            let gap = at.saturating_sub(last);
+            if let Some(previous) = self.gap {
+                self.jitter += (gap.abs_diff(previous) as f64 - self.jitter) * GAIN;
+            }
+            self.gap = Some(gap);
+            self.gaps += 1;
+            self.gap_total += gap;
+        }
+        self.last = Some(at);
+    }
+
+    /// Mean time between packets, 0 before the second one
+    pub fn inter_arrival_ms(&self) -> f64 {
+        if self.gaps == 0 { 0.0 } else { self.gap_total as f64 / self.gaps as f64 / 1000.0 }
+    }
+
+    pub fn jitter_ms(&self) -> f64 {
+        self.jitter / 1000.0
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use kafka::producer::{Producer, Record, RequiredAcks};
+use log::{info, Level};
+use serde::Serialize;
+
+use crate::data::{StatsKey, StatsValue};
+use crate::logging::WarnOnce;
+use crate::state::State;
+
+/// Records per produce request
+const MAX_BATCH: usize = 1000;
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct Event<'a> {
+    /// created the first time a flow goes out, updated after
+    event: &'static str,
+    flow: &'a StatsKey,
+    #[serde(flatten)]
+    value: &'a StatsValue,
+}
+
+/// Publishes flows touched since the last round to a topic, keyed by flow
+pub struct KafkaSink {
+    brokers: Vec<String>,
+    topic: String,
+    producer: Option<Producer>,
+    /// Flows already announced as created
+    seen: HashSet<StatsKey>,
+    sent_sequence: u64,
+    failing: WarnOnce,
+}
+
+impl KafkaSink {
+    pub fn new(brokers: Vec<String>, topic: String) -> Self {
+        KafkaSink { brokers, topic, producer: None, seen: HashSet::new(), sent_sequence: 0, failing: WarnOnce::new(Level::Warn, module_path!()) }
+    }
+
+    fn connect(&mut self) -> Result<&mut Producer, kafka::Error> {
+        if self.producer.is_none() {
+            let producer = Producer::from_hosts(self.brokers.clone())
+                .with_ack_timeout(ACK_TIMEOUT)
+                .with_required_acks(RequiredAcks::One)
+                .create()?;
+            info!("Connected to Kafka at {}", self.brokers.join(","));
+            self.producer = Some(producer);
+        }
+        Ok(self.producer.as_mut().unwrap())
+    }
+
+    /// Sends what changed in the published flows. A broker that can't keep up
+    /// only holds up this thread, what it missed goes out on the next round.
+    pub fn flush(&mut self, state: &State) -> Result<(), kafka::Error> {
+        // Not the newest sequence sent: a flow updated earlier but left out of the
+        // snapshot could still be below that, and would never go out
+        let (stats, sequence) = state.published_at();
+        let changed: Vec<_> = stats.0.iter().filter(|(_, v)| v.sequence > self.sent_sequence).collect();
+        // Flows gone with a reset come back as created
+        self.seen.retain(|k| stats.0.contains_key(k));
+
+        let mut records = Vec::with_capacity(changed.len());
+        for (k, v) in &changed {
+            let event = Event { event: if self.seen.contains(k) { "updated" } else { "created" }, flow: k, value: v };
+            let value = serde_json::to_string(&event).unwrap_or_else(|e| panic!("Fail to serialize flow: {}", e));
+            records.push((k.to_string(), value));
+        }
+        let topic = self.topic.clone();
+        let producer = self.connect()?;
+        for chunk in records.chunks(MAX_BATCH) {
+            let chunk: Vec<_> = chunk.iter().map(|(k, v)| Record::from_key_value(&topic, k.as_str(), v.as_str())).collect();
+            producer.send_all(&chunk)?;
+        }
+        self.seen.extend(changed.iter().map(|(k, _)| **k));
+        self.sent_sequence = sequence;
+        Ok(())
+    }
+
+    /// Called every --kafka-interval. Warns once per outage, reconnecting each round until it ends
+    pub fn round(&mut self, state: &State) {
+        let flushed = self.flush(state);
+        let _ = self.failing.report(flushed, "publish to Kafka, retrying every round");
+        if self.failing.failing() {
+            self.producer = None;
+        }
+    }
+}
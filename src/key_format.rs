@@ -0,0 +1,198 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use either::Either;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::data::{parse_protocol, protocol_name, Ipv4StatsKey, Ipv6StatsKey, StatsKey, TransportKey};
+
+/// How flow keys are written out, reading takes any of them
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyFormat {
+    /// "a -> b", "a:port -> b:port/protocol"
+    Arrow,
+    /// "a,b", "a,port,b,port,protocol"
+    Tuple,
+    /// {"src": a, "dst": b, ...}, maps keyed by flow become arrays of entries
+    Json,
+}
+
+impl FromStr for KeyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arrow" => Ok(KeyFormat::Arrow),
+            "tuple" => Ok(KeyFormat::Tuple),
+            "json" => Ok(KeyFormat::Json),
+            _ => Err(format!("Unknown key format: {}", s))
+        }
+    }
+}
+
+// Process wide, serde gives Serialize impls no way to be told
+static KEY_FORMAT: AtomicU8 = AtomicU8::new(KeyFormat::Arrow as u8);
+
+pub fn set(format: KeyFormat) {
+    KEY_FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+pub fn get() -> KeyFormat {
+    match KEY_FORMAT.load(Ordering::Relaxed) {
+        1 => KeyFormat::Tuple,
+        2 => KeyFormat::Json,
+        _ => KeyFormat::Arrow
+    }
+}
+
+/// The json key format
+#[derive(Serialize, Deserialize)]
+pub struct KeyObject {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proto: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub src_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dst_port: Option<u16>,
+}
+
+fn key(source: IpAddr, dest: IpAddr, transport: Option<TransportKey>) -> Result<StatsKey, String> {
+    match (source, dest) {
+        (IpAddr::V4(source), IpAddr::V4(dest)) => Ok(StatsKey(Either::Left(Ipv4StatsKey { source, dest }), transport)),
+        (IpAddr::V6(source), IpAddr::V6(dest)) => Ok(StatsKey(Either::Right(Ipv6StatsKey { source, dest }), transport)),
+        _ => Err("Invalid StatsKey: mixed IPv4 and IPv6".to_string())
+    }
+}
+
+impl KeyObject {
+    pub fn of(k: &StatsKey) -> Self {
+        let (src, dst) = k.addrs();
+        KeyObject {
+            src,
+            dst,
+            proto: k.1.map(|t| protocol_name(t.protocol)),
+            src_port: k.1.map(|t| t.source_port),
+            dst_port: k.1.map(|t| t.dest_port),
+        }
+    }
+
+    pub fn key(&self) -> Result<StatsKey, String> {
+        let transport = match &self.proto {
+            Some(p) => Some(TransportKey {
+                protocol: parse_protocol(p).ok_or("Invalid StatsKey protocol")?,
+                source_port: self.src_port.unwrap_or(0),
+                dest_port: self.dst_port.unwrap_or(0),
+            }),
+            None => None
+        };
+        key(self.src, self.dst, transport)
+    }
+}
+
+pub fn tuple(k: &StatsKey) -> String {
+    let (source, dest) = k.addrs();
+    match k.1 {
+        None => format!("{},{}", source, dest),
+        Some(t) => format!("{},{},{},{},{}", source, t.source_port, dest, t.dest_port, protocol_name(t.protocol))
+    }
+}
+
+pub fn parse_tuple(s: &str) -> Result<StatsKey, String> {
+    let ip = |s: &str| s.parse::<IpAddr>().map_err(|e| e.to_string());
+    let port = |s: &str| s.parse::<u16>().map_err(|e| e.to_string());
+    match *s.split(',').collect::<Vec<_>>() {
+        [source, dest] => key(ip(source)?, ip(dest)?, None),
+        [source, source_port, dest, dest_port, protocol] => {
+            let protocol = parse_protocol(protocol).ok_or("Invalid StatsKey protocol")?;
+            let transport = TransportKey { protocol, source_port: port(source_port)?, dest_port: port(dest_port)? };
+            key(ip(source)?, ip(dest)?, Some(transport))
+        }
+        _ => Err("Invalid StatsKey format".to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct Entry<K, V> {
+    flow: K,
+    #[serde(flatten)]
+    value: V,
+}
+
+/// A map keyed by flow, or with the json key format, which can't have objects as keys,
+/// an array of the values each with its key as `flow`. Values must serialize as objects.
+pub fn serialize_flows<S, K, V>(flows: impl IntoIterator<Item = (K, V)>, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer, K: Serialize, V: Serialize {
+    match get() {
+        KeyFormat::Json => serializer.collect_seq(flows.into_iter().map(|(flow, value)| Entry { flow, value })),
+        _ => serializer.collect_map(flows)
+    }
+}
+
+/// For `#[serde(serialize_with)]` on maps keyed by flow
+pub fn flow_map<S, K, V>(map: &std::collections::HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer, K: Serialize, V: Serialize {
+    serialize_flows(map, serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<StatsKey> {
+        let v4 = |s: &str, d: &str| Either::Left(Ipv4StatsKey { source: s.parse().unwrap(), dest: d.parse().unwrap() });
+        let v6 = |s: &str, d: &str| Either::Right(Ipv6StatsKey { source: s.parse().unwrap(), dest: d.parse().unwrap() });
+        let transport = |protocol, source_port, dest_port| Some(TransportKey { protocol, source_port, dest_port });
+        vec![
+            StatsKey(v4("10.0.0.1", "10.0.0.2"), None),
+            StatsKey(v6("fd00::1", "fd00::2"), None),
+            StatsKey(v4("10.0.0.1", "10.0.0.2"), transport(6, 40000, 443)),
+            StatsKey(v4("0.0.0.0", "255.255.255.255"), transport(17, 68, 67)),
+            StatsKey(v6("fe80::1", "ff02::1"), transport(58, 0, 0)),
+            // No name for it, written as the number
+            StatsKey(v4("10.0.0.1", "10.0.0.2"), transport(253, 1, 65535)),
+        ]
+    }
+
+    #[test]
+    fn reads_back_tuples() {
+        for k in keys() {
+            let s = tuple(&k);
+            assert!(parse_tuple(&s) == Ok(k), "{}", s);
+        }
+        assert_eq!(tuple(&keys()[2]), "10.0.0.1,40000,10.0.0.2,443,tcp");
+        assert_eq!(tuple(&keys()[5]), "10.0.0.1,1,10.0.0.2,65535,253");
+    }
+
+    #[test]
+    fn reads_back_arrows_and_objects() {
+        for k in keys() {
+            let s = k.to_string();
+            assert!(s.parse::<StatsKey>() == Ok(k), "{}", s);
+            assert!(KeyObject::of(&k).key() == Ok(k), "{}", s);
+        }
+    }
+
+    #[test]
+    fn refuses_bad_tuples() {
+        let cases = [
+            ("", "Invalid StatsKey format"),
+            ("10.0.0.1", "Invalid StatsKey format"),
+            ("10.0.0.1,40000,10.0.0.2,443", "Invalid StatsKey format"),
+            ("10.0.0.1,40000,10.0.0.2,443,tcp,x", "Invalid StatsKey format"),
+            ("10.0.0.1,fd00::2", "Invalid StatsKey: mixed IPv4 and IPv6"),
+            ("10.0.0.1,40000,fd00::2,443,tcp", "Invalid StatsKey: mixed IPv4 and IPv6"),
+            ("10.0.0.1:1,10.0.0.2", "invalid IP address syntax"),
+            ("10.0.0.1,40000,10.0.0.2,65536,tcp", "number too large to fit in target type"),
+            ("10.0.0.1,-1,10.0.0.2,443,tcp", "invalid digit found in string"),
+            ("10.0.0.1,40000,10.0.0.2,443,bogus", "Invalid StatsKey protocol"),
+            ("10.0.0.1,40000,10.0.0.2,443,256", "Invalid StatsKey protocol"),
+        ];
+        for (s, error) in cases {
+            assert_eq!(parse_tuple(s).err().as_deref(), Some(error), "{}", s);
+        }
+    }
+}
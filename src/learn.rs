@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::StatsKey;
+
+/// --learn-duration: new host and rate alerts are held back until it is over,
+/// the hosts and rates seen meanwhile become what's normal
+pub struct Warmup {
+    until: Instant,
+    // `ended` said so already
+    over: AtomicBool,
+}
+
+impl Default for Warmup {
+    fn default() -> Self {
+        Warmup::new(Duration::ZERO)
+    }
+}
+
+impl Warmup {
+    pub fn new(duration: Duration) -> Self {
+        Warmup { until: Instant::now() + duration, over: AtomicBool::new(duration.is_zero()) }
+    }
+
+    pub fn learning(&self) -> bool {
+        !self.over.load(Ordering::Relaxed) && Instant::now() < self.until
+    }
+
+    /// True the one time it's called once learning is over
+    pub fn ended(&self) -> bool {
+        !self.learning() && !self.over.swap(true, Ordering::Relaxed)
+    }
+}
+
+/// A flow over --alert-bps while learning, at its fastest
+#[derive(Serialize, Deserialize)]
+pub struct LearnedRate {
+    pub flow: StatsKey,
+    pub bps: f64,
+}
+
+/// What --learn-duration learned besides the seen hosts, kept next to the database.
+/// Finding it there on startup means there is nothing left to learn.
+#[derive(Serialize, Deserialize)]
+pub struct Baseline {
+    /// Unix seconds
+    pub learned_at: u64,
+    pub flows: Vec<LearnedRate>,
+}
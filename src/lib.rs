@@ -0,0 +1,65 @@
+pub mod age;
+pub mod alert;
+pub mod api;
+pub mod app;
+pub mod app_protocol;
+pub mod bogon;
+pub mod cidr;
+pub mod config;
+pub mod conn_log;
+pub mod connection;
+pub mod convert;
+pub mod conversation;
+pub mod daemon;
+pub mod data;
+pub mod db_format;
+pub mod debug;
+pub mod diff;
+pub mod fanout;
+pub mod filter;
+pub mod grafana;
+pub mod history;
+pub mod http;
+pub mod icmp;
+pub mod iface;
+pub mod jitter;
+pub mod kafka;
+pub mod key_format;
+pub mod learn;
+pub mod logging;
+pub mod matrix;
+pub mod metrics;
+pub mod mqtt;
+pub mod neighbor;
+pub mod ntop;
+pub mod packet;
+pub mod pcap;
+pub mod privileges;
+pub mod protocols;
+pub mod query;
+pub mod rate_alert;
+mod reader;
+pub mod replay;
+pub mod retrans;
+pub mod ring;
+pub mod rollup;
+pub mod runtime;
+pub mod scan;
+pub mod schema;
+pub mod seen;
+pub mod services;
+pub mod shard;
+pub mod sketch;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod state;
+pub mod statsd;
+pub mod summary;
+pub mod syn_flood;
+pub mod table;
+pub mod term;
+pub mod top_hosts;
+pub mod tui;
+pub mod update;
+pub mod window;
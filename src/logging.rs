@@ -0,0 +1,119 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::str::FromStr;
+
+use log::kv::{Error, Key, Value, VisitSource, VisitValue};
+use log::{log, Level};
+use serde::Serialize;
+use serde_json::Map;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    /// One object per line: timestamp, level, target, message and the record's fields
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format: {}", s))
+        }
+    }
+}
+
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let mut json = Json(serde_json::Value::Null);
+        value.visit(&mut json)?;
+        self.0.insert(key.to_string(), json.0);
+        Ok(())
+    }
+}
+
+/// Numbers, bools and None keep their JSON type, anything else is its Display
+struct Json(serde_json::Value);
+
+impl<'v> VisitValue<'v> for Json {
+    fn visit_any(&mut self, value: Value) -> Result<(), Error> {
+        self.0 = value.to_string().into();
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), Error> {
+        self.0 = serde_json::Value::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+}
+
+/// Log to stderr at info unless RUST_LOG says otherwise
+pub fn init(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let mut line = Map::new();
+            line.insert("timestamp".to_string(), buf.timestamp().to_string().into());
+            line.insert("level".to_string(), record.level().as_str().into());
+            line.insert("target".to_string(), record.target().into());
+            line.insert("message".to_string(), record.args().to_string().into());
+            // A field that fails to render shouldn't lose the line
+            let _ = record.key_values().visit(&mut Fields(&mut line));
+            writeln!(buf, "{}", serde_json::Value::Object(line))
+        });
+    }
+    builder.init();
+}
+
+/// Logs the first failure of a run of them, leaving the rest to be retried quietly
+pub struct WarnOnce {
+    level: Level,
+    /// Whose log it is, `module_path!()` of the caller
+    target: &'static str,
+    failing: bool,
+}
+
+impl WarnOnce {
+    pub const fn new(level: Level, target: &'static str) -> Self {
+        WarnOnce { level, target, failing: false }
+    }
+
+    /// Since the last Ok, if any
+    pub fn failing(&self) -> bool {
+        self.failing
+    }
+
+    /// Logs "Fail to `what`: error" unless it already did since the last Ok, then hands `r` back
+    pub fn report<T, E: Display>(&mut self, r: Result<T, E>, what: &str) -> Result<T, E> {
+        match &r {
+            Err(e) if !self.failing => {
+                log!(target: self.target, self.level, "Fail to {}: {}", what, e);
+                self.failing = true;
+            }
+            Err(_) => (),
+            Ok(_) => self.failing = false
+        }
+        r
+    }
+}
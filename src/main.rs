@@ -1,60 +1,857 @@
-mod data;
+extern crate pnet;
+
+use std::{env, panic, process, thread};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn, Level};
+use serde::Serialize;
+use structopt::StructOpt;
+use structopt::clap::{AppSettings, Error, ErrorKind};
+
+use pnet::datalink::{self, DataLinkReceiver, NetworkInterface};
+use pnet::datalink::Channel::Ethernet;
+use pnet::packet::ethernet::EtherType;
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+
+use tokio::{task, time};
+use tokio::time::MissedTickBehavior;
+
+use whoisthere::{config, convert, daemon, diff, http, icmp, iface, jitter, neighbor, runtime, summary, term, tui};
+use whoisthere::alert::Alerter;
+use whoisthere::app::NameTracker;
+use whoisthere::app_protocol::{AppFilter, AppProtocol};
+use whoisthere::bogon::BogonWatcher;
+use whoisthere::config::{parse_duration, parse_interval, Granularity, InterfaceLoss, Live, QueueFull};
+use whoisthere::conn_log::ConnectionLog;
+use whoisthere::connection::ConnectionFilter;
+use whoisthere::logging::{self, LogFormat, WarnOnce};
+use whoisthere::data::{unix_now, Stats, StatsUpdate};
+use whoisthere::db_format::{self, DbFormat};
+use whoisthere::fanout::Fanout;
+use whoisthere::filter::Filter;
+use whoisthere::history::{self, History, Sample};
+use whoisthere::kafka::KafkaSink;
+use whoisthere::key_format::{self, KeyFormat};
+use whoisthere::learn::{Baseline, Warmup};
+use whoisthere::mqtt::MqttSink;
+use whoisthere::packet::{parse_ethertype, proc_packet, FCS_LEN};
+use whoisthere::pcap::PcapReader;
+use whoisthere::privileges::{self, Credentials};
+use whoisthere::rate_alert::RateAlerter;
+use whoisthere::ring::PacketRing;
+use whoisthere::rollup::Rollups;
+use whoisthere::scan::ScanDetector;
+use whoisthere::seen::SeenHosts;
+use whoisthere::sketch::Sketch;
+use whoisthere::snapshot::Snapshots;
+use whoisthere::state::State;
+use whoisthere::statsd::StatsdSink;
+use whoisthere::term::Units;
+use whoisthere::syn_flood::SynFloodDetector;
+use whoisthere::update::Updater;
+
+#[derive(StructOpt, Debug, Serialize)]
+#[structopt(name = "whoisthere", setting = AppSettings::AllArgsOverrideSelf)]
+struct WitOpt {
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+
+    #[structopt(
+        short,
+        long,
+        help = "JSON file of long options, e.g. {\"interface\": \"eth0\", \"stdout-top\": 5}. \
+                Command line options win; SIGHUP re-reads it",
+        parse(from_os_str),
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        short,
+        long,
+        help = "Network interface whoisthere is sniffing from, required unless running a subcommand or reading --pcap. \
+                Also #<index> or a MAC address, see list-interfaces; on Windows the adapter description works too, \
+                capturing there needs Npcap installed in WinPcap API-compatible mode",
+    )]
+    interface: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Read frames from a pcap or pcapng file instead, gzipped or not, and exit once it's all counted. \
+                Ethernet, raw IP, Linux cooked (tcpdump -i any) and BSD loopback captures are read, each pcapng \
+                interface by its own link type. - reads standard input, e.g. from tcpdump -U -w -. One cut off in its last record, copied while \
+                still being written say, counts the frames before it",
+        parse(from_os_str),
+        conflicts_with = "interface",
+    )]
+    pcap: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Read at most that many bytes of each frame off the interface. Longer frames count as truncated \
+                and only their captured bytes go into frame_bytes and casts, flows use the IP length. \
+                Without it, flows of truncated frames only count the bytes captured.",
+        conflicts_with = "pcap",
+    )]
+    snaplen: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Frames come with their 4 bytes Ethernet FCS, cut it off so frame and wire bytes match interface \
+                counters. Without it they're assumed not to, as with most captures. Frames cut by --snaplen have none",
+    )]
+    fcs_included: bool,
+
+    #[structopt(
+        long,
+        help = "Headers only, --snaplen defaults to 128. SNI and HTTP Host tracking see no payload and find nothing.",
+        conflicts_with = "pcap",
+    )]
+    stats_only: bool,
+
+    #[structopt(
+        short,
+        long,
+        help = "Statistics http server bind address & port, or unix:<path> for a Unix domain socket. \
+                Repeat it to serve on each.",
+        default_value = "127.0.0.1:3648",
+        number_of_values = 1,
+    )]
+    bind: Vec<String>,
+
+    #[structopt(long, help = "Don't run the HTTP server at all, capture and write the database only")]
+    no_http: bool,
+
+    #[structopt(
+        long,
+        help = "Exit when a --bind address can't be bound. Without it capture goes on, retrying the bind now and then",
+        conflicts_with = "no-http",
+    )]
+    require_http: bool,
+
+    #[structopt(
+        long,
+        help = "Stop after capturing that long, e.g. 10s or 5m, saving the database like on SIGTERM",
+        parse(try_from_str = parse_duration),
+    )]
+    duration: Option<Duration>,
+
+    #[structopt(
+        long,
+        help = "One-shot measurement: no HTTP server, and all flows printed to stdout at the end of --duration, \
+                --pcap or on SIGINT",
+        conflicts_with = "tui",
+    )]
+    once: bool,
+
+    #[structopt(
+        long,
+        help = "File permissions of the Unix domain socket, in octal",
+        default_value = "660",
+        parse(try_from_str = parse_mode),
+    )]
+    socket_mode: u32,
+
+    #[structopt(long, help = "Require `Authorization: Bearer <token>` on HTTP requests", conflicts_with = "basic-auth")]
+    auth_token: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Require HTTP basic auth on HTTP requests, as user:pass",
+        parse(try_from_str = http::parse_basic_auth),
+    )]
+    basic_auth: Option<(String, String)>,
+
+    #[structopt(long, help = "PEM certificate to serve HTTPS with", parse(from_os_str), requires = "tls-key")]
+    tls_cert: Option<PathBuf>,
+
+    #[structopt(long, help = "PEM private key of --tls-cert", parse(from_os_str), requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+
+    #[structopt(
+        short,
+        long,
+        help = "Database file path, default to in-memory database",
+        parse(from_os_str),
+    )]
+    db: Option<PathBuf>,
+
+    #[structopt(
+        long = "format",
+        help = "Database format, bincode is smaller and faster to load and save. Defaults to bincode for .bin and .bincode files, JSON otherwise",
+        possible_values = &["json", "bincode"],
+    )]
+    db_format: Option<DbFormat>,
+
+    #[structopt(
+        long,
+        help = "How often --db is saved, once more on the way out",
+        default_value = "10",
+        parse(try_from_str = parse_interval),
+    )]
+    db_interval: Duration,
+
+    #[structopt(
+        long,
+        help = "Force every database save to disk. Survives power loss, \
+                but each save then waits on the disk instead of the OS page cache",
+    )]
+    fsync: bool,
+
+    #[structopt(
+        long,
+        help = "Count on from what the database holds, also with --pcap. The default",
+    )]
+    merge: bool,
+
+    #[structopt(
+        long,
+        help = "Start from no flows and overwrite the database at the first save",
+        conflicts_with = "merge",
+    )]
+    fresh: bool,
+
+    #[structopt(long, help = "Periodically write the flows to stats-<unix time>.json, or .json.gz with --snapshot-gzip, in this directory", parse(from_os_str))]
+    snapshot_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "How often to write --snapshot-dir snapshots, e.g. 10m or 1h",
+        default_value = "1h",
+        parse(try_from_str = parse_interval),
+    )]
+    snapshot_interval: Duration,
+
+    #[structopt(long, help = "Only keep the newest N snapshots")]
+    snapshot_keep: Option<usize>,
+
+    #[structopt(long, help = "Only keep the newest snapshots that fit in that many bytes together, the newest one always")]
+    snapshot_max_bytes: Option<u64>,
+
+    #[structopt(long, help = "Gzip snapshots, to stats-<unix time>.json.gz. diff and --db read them either way")]
+    snapshot_gzip: bool,
+
+    #[structopt(long, help = "Zero the flows after each snapshot, so every snapshot covers one interval")]
+    snapshot_reset: bool,
+
+    #[structopt(
+        long,
+        help = "Zero the flows every interval, logging a summary of it and saving it to --snapshot-dir if given",
+        parse(try_from_str = parse_interval),
+    )]
+    reset_interval: Option<Duration>,
+
+    #[structopt(
+        long,
+        help = "Once there are more than N flows, write them all to a --snapshot-dir snapshot and start over from none",
+        requires = "snapshot-dir",
+    )]
+    rollover_at: Option<usize>,
+
+    #[structopt(long, help = "Updates buffered between capture and aggregation", default_value = "65536")]
+    queue_size: usize,
+
+    #[structopt(
+        long,
+        help = "When the queue is full, drop updates to keep capturing or block capture until there is room",
+        default_value = "drop",
+        possible_values = &["drop", "block"],
+    )]
+    queue_full: QueueFull,
+
+    #[structopt(
+        long,
+        help = "Tell flows apart by host pair, or by host pair, IP protocol and ports",
+        default_value = "host",
+        possible_values = &["host", "port"],
+    )]
+    granularity: Granularity,
+
+    #[structopt(
+        long,
+        help = "Once --interface is gone, e.g. undocked or renamed, wait for one by that name to capture on again, \
+                or save and exit. One given as #index is waited for by the name it had. With --user reopening it \
+                takes the privileges given up",
+        default_value = "wait",
+        possible_values = &["wait", "exit"],
+    )]
+    on_interface_loss: InterfaceLoss,
+
+    #[structopt(
+        long,
+        help = "With --granularity port, zero the client's port so connections don't each make a flow: the higher \
+                port of the two, when it's at or above --ephemeral-port-min. Server ports stay.",
+    )]
+    ignore_ephemeral_ports: bool,
+
+    #[structopt(
+        long,
+        help = "Lowest port --ignore-ephemeral-ports takes for a client's. Linux hands out 32768 and up, \
+                Windows 49152 and up, raise it when servers listen above 1024",
+        default_value = "1024",
+    )]
+    ephemeral_port_min: u16,
+
+    #[structopt(
+        long,
+        help = "Only count packets matching a pcap-filter style expression, e.g. \"tcp and not port 22\"",
+        conflicts_with = "filter-file",
+    )]
+    filter: Option<String>,
+
+    #[structopt(long, help = "Read --filter from a file, lines starting with # are comments", parse(from_os_str))]
+    filter_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Only count frames from or to these MAC addresses, comma separated or repeated",
+        use_delimiter = true,
+    )]
+    mac: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "Only count frames tagged with this VLAN id, outer or inner. Repeat it for several, \
+                \"untagged\" lets untagged frames in too.",
+        number_of_values = 1,
+    )]
+    vlan: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "Only count flows of these application protocols, comma separated or repeated. Matches the well known \
+                ports (53 and 5353, 80 and 8080, 443, 22), and TCP connections on others once their payload looks the part.",
+        possible_values = &["dns", "http", "tls", "ssh"],
+        use_delimiter = true,
+    )]
+    app_protocol: Vec<AppProtocol>,
+
+    #[structopt(
+        long,
+        help = "Label flows with the application protocol their first payloads look like, whatever the ports: \
+                an HTTP request line, a TLS record, an SSH banner or, over UDP, a DNS header. /services goes by it.",
+    )]
+    guess_protocol: bool,
+
+    #[structopt(
+        long,
+        help = "Only count TCP connections opened while capturing, from their SYN on, and nothing else. \
+                Each flow's connections and /summary's cps count the SYNs either way.",
+    )]
+    connections_only: bool,
+
+    #[structopt(
+        long,
+        help = "Append a line of JSON to that file, or - for standard output, for each flow's first packet: its 5-tuple \
+                and when. The packets after it are left uncounted, until the flow goes quiet for 5 minutes",
+        parse(from_os_str),
+    )]
+    connection_log: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Only look into frames of these EtherTypes, e.g. ipv4,ipv6,arp or 0x88cc, the rest are counted as skipped. \
+                Tags and PPPoE are seen through first. Default is all.",
+        use_delimiter = true,
+        parse(try_from_str = parse_ethertype),
+    )]
+    #[serde(serialize_with = "ethertype_numbers")]
+    ethertypes: Vec<EtherType>,
+
+    #[structopt(
+        long,
+        help = "Refuse to start, or to reload, without at least one of --filter, --filter-file, --mac, --vlan, \
+                --app-protocol or --ethertypes, so nothing gets counted by accident",
+    )]
+    strict: bool,
+
+    #[structopt(
+        long,
+        help = "Most bytes of each packet the SNI, HTTP Host and DHCP parsers read. Too low misses server names \
+                late in big ClientHellos, browsers put them anywhere among the extensions.",
+        default_value = "512",
+    )]
+    deep_inspect_bytes: usize,
+
+    #[structopt(
+        long,
+        help = "Keep the last N frames in memory as captured, counted or not, for /recent.pcap. \
+                Only served with --auth-token or --basic-auth, it's what the packets carry",
+    )]
+    packet_ring: Option<usize>,
+
+    #[structopt(long, help = "Keep multicast and broadcast out of the flow map, they are still counted in /summary")]
+    unicast_only: bool,
+
+    #[structopt(
+        long,
+        help = "How often the flows served over HTTP are refreshed",
+        default_value = "1",
+        parse(try_from_str = parse_interval),
+    )]
+    publish_interval: Duration,
+
+    #[structopt(
+        long,
+        help = "Publish the flows after every batch of packets rather than every --publish-interval, so HTTP shows \
+                a packet within milliseconds. Each publish copies the whole map, which costs throughput on busy \
+                links; the capture side needs nothing, pnet hands frames over as they arrive everywhere.",
+    )]
+    low_latency: bool,
+
+    #[structopt(long, help = "Number of independently locked parts of the flow map", default_value = "16")]
+    shards: usize,
+
+    #[structopt(long, help = "Print the top flows to stdout every interval, e.g. 10s or 1m", parse(try_from_str = parse_interval))]
+    stdout_interval: Option<Duration>,
+
+    #[structopt(
+        long,
+        help = "Log the aggregate pps and bps since the last line and the number of flows every interval, e.g. 1m",
+        parse(try_from_str = parse_interval),
+    )]
+    rate_log_interval: Option<Duration>,
+
+    #[structopt(long, help = "Number of flows printed by --stdout-interval", default_value = "10")]
+    stdout_top: usize,
+
+    #[structopt(long, help = "Keep per-second samples of each flow for an exact recent rate_bps, costs memory per flow")]
+    track_rates: bool,
+
+    #[structopt(
+        long,
+        help = "Count flows in a Count-Min sketch of fixed size instead of the flow map, for links with too many flows \
+                to keep. Only the heavy hitters are known, at /top; /stats and the database stay empty. \
+                Counts are never under the truth and over it by at most 2.72 / --sketch-width of all the traffic, \
+                but for a chance of e^-(--sketch-depth).",
+    )]
+    sketch: bool,
+
+    #[structopt(
+        long,
+        help = "Counters per row of --sketch, each row takes 16 bytes per counter",
+        default_value = "65536",
+    )]
+    sketch_width: usize,
+
+    #[structopt(long, help = "Rows of --sketch, each with its own hash", default_value = "4")]
+    sketch_depth: usize,
+
+    #[structopt(long, help = "Heavy hitters --sketch keeps for /top", default_value = "100")]
+    sketch_top: usize,
+
+    #[structopt(
+        long,
+        help = "Keep the mean time between packets and the jitter of UDP flows, as inter_arrival_ms and jitter_ms. \
+                Costs some work per packet and memory per flow. --pcap goes by the file's timestamps",
+    )]
+    track_jitter: bool,
+
+    #[structopt(
+        long,
+        help = "Keep the latest and the smallest TCP window each flow's sender advertised, as window and min_window. \
+                Scaled by what the handshake agreed on, connections that started before capture go unscaled",
+    )]
+    track_windows: bool,
+
+    #[structopt(
+        long,
+        help = "How often each flow's bytes_per_sec is worked out from what it grew since the last time",
+        default_value = "2",
+        parse(try_from_str = parse_interval),
+    )]
+    rate_interval: Duration,
+
+    #[structopt(
+        long,
+        help = "Comma separated Kafka bootstrap brokers to publish flow events to, e.g. kafka1:9092,kafka2:9092",
+        use_delimiter = true,
+        requires = "kafka-topic",
+    )]
+    kafka_brokers: Vec<String>,
+
+    #[structopt(long, help = "Kafka topic for --kafka-brokers, records are JSON keyed by flow")]
+    kafka_topic: Option<String>,
+
+    #[structopt(
+        long,
+        help = "How often flows changed since the last round go to Kafka",
+        default_value = "1",
+        parse(try_from_str = parse_interval),
+    )]
+    kafka_interval: Duration,
+
+    #[structopt(long, help = "MQTT broker to publish summaries to, host[:port], port 1883 by default")]
+    mqtt_broker: Option<String>,
+
+    #[structopt(long, help = "Topic for --mqtt-broker", default_value = "whoisthere/summary")]
+    mqtt_topic: String,
+
+    #[structopt(
+        long,
+        help = "How often a summary goes to --mqtt-broker",
+        default_value = "10",
+        parse(try_from_str = parse_interval),
+    )]
+    mqtt_interval: Duration,
+
+    #[structopt(long, help = "Number of top flows in each MQTT summary", default_value = "10")]
+    mqtt_top: usize,
+
+    #[structopt(long, help = "Username for --mqtt-broker")]
+    mqtt_username: Option<String>,
+
+    #[structopt(long, help = "Password for --mqtt-username", requires = "mqtt-username")]
+    mqtt_password: Option<String>,
+
+    #[structopt(long, help = "StatsD server to send gauges of bytes, packets and flows to, host:port")]
+    statsd: Option<String>,
+
+    #[structopt(long, help = "Namespace of the StatsD metrics", default_value = "whoisthere")]
+    statsd_prefix: String,
+
+    #[structopt(
+        long,
+        help = "How often gauges go to --statsd",
+        default_value = "10",
+        parse(try_from_str = parse_interval),
+    )]
+    statsd_interval: Duration,
+
+    #[structopt(long, help = "Also send the bytes of that many top flows", default_value = "0")]
+    statsd_top: usize,
+
+    #[structopt(long, help = "Name --statsd-top flows with DogStatsD tags instead of in the metric name")]
+    statsd_tags: bool,
+
+    #[structopt(
+        long,
+        help = "Every that often, report what each flow did since the last report: \
+                one JSON object per line to --rollup-to",
+        parse(try_from_str = parse_interval),
+    )]
+    rollup_interval: Option<Duration>,
+
+    #[structopt(
+        long,
+        help = "Where --rollup-interval reports go: - for standard output, an http(s):// URL to POST them to, \
+                sqlite:<path> for a row per flow and report in tables by UTC day (built with --features sqlite), \
+                or a file to append them to",
+        default_value = "-",
+    )]
+    rollup_to: String,
+
+    #[structopt(long, help = "Alert on a source reaching more than that many destination ports within --scan-window")]
+    scan_ports: Option<usize>,
+
+    #[structopt(long, help = "Alert on a source reaching more than that many hosts within --scan-window")]
+    scan_hosts: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Window of --scan-ports and --scan-hosts",
+        default_value = "60",
+        parse(try_from_str = parse_duration),
+    )]
+    scan_window: Duration,
+
+    #[structopt(long, help = "Alert on a host getting, or a source sending, more than that many unanswered TCP SYNs within --syn-flood-window")]
+    syn_flood: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Window of --syn-flood",
+        default_value = "10",
+        parse(try_from_str = parse_duration),
+    )]
+    syn_flood_window: Duration,
+
+    #[structopt(long, help = "Alert on reserved addresses, e.g. documentation or 240.0.0.0/4, once an hour per address")]
+    flag_bogons: bool,
+
+    #[structopt(long, help = "Alert on flows going faster than that many bits per second for --alert-bps-samples rate intervals in a row")]
+    alert_bps: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Rate intervals in a row a flow has to be over --alert-bps, so a single spike doesn't alert",
+        default_value = "3",
+    )]
+    alert_bps_samples: u32,
+
+    #[structopt(long, help = "Alert on source addresses never seen before, remembering them next to --db")]
+    alert_new_hosts: bool,
+
+    #[structopt(long, help = "Start --alert-new-hosts over, every host is new again", requires = "alert-new-hosts")]
+    forget_hosts: bool,
+
+    #[structopt(
+        long,
+        help = "Hold --alert-new-hosts and --alert-bps alerts back that long at startup, taking the hosts and the \
+                flows' rates seen meanwhile as normal. What was learned is kept next to --db, later runs don't learn again",
+        parse(try_from_str = parse_duration),
+    )]
+    learn_duration: Option<Duration>,
+
+    #[structopt(long, help = "POST each alert as JSON to this URL")]
+    alert_webhook: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Sample the totals and top flows every so often, e.g. 10s, for --grafana and /rate-history",
+        parse(try_from_str = parse_interval),
+    )]
+    history_step: Option<Duration>,
+
+    #[structopt(long, help = "Samples of --history-step kept, the oldest are dropped", default_value = "8640")]
+    history_keep: usize,
+
+    #[structopt(long, help = "Serve a Grafana SimpleJSON datasource under /grafana, backed by --history-step", requires = "history-step")]
+    grafana: bool,
+
+    #[structopt(
+        long,
+        help = "Serve POST /debug/packet, which runs a hex or base64 Ethernet frame in the body through the parser \
+                and tells what it found, or why it got dropped",
+    )]
+    debug: bool,
+
+    #[structopt(long, help = "Count distinct destination ports and hosts of each source and list the protocols it used, served at /hosts")]
+    track_fanout: bool,
+
+    #[structopt(long, help = "Estimate --track-fanout counts with HyperLogLog, 1KiB per set however big", requires = "track-fanout")]
+    fanout_hll: bool,
+
+    #[structopt(long, help = "Full-screen live flow table, logs still go to stderr so redirect it away")]
+    tui: bool,
+
+    #[structopt(
+        long,
+        help = "Flow keys as \"a -> b\", as \"a,b\" tuples, or as JSON objects, which turns maps of flows into arrays",
+        default_value = "arrow",
+        possible_values = &["arrow", "tuple", "json"],
+    )]
+    key_format: KeyFormat,
+
+    #[structopt(
+        long,
+        help = "Byte and packet counts on standard output, in diff and the TUI as 1.50MiB and 2.30k. \
+                JSON, /text and the database keep every digit",
+    )]
+    human: bool,
+
+    #[structopt(long, help = "With --human, bytes in powers of 1000 as kB and MB rather than of 1024", requires = "human")]
+    si: bool,
+
+    #[structopt(
+        long,
+        help = "Plain text or one JSON object per line, RUST_LOG sets the level",
+        default_value = "text",
+        possible_values = &["text", "json"],
+    )]
+    log_format: LogFormat,
+
+    #[structopt(long, help = "Fork into the background", conflicts_with = "tui")]
+    daemon: bool,
+
+    #[structopt(
+        long,
+        help = "Check the interface or file, filters, database and other paths, print the resolved options and exit",
+    )]
+    check: bool,
+
+    #[structopt(long, help = "Write the process id there, removed on exit", parse(from_os_str))]
+    pidfile: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Switch to this user, a name or uid, once the interface or file is open. \
+                The database and snapshots must be writable by it, and HTTP ports below 1024 are out of reach",
+    )]
+    user: Option<String>,
+
+    #[structopt(long, help = "Switch to this group too, the user's primary group by default")]
+    group: Option<String>,
+}
+
+impl WitOpt {
+    fn http(&self) -> bool {
+        !self.no_http && !self.once
+    }
+
+    fn snapshots(&self) -> Option<Snapshots> {
+        self.snapshot_dir.clone().map(|dir| Snapshots {
+            dir,
+            gzip: self.snapshot_gzip,
+            keep: self.snapshot_keep,
+            max_bytes: self.snapshot_max_bytes,
+        })
+    }
+
+    fn live(&self) -> Result<Live, String> {
+        let filter = match (&self.filter, &self.filter_file) {
+            (Some(f), _) => Some(f.parse()?),
+            (None, Some(path)) => Some(Filter::from_file(path)?),
+            (None, None) => None
+        };
+        let macs = self.mac.iter()
+            .map(|m| m.parse().map_err(|_| format!("Invalid MAC address: {}", m)))
+            .collect::<Result<_, String>>()?;
+        let vlans = self.vlan.iter()
+            .map(|v| match v.as_str() {
+                "untagged" => Ok(None),
+                v => v.parse().ok().filter(|id| *id < 4096).map(Some).ok_or(format!("Invalid VLAN id: {}", v))
+            })
+            .collect::<Result<_, String>>()?;
+        if self.strict && filter.is_none() && self.mac.is_empty() && self.vlan.is_empty()
+            && self.app_protocol.is_empty() && self.ethertypes.is_empty() {
+            return Err("--strict needs at least one of --filter, --filter-file, --mac, --vlan, --app-protocol or --ethertypes".into());
+        }
+        Ok(Live {
+            filter,
+            macs,
+            vlans,
+            queue_full: self.queue_full,
+            unicast_only: self.unicast_only,
+            stdout_interval: self.stdout_interval,
+            stdout_top: self.stdout_top,
+        })
+    }
+}
+
+fn ethertype_numbers<S>(ethertypes: &[EtherType], serializer: S) -> Result<S::Ok, S::Error>
+    where S: serde::Serializer {
+    serializer.collect_seq(ethertypes.iter().map(|e| format!("0x{:04x}", e.0)))
+}
 
-extern crate pnet;
+/// Most updates applied under one lock
+const MAX_BATCH: usize = 1024;
 
-use std::ops::Deref;
-use std::{panic, process, thread};
-use std::fs;
-use std::path::PathBuf;
+/// How often a lost --interface is looked for
+const INTERFACE_POLL: Duration = Duration::from_secs(1);
 
-use structopt::StructOpt;
-use either::Either;
+/// Wait before binding again, doubled on each failure up to HTTP_RETRY_MAX
+const HTTP_RETRY_MIN: Duration = Duration::from_secs(1);
+const HTTP_RETRY_MAX: Duration = Duration::from_secs(60);
 
-use pnet::datalink;
-use pnet::datalink::Channel::Ethernet;
-use pnet::packet::Packet;
-use pnet::packet::ethernet::{EthernetPacket, EtherTypes};
-use pnet::packet::ipv4::Ipv4Packet;
-use pnet::packet::ipv6::Ipv6Packet;
+/// Wait after a --rollover-at snapshot failed to write, rather than try again every batch
+const ROLLOVER_RETRY: Duration = Duration::from_secs(10);
 
-use serde::{Serialize, Serializer};
+/// Room for Ethernet with two VLAN tags, IPv6 and a TCP header with options
+const STATS_ONLY_SNAPLEN: usize = 128;
 
-use rouille::Response;
+#[derive(StructOpt, Debug, Serialize)]
+enum Command {
+    #[structopt(about = "Show per-flow changes between two database snapshots")]
+    Diff {
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
 
-use std::sync::{Mutex, Arc};
-use crate::data::{Ipv4StatsKey, Ipv6StatsKey, Stats, StatsKey, update_db};
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
 
-#[derive(StructOpt, Debug)]
-#[structopt(name = "whoisthere")]
-struct WitOpt {
-    #[structopt(short, long, help = "Network interface whoisthere is sniffing from")]
-    interface: String,
+        #[structopt(long, help = "Machine readable output")]
+        json: bool,
+    },
 
-    #[structopt(
-        short,
-        long,
-        help = "Statistics http server bind address & port",
-        default_value = "127.0.0.1:3648"
-    )]
-    bind: String,
+    #[structopt(about = "Rewrite a database in another format, e.g. bincode to JSON to look at it")]
+    Convert {
+        #[structopt(long, parse(from_os_str))]
+        from: PathBuf,
 
-    #[structopt(
-        short,
-        long,
-        help = "Database file path, default to in-memory database",
-        parse(from_os_str),
-    )]
-    db: Option<PathBuf>,
+        #[structopt(long, help = "Gzipped when it ends in .gz", parse(from_os_str))]
+        to: PathBuf,
+
+        #[structopt(
+            long,
+            help = "Format of --from, by its extension (.bin and .bincode for bincode, JSON otherwise) without",
+            possible_values = &["json", "bincode"],
+        )]
+        in_format: Option<DbFormat>,
+
+        #[structopt(long, help = "Format of --to, by its extension without", possible_values = &["json", "bincode"])]
+        out_format: Option<DbFormat>,
+    },
+
+    #[structopt(about = "List the interfaces --interface accepts")]
+    ListInterfaces,
+}
+
+#[cfg(target_os = "linux")]
+const PRIVILEGE_HINT: &str =
+    "Capturing needs root or CAP_NET_RAW; try sudo or `setcap cap_net_raw+ep` on the binary";
+#[cfg(not(target_os = "linux"))]
+const PRIVILEGE_HINT: &str = "Capturing needs root (or read access to /dev/bpf*); try sudo";
+
+fn parse_mode(s: &str) -> Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(s, 8)
+}
+
+/// Command line options on top of the options in `config`
+fn parse_with_config(config: &Path, cli: &[OsString]) -> Result<WitOpt, String> {
+    let mut args = vec![cli[0].clone()];
+    args.extend(config::file_args(config)?);
+    args.extend(cli[1..].iter().cloned());
+    WitOpt::from_iter_safe(args).map_err(|e| e.message)
+}
+
+/// `shown` is what /config serves, its live options get the reloaded values
+fn reload(config: Option<&Path>, cli: &[OsString], started: &serde_json::Value, shown: &RwLock<serde_json::Value>, state: &State) {
+    let config = match config {
+        Some(c) => c,
+        None => {
+            warn!("Got SIGHUP but there is no --config to reload");
+            return;
+        }
+    };
+    let opt = match parse_with_config(config, cli) {
+        Ok(opt) => opt,
+        Err(e) => {
+            error!("Fail to reload config, keeping the current one: {}", e.lines().next().unwrap_or(""));
+            return;
+        }
+    };
+    if let (Some(old), Ok(serde_json::Value::Object(new))) = (started.as_object(), serde_json::to_value(&opt)) {
+        for (name, value) in new {
+            if !Live::OPTIONS.contains(&name.as_str()) && old.get(&name) != Some(&value) {
+                warn!("--{} changed in {}, restart to apply it", name.replace('_', "-"), config.display());
+            }
+        }
+    }
+    let live = match opt.live() {
+        Ok(live) => live,
+        Err(e) => {
+            error!("Fail to reload config, keeping the current one: {}", e);
+            return;
+        }
+    };
+    *state.live.write().unwrap() = live;
+    let reloaded = redacted(&opt);
+    let mut shown = shown.write().unwrap();
+    for name in Live::OPTIONS {
+        shown[*name] = reloaded[*name].clone();
+    }
+    info!("Reloaded {}", config.display());
 }
 
-fn read_db(path: &Option<PathBuf>) -> Stats {
+fn read_db(path: &Option<PathBuf>, format: Option<DbFormat>) -> Stats {
     if let Some(p) = path {
-        match fs::read_to_string(p) {
-            Ok(s) => serde_json::from_str(&s).unwrap(),
+        let format = DbFormat::of(p, format);
+        match fs::read(p) {
+            Ok(data) => db_format::decode(&data, format).unwrap_or_else(|e| panic!("Fail to parse database: {}", e)),
             Err(e) => {
                 if e.kind() == std::io::ErrorKind::NotFound {
-                    // Create empty json
-                    fs::File::create(p).unwrap();
+                    fs::write(p, db_format::encode(&Stats::new(), format))
+                        .unwrap_or_else(|e| panic!("Fail to create database: {}", e));
                     Stats::new()
                 } else {
                     panic!("Fail to read database: {}", e);
@@ -66,101 +863,943 @@ fn read_db(path: &Option<PathBuf>) -> Stats {
     }
 }
 
-fn save_db(path: &Option<PathBuf>, in_memory: &Stats) {
-    if let Some(p) = path {
-        match serde_json::to_string(in_memory) {
-            Ok(s) => match fs::write(p, s) {
-                Ok(_) => (),
-                Err(e) => {
-                    panic!("Fail to write database: {}", e);
-                }
+/// The seen hosts of --alert-new-hosts live next to the database
+fn hosts_path(db: &Path) -> PathBuf {
+    let mut path = db.as_os_str().to_owned();
+    path.push(".hosts");
+    PathBuf::from(path)
+}
+
+fn read_hosts(db: &Option<PathBuf>) -> HashSet<IpAddr> {
+    let path = match db {
+        Some(db) => hosts_path(db),
+        None => return HashSet::new()
+    };
+    match fs::read_to_string(&path) {
+        Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| panic!("Fail to parse {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+        Err(e) => panic!("Fail to read {}: {}", path.display(), e)
+    }
+}
+
+/// What --learn-duration learned about rates lives next to the database too
+fn baseline_path(db: &Path) -> PathBuf {
+    let mut path = db.as_os_str().to_owned();
+    path.push(".baseline");
+    PathBuf::from(path)
+}
+
+fn read_baseline(db: &Option<PathBuf>) -> Option<Baseline> {
+    let path = baseline_path(db.as_ref()?);
+    match fs::read_to_string(&path) {
+        Ok(s) => Some(serde_json::from_str(&s).unwrap_or_else(|e| panic!("Fail to parse {}: {}", path.display(), e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => panic!("Fail to read {}: {}", path.display(), e)
+    }
+}
+
+/// Learning is over, write what it learned so the next run doesn't start over
+fn end_learning(db: &Option<PathBuf>, baseline: Baseline, state: &State, fsync: bool) {
+    let hosts = state.seen_hosts.lock().unwrap().known();
+    info!(hosts = hosts, flows = baseline.flows.len();
+          "Done learning: {} hosts, {} flows over --alert-bps, alerting from now on", hosts, baseline.flows.len());
+    if let Some(db) = db {
+        let path = baseline_path(db);
+        if let Err(e) = write_aside(&path, serde_json::to_string(&baseline).unwrap().as_bytes(), fsync) {
+            error!("Fail to save {}, the next run learns again: {}", path.display(), e);
+        }
+    }
+}
+
+// The aggregator and the exit flush share the .tmp files
+static SAVING: Mutex<()> = Mutex::new(());
+
+// Only the first of a run of failed saves gets logged
+static SAVE_FAILING: Mutex<WarnOnce> = Mutex::new(WarnOnce::new(Level::Error, module_path!()));
+
+/// Write the flows, and the seen hosts if they changed. A failure is logged and
+/// counted, and the next save tries again. False if it failed.
+fn save_db(path: &Option<PathBuf>, format: Option<DbFormat>, state: &State, fsync: bool) -> bool {
+    let p = match path {
+        Some(p) => p,
+        None => return true
+    };
+    let _saving = SAVING.lock().unwrap_or_else(|e| e.into_inner());
+    let mut result = write_aside(p, &db_format::encode(&state.db.snapshot(), DbFormat::of(p, format)), fsync);
+    if result.is_ok() {
+        let mut seen = state.seen_hosts.lock().unwrap();
+        if let Some(hosts) = seen.changed() {
+            result = write_aside(&hosts_path(p), serde_json::to_string(&hosts).unwrap().as_bytes(), fsync);
+            if result.is_err() {
+                seen.unsaved();
+            }
+        }
+    }
+    let mut failing = SAVE_FAILING.lock().unwrap_or_else(|e| e.into_inner());
+    let was_failing = failing.failing();
+    match failing.report(result, &format!("save {}, retrying every --db-interval", p.display())) {
+        Ok(()) => {
+            if was_failing {
+                info!("Saving {} works again", p.display());
+            }
+            true
+        }
+        Err(_) => {
+            runtime::inc(&state.runtime.save_errors);
+            false
+        }
+    }
+}
+
+fn with_path(path: &Path) -> impl Fn(std::io::Error) -> std::io::Error + '_ {
+    move |e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+/// Write aside then rename, so a crash mid-write leaves the old file whole
+fn write_aside(path: &Path, data: &[u8], fsync: bool) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = fs::File::create(&tmp).map_err(with_path(&tmp))?;
+    file.write_all(data).map_err(with_path(&tmp))?;
+    if fsync {
+        file.sync_all().map_err(with_path(&tmp))?;
+    }
+    fs::rename(&tmp, path).map_err(with_path(path))?;
+    if fsync {
+        sync_dir(path)?;
+    }
+    Ok(())
+}
+
+// The rename itself only survives a power loss once the directory is synced
+#[cfg(unix)]
+fn sync_dir(file: &Path) -> std::io::Result<()> {
+    let dir = match file.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new(".")
+    };
+    fs::File::open(dir).and_then(|d| d.sync_all()).map_err(with_path(dir))
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_file: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Options holding credentials, masked wherever options get shown
+// Webhook URLs tend to carry their token
+const SECRETS: [&str; 5] = ["auth_token", "basic_auth", "mqtt_password", "alert_webhook", "rollup_to"];
+
+fn redacted(opt: &WitOpt) -> serde_json::Value {
+    let mut options = serde_json::to_value(opt).unwrap();
+    for name in SECRETS {
+        if !options[name].is_null() {
+            options[name] = "<redacted>".into();
+        }
+    }
+    options
+}
+
+/// Try `<path>.tmp`, the file saving writes aside to anyway
+fn check_writable(path: &Path) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::File::create(&tmp)?;
+    fs::remove_file(&tmp)
+}
+
+/// What would make startup fail, without capturing anything or writing what's there
+fn check(opt: &WitOpt) -> Vec<String> {
+    let mut errors = Vec::new();
+    if let Err(e) = opt.live() {
+        errors.push(e);
+    }
+    match (&opt.interface, &opt.pcap) {
+        (_, Some(path)) => if let Err(e) = PcapReader::open(path) {
+            errors.push(format!("Fail to open {}: {}", path.display(), e));
+        },
+        (Some(spec), None) => if let Err(e) = iface::find(spec) {
+            errors.push(e);
+        },
+        (None, None) => ()
+    }
+    if let Some(db) = &opt.db {
+        match fs::read(db) {
+            // Overwritten anyway
+            _ if opt.fresh => (),
+            Ok(data) => if let Err(e) = db_format::decode(&data, DbFormat::of(db, opt.db_format)) {
+                errors.push(format!("Fail to parse database {}: {}", db.display(), e));
             },
-            Err(e) => {
-                panic!("Fail to serialize database: {}", e);
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => errors.push(format!("Fail to read database {}: {}", db.display(), e)),
+            _ => ()
+        }
+        if let Err(e) = check_writable(db) {
+            errors.push(format!("Database {} is not writable: {}", db.display(), e));
+        }
+        let baseline = baseline_path(db);
+        if opt.learn_duration.is_some() && baseline.exists() {
+            if let Err(e) = fs::read_to_string(&baseline).map_err(|e| e.to_string())
+                .and_then(|s| serde_json::from_str::<Baseline>(&s).map_err(|e| e.to_string())) {
+                errors.push(format!("Fail to read {}: {}", baseline.display(), e));
+            }
+        }
+        let hosts = hosts_path(db);
+        if opt.alert_new_hosts && !opt.forget_hosts && hosts.exists() {
+            if let Err(e) = fs::read_to_string(&hosts).map_err(|e| e.to_string())
+                .and_then(|s| serde_json::from_str::<HashSet<IpAddr>>(&s).map_err(|e| e.to_string())) {
+                errors.push(format!("Fail to read {}: {}", hosts.display(), e));
+            }
+        }
+    }
+    for path in opt.tls_cert.iter().chain(&opt.tls_key) {
+        if let Err(e) = fs::read(path) {
+            errors.push(format!("Fail to read {}: {}", path.display(), e));
+        }
+    }
+    if let Some(dir) = opt.snapshot_dir.as_ref().filter(|d| d.exists()) {
+        if let Err(e) = check_writable(&dir.join("stats")) {
+            errors.push(format!("Snapshot directory {} is not writable: {}", dir.display(), e));
+        }
+    }
+    for bind in opt.bind.iter().filter(|_| opt.http()) {
+        if let Some(path) = bind.strip_prefix("unix:") {
+            let dir = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            if !dir.is_dir() {
+                errors.push(format!("No directory {} for {}", dir.display(), bind));
             }
+        } else if let Err(e) = bind.to_socket_addrs() {
+            errors.push(format!("Invalid bind address {}: {}", bind, e));
         }
     }
+    if let Some(addr) = &opt.statsd {
+        if let Err(e) = addr.to_socket_addrs() {
+            errors.push(format!("Invalid StatsD address {}: {}", addr, e));
+        }
+    }
+    if let Err(e) = privileges::resolve(opt.user.as_deref(), opt.group.as_deref()) {
+        errors.push(e);
+    }
+    errors
 }
 
 fn main() {
-    let opt = WitOpt::from_args();
-    let db = Arc::new(Mutex::new(read_db(&opt.db)));
+    let cli: Vec<OsString> = env::args_os().collect();
+    let mut opt = WitOpt::from_iter(&cli);
+    if let Some(config) = &opt.config {
+        opt = parse_with_config(config, &cli)
+            .unwrap_or_else(|e| Error::with_description(&e, ErrorKind::InvalidValue).exit());
+    }
+    logging::init(opt.log_format);
+    key_format::set(opt.key_format);
+    term::set_units(match (opt.human, opt.si) {
+        (false, _) => Units::Raw,
+        (true, false) => Units::Binary,
+        (true, true) => Units::Si
+    });
+    if let Some(cmd) = opt.cmd {
+        match cmd {
+            Command::Diff { old, new, json } => diff::run(&old, &new, json),
+            Command::Convert { from, to, in_format, out_format } => convert::run(&from, &to, in_format, out_format),
+            Command::ListInterfaces => iface::list()
+        }
+        return;
+    }
+    if opt.interface.is_none() && opt.pcap.is_none() {
+        Error::with_description("--interface or --pcap is required", ErrorKind::MissingRequiredArgument).exit()
+    }
+    if opt.check {
+        let errors = check(&opt);
+        println!("{}", serde_json::to_string_pretty(&redacted(&opt)).unwrap());
+        for e in &errors {
+            error!("{}", e);
+        }
+        if !errors.is_empty() {
+            process::exit(1);
+        }
+        info!("Configuration OK");
+        return;
+    }
+    if opt.daemon {
+        daemon::daemonize(opt.pidfile.as_deref());
+    } else if let Some(p) = &opt.pidfile {
+        daemon::write_pidfile(p);
+    }
+
+    let live = opt.live().unwrap_or_else(|e| Error::with_description(&e, ErrorKind::InvalidValue).exit());
+    let credentials = privileges::resolve(opt.user.as_deref(), opt.group.as_deref())
+        .unwrap_or_else(|e| Error::with_description(&e, ErrorKind::InvalidValue).exit());
+    let seen = match (opt.alert_new_hosts, opt.forget_hosts) {
+        (false, _) => SeenHosts::default(),
+        (true, true) => SeenHosts::new(HashSet::new()),
+        (true, false) => SeenHosts::new(read_hosts(&opt.db))
+    };
+    let baseline = opt.learn_duration.and_then(|_| read_baseline(&opt.db));
+    let warmup = match (&baseline, opt.learn_duration) {
+        (None, Some(duration)) => {
+            info!("Learning for {}s, holding new host and rate alerts back", duration.as_secs());
+            Warmup::new(duration)
+        }
+        (Some(_), _) => {
+            info!("Learned on an earlier run, remove {} to learn again", baseline_path(opt.db.as_ref().unwrap()).display());
+            Warmup::default()
+        }
+        (None, None) => Warmup::default()
+    };
+    let mut stats = Stats::new();
+    if !opt.fresh {
+        stats.merge(read_db(&opt.db, opt.db_format));
+        let families = summary::families(&stats);
+        for (name, family) in [("IPv4", &families.ipv4), ("IPv6", &families.ipv6)] {
+            if family.mismatched_flows > 0 {
+                warn!(flows = family.mismatched_flows; "{} of the {} {} flows in the database have lengths that don't add up, \
+                      see families in /summary", family.mismatched_flows, family.flows, name);
+            }
+        }
+    }
+    let state = State::new(stats, opt.shards, opt.track_rates, live,
+        opt.interface.as_deref().map(|spec| iface::link(&iface::find(spec).map_or_else(|_| spec.to_string(), |i| i.name)))
+            .unwrap_or_default(), Alerter::new(opt.alert_webhook.clone()),
+        Fanout::new(opt.fanout_hll))
+        .with_history(History::new(if opt.history_step.is_some() { opt.history_keep } else { 0 }))
+        .with_seen_hosts(seen)
+        .with_warmup(warmup);
+    state.runtime.fcs_included.store(opt.fcs_included, Ordering::Relaxed);
+    let state = match opt.packet_ring {
+        Some(n) => state.with_ring(PacketRing::new(n)),
+        None => state
+    };
+    let state = Arc::new(if opt.sketch {
+        state.with_sketch(Sketch::new(opt.sketch_width, opt.sketch_depth, opt.sketch_top))
+    } else {
+        state
+    });
 
     // https://stackoverflow.com/questions/35988775/how-can-i-cause-a-panic-on-a-thread-to-immediately-end-the-main-thread
     let orig_hook = panic::take_hook();
     panic::set_hook(Box::new(move |panic_info| {
-        // invoke the default handler and exit the process
+        // invoke the default handler and exit the process, unless run() is there to see it
         orig_hook(panic_info);
-        process::exit(1);
+        if !JOINED.get() {
+            process::exit(1);
+        }
     }));
 
-    let capdb = db.clone();
-    let capture_thread = thread::spawn(move || {
-        let interfaces = datalink::interfaces();
-        let interface = interfaces
-            .iter().find(|iface| iface.name == opt.interface)
-            .expect("No interfaces found");
-
-        let (_tx, mut rx) = match datalink::channel(interface, Default::default()) {
-            Ok(Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => panic!("Unknown channel type: Only Ethernet is supported"),
-            Err(e) => panic!("Error creating channel: {}", e)
-        };
+    let runtime = tokio::runtime::Runtime::new().unwrap_or_else(|e| panic!("Fail to start the runtime: {}", e));
+    let result = runtime.block_on(run(Arc::new(opt), cli, state, credentials, baseline));
+    // Dropping it would wait on the HTTP server, which never returns
+    runtime.shutdown_background();
+    if let Err(e) = result {
+        error!("{}", e);
+        process::exit(1);
+    }
+}
+
+thread_local! {
+    /// Set on threads whose panics run() gets from the join handle
+    static JOINED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the current thread as JOINED until dropped, the blocking pool reuses threads
+struct Joined;
+
+impl Joined {
+    fn mark() -> Self {
+        JOINED.set(true);
+        Joined
+    }
+}
+
+impl Drop for Joined {
+    fn drop(&mut self) {
+        JOINED.set(false);
+    }
+}
+
+/// What a panicked task said, for the error it ends run() with
+fn stopped(task: &str, e: task::JoinError) -> String {
+    let cause = match e.try_into_panic() {
+        Ok(panic) => panic.downcast_ref::<String>().cloned()
+            .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "unknown panic".to_string()),
+        Err(e) => e.to_string()
+    };
+    format!("{} stopped: {}", task, cause)
+}
+
+/// GET /db, what save_db would write right now
+fn download_db(state: &State, path: Option<&Path>, format: Option<DbFormat>) -> rouille::Response {
+    let path = path.unwrap_or(Path::new("whoisthere.json"));
+    let format = DbFormat::of(path, format);
+    let name = path.file_name().map_or("whoisthere.json".into(), |n| n.to_string_lossy());
+    let content_type = match format {
+        DbFormat::Json => "application/json",
+        DbFormat::Bincode => "application/octet-stream"
+    };
+    rouille::Response::from_data(content_type, db_format::encode(&state.db.snapshot(), format))
+        .with_unique_header("Content-Disposition", format!("attachment; filename=\"{}\"", name.replace('"', "")))
+}
+
+/// Move all the flows to a snapshot, --rollover-at. False when it can't be written,
+/// the flows are kept then.
+fn roll_over(state: &State, snapshots: &Snapshots) -> bool {
+    let stats = state.reset();
+    match snapshots.write(&stats) {
+        Ok(path) => {
+            warn!(flows = stats.0.len(); "Rolled {} flows over to {}", stats.0.len(), path.display());
+            true
+        }
+        Err(e) => {
+            warn!("Fail to write snapshot {}, keeping the {} flows", e, stats.0.len());
+            state.db.merge(stats);
+            state.publish();
+            false
+        }
+    }
+}
 
-        eprintln!("Capturing packets on interface: {}", interface.name);
+/// Run `f` every `period`, the first time one period from now
+fn every<F>(period: Duration, mut f: F)
+    where F: FnMut() + Send + 'static {
+    tokio::spawn(async move {
+        let mut ticks = time::interval_at(time::Instant::now() + period, period);
+        // A slow round pushes the next ones back rather than bunching them up
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
-            match rx.next() {
-                Ok(packet) => {
-                    if let Some(p) = proc_packet(packet) {
-                        update_db(capdb.lock().unwrap(), p);
-                        save_db(&opt.db, capdb.lock().unwrap().deref());
-                    }
+            ticks.tick().await;
+            task::block_in_place(&mut f);
+        }
+    });
+}
+
+/// Err when capture or the aggregator died, the HTTP server goes down with the runtime
+async fn run(opt: Arc<WitOpt>, cli: Vec<OsString>, state: Arc<State>, credentials: Option<Credentials>,
+             baseline: Option<Baseline>) -> Result<(), String> {
+    let terminated = daemon::terminated();
+    let started = serde_json::to_value(opt.as_ref()).unwrap();
+    let shown = Arc::new(RwLock::new(redacted(&opt)));
+    let (hupstate, hupshown, config) = (state.clone(), shown.clone(), opt.config.clone());
+    daemon::on_hangup(move || reload(config.as_deref(), &cli, &started, &hupshown, &hupstate));
+
+    let (updates_tx, updates_rx) = mpsc::sync_channel::<StatsUpdate>(opt.queue_size);
+
+    let (aggstate, aggopt, rollover) = (state.clone(), opt.clone(), opt.snapshots());
+    let mut aggregate_task = task::spawn_blocking(move || {
+        // The TUI has the terminal and never looks at the tasks, the hook ends it
+        let _joined = (!aggopt.tui).then(Joined::mark);
+        let mut retry_rollover: Option<Instant> = None;
+        while let Ok(first) = updates_rx.recv() {
+            let mut batch = vec![first];
+            batch.extend(updates_rx.try_iter().take(MAX_BATCH - 1));
+            runtime::sub(&aggstate.runtime.queue_depth, batch.len() as u64);
+            match &aggstate.sketch {
+                Some(sketch) => {
+                    let mut sketch = sketch.lock().unwrap();
+                    batch.iter().for_each(|u| sketch.update(u));
+                }
+                None => aggstate.db.update_batch(batch)
+            }
+            if aggopt.low_latency {
+                aggstate.publish();
+            }
+            if let (Some(limit), Some(snapshots)) = (aggopt.rollover_at, &rollover) {
+                if aggstate.db.len() > limit && retry_rollover.is_none_or(|at| Instant::now() >= at) {
+                    retry_rollover = (!roll_over(&aggstate, snapshots)).then(|| Instant::now() + ROLLOVER_RETRY);
+                }
+            }
+        }
+    });
+
+    let source = open_source(&opt, &state);
+    // Before the HTTP server or anything else that talks to the outside is up
+    if let Some(credentials) = &credentials {
+        credentials.apply().unwrap_or_else(|e| panic!("{}, refusing to go on with the privileges it has", e));
+        info!(user = opt.user.as_deref(), group = opt.group.as_deref(); "Dropped privileges");
+    }
+    let (capstate, capopt) = (state.clone(), opt.clone());
+    let mut capture_task = task::spawn_blocking(move || {
+        let _joined = (!capopt.tui).then(Joined::mark);
+        capture(&capopt, &capstate, source, updates_tx)
+    });
+
+    let (ratestate, rateopt) = (state.clone(), opt.clone());
+    let mut rate_alerts = opt.alert_bps.map(|bps| {
+        let alerter = RateAlerter::new(bps, opt.alert_bps_samples);
+        match baseline {
+            Some(baseline) => alerter.with_baseline(baseline),
+            None => alerter
+        }
+    });
+    every(opt.rate_interval, move || {
+        ratestate.db.roll_rates();
+        if let Some(rate_alerts) = &mut rate_alerts {
+            let above = ratestate.db.faster_than(rate_alerts.bytes_per_sec());
+            if ratestate.warmup.learning() {
+                rate_alerts.learn(above);
+            } else {
+                for alert in rate_alerts.observe(above) {
+                    ratestate.alerts.fire(alert);
                 }
-                Err(e) => eprintln!("Error receiving packet: {}", e)
             }
         }
+        if ratestate.warmup.ended() {
+            let baseline = rate_alerts.as_ref().map_or(Baseline { learned_at: unix_now(), flows: Vec::new() }, |r| r.baseline());
+            end_learning(&rateopt.db, baseline, &ratestate, rateopt.fsync);
+        }
     });
 
-    let httpdb = db.clone();
-    let http_thread = thread::spawn(move || {
-        eprintln!("HTTP server @ {}", opt.bind);
-        rouille::start_server(opt.bind, move |request| {
-            eprintln!("{:?}", request);
-            Response::json(httpdb.lock().unwrap().deref())
+    let pubstate = state.clone();
+    every(opt.publish_interval, move || pubstate.publish());
+
+    if opt.db.is_some() {
+        let (savestate, saveopt) = (state.clone(), opt.clone());
+        every(opt.db_interval, move || {
+            save_db(&saveopt.db, saveopt.db_format, &savestate, saveopt.fsync);
+        });
+    }
+
+    if let Some(step) = opt.history_step {
+        let histstate = state.clone();
+        every(step, move || histstate.history.lock().unwrap().record(&histstate.published()));
+    }
+
+    if let Some(topic) = opt.kafka_topic.clone().filter(|_| !opt.kafka_brokers.is_empty()) {
+        let kafkastate = state.clone();
+        let mut sink = KafkaSink::new(opt.kafka_brokers.clone(), topic);
+        every(opt.kafka_interval, move || sink.round(&kafkastate));
+    }
+
+    if let Some(broker) = opt.mqtt_broker.clone() {
+        let mqttstate = state.clone();
+        let credentials = opt.mqtt_username.clone().map(|u| (u, opt.mqtt_password.clone()));
+        let mut sink = MqttSink::new(broker, opt.mqtt_topic.clone(), credentials, opt.mqtt_top);
+        every(opt.mqtt_interval, move || sink.round(&mqttstate));
+    }
+
+    if let Some(addr) = &opt.statsd {
+        let statsdstate = state.clone();
+        let mut sink = StatsdSink::new(addr, opt.statsd_prefix.clone(), opt.statsd_top, opt.statsd_tags)
+            .unwrap_or_else(|e| panic!("Fail to set up StatsD to {}: {}", addr, e));
+        every(opt.statsd_interval, move || sink.round(&statsdstate));
+    }
+
+    if let Some(interval) = opt.rollup_interval {
+        let rollupstate = state.clone();
+        let sink = opt.rollup_to.parse().unwrap_or_else(|e| panic!("{}", e));
+        let mut rollups = Rollups::new(sink, &state);
+        every(interval, move || rollups.round(&rollupstate));
+    }
+
+    if let Some(snapshots) = opt.snapshots() {
+        let (snapstate, reset) = (state.clone(), opt.snapshot_reset);
+        fs::create_dir_all(&snapshots.dir).unwrap_or_else(|e| panic!("Fail to create snapshot directory: {}", e));
+        every(opt.snapshot_interval, move || {
+            let stats = if reset { snapstate.reset() } else { snapstate.db.snapshot() };
+            match snapshots.write(&stats) {
+                Ok(path) => info!(flows = stats.0.len(); "Wrote snapshot {} with {} flows", path.display(), stats.0.len()),
+                Err(e) => {
+                    warn!("Fail to write snapshot {}", e);
+                    if reset {
+                        snapstate.db.merge(stats);
+                        snapstate.publish();
+                    }
+                }
+            }
+        });
+    }
+
+    if let Some(interval) = opt.rate_log_interval {
+        let ratelogstate = state.clone();
+        let mut previous = Sample::totals(&state.published());
+        every(interval, move || {
+            let sample = Sample::totals(&ratelogstate.published());
+            if let Some(&(_, bytes, packets)) = history::rates([&previous, &sample].into_iter()).first() {
+                info!(pps = packets, bps = bytes * 8.0, flows = sample.flows;
+                      "Rate: {:.1} pps, {:.0} bps, {} flows", packets, bytes * 8.0, sample.flows);
+            }
+            previous = sample;
+        });
+    }
+
+    if let Some(interval) = opt.reset_interval {
+        let resetstate = state.clone();
+        let snapshots = opt.snapshots();
+        every(interval, move || {
+            // Readers go straight from the old interval to the new one
+            let stats = resetstate.reset();
+            let s = summary::summary(&stats, &resetstate.runtime, &resetstate.link, resetstate.bps_1m(), resetstate.cps(), resetstate.db.peak());
+            info!(flows = s.flows, count = s.total_count as u64, size = s.total_length as u64;
+                  "Interval ended: {} flows, count : {} size : {}", s.flows, s.total_count, s.total_length);
+            if let Some(snapshots) = &snapshots {
+                fs::create_dir_all(&snapshots.dir).unwrap_or_else(|e| panic!("Fail to create snapshot directory: {}", e));
+                if let Err(e) = snapshots.write(&stats) {
+                    warn!("Fail to write snapshot {}, the interval's flows carry over to the next", e);
+                    resetstate.db.merge(stats);
+                    resetstate.publish();
+                }
+            }
         });
+    }
+
+    let termstate = state.clone();
+    let color = term::color_enabled();
+    tokio::spawn(async move {
+        loop {
+            let interval = termstate.live.read().unwrap().stdout_interval;
+            match interval {
+                Some(interval) => {
+                    time::sleep(interval).await;
+                    let top = termstate.live.read().unwrap().stdout_top;
+                    task::block_in_place(|| term::dump(&termstate.db.snapshot(), top, color));
+                }
+                // A reload may turn it on
+                None => time::sleep(Duration::from_secs(1)).await
+            }
+        }
+    });
+
+    let httpstate = state.clone();
+    let auth = http::Auth::new(opt.auth_token.clone(), opt.basic_auth.clone());
+    let tls = opt.tls_cert.as_ref().zip(opt.tls_key.as_ref()).map(|(cert, key)| http::Tls::load(cert, key));
+    let (grafana, debug) = (opt.grafana, opt.debug);
+    let (db, db_format) = (opt.db.clone(), opt.db_format);
+    let handler = Arc::new(move |request: &rouille::Request| {
+        // Not the whole request, headers may carry credentials
+        info!("{} {} from {}", request.method(), request.raw_url(), request.remote_addr());
+        if !auth.allows(request) {
+            return auth.reject();
+        }
+        if request.method() == "GET" && request.url() == "/db" {
+            // All of it at once, not for anyone who can reach the port
+            if matches!(auth, http::Auth::None) {
+                return rouille::Response::text("/db needs --auth-token or --basic-auth").with_status_code(403);
+            }
+            return download_db(&httpstate, db.as_deref(), db_format);
+        }
+        if request.method() == "GET" && request.url() == "/recent.pcap" {
+            if matches!(auth, http::Auth::None) {
+                return rouille::Response::text("/recent.pcap needs --auth-token or --basic-auth").with_status_code(403);
+            }
+            return match &httpstate.ring {
+                Some(ring) => rouille::Response::from_data("application/vnd.tcpdump.pcap", ring.lock().unwrap().pcap())
+                    .with_unique_header("Content-Disposition", "attachment; filename=\"recent.pcap\""),
+                None => rouille::Response::text("/recent.pcap needs --packet-ring").with_status_code(404)
+            };
+        }
+        if request.method() == "GET" && request.url() == "/config" {
+            return rouille::Response::json(&*shown.read().unwrap());
+        }
+        if grafana && request.url().starts_with("/grafana") {
+            return whoisthere::grafana::handle(request, &httpstate);
+        }
+        if debug && request.url().starts_with("/debug/") {
+            return whoisthere::debug::handle(request);
+        }
+        whoisthere::api::handle(request, &httpstate)
     });
+    let binds = if opt.http() { &opt.bind[..] } else { &[][..] };
+    for bind in binds {
+        let (bind, socket_mode, tls, handler) = (bind.clone(), opt.socket_mode, tls.clone(), handler.clone());
+        let (boundstate, require) = (state.clone(), opt.require_http);
+        task::spawn_blocking(move || {
+            let mut retry = HTTP_RETRY_MIN;
+            loop {
+                let handler = handler.clone();
+                let e = http::serve(&bind, socket_mode, tls.clone(), move |request| handler(request), |addr| {
+                    info!("HTTP server @ {}", addr);
+                    // For scripts binding port 0
+                    println!("{}", addr);
+                    boundstate.runtime.http_addrs.lock().unwrap().push(addr);
+                });
+                if require {
+                    panic!("{}", e);
+                }
+                warn!("{}, capturing on without it and trying again in {}s", e, retry.as_secs());
+                thread::sleep(retry);
+                retry = (retry * 2).min(HTTP_RETRY_MAX);
+            }
+        });
+    }
+
+    let done = async {
+        if opt.tui {
+            let tuistate = state.clone();
+            if let Err(e) = task::spawn_blocking(move || tui::run(tuistate)).await.unwrap() {
+                panic!("TUI failed: {}", e);
+            }
+            process::exit(0);
+        }
+        // Whichever dies first ends it. The aggregator ends once capture hangs up its side of the
+        // queue: at the end of --pcap, or with the interface gone and --on-interface-loss exit
+        let ended = tokio::select! {
+            captured = &mut capture_task => match captured {
+                Ok(()) => (&mut aggregate_task).await.map_err(|e| stopped("Aggregation", e)),
+                Err(e) => Err(stopped("Capture", e))
+            },
+            aggregated = &mut aggregate_task => match aggregated {
+                Ok(()) => (&mut capture_task).await.map_err(|e| stopped("Capture", e)),
+                Err(e) => Err(stopped("Aggregation", e))
+            }
+        };
+        // What was counted since the last --db-interval, also when a task died
+        let saved = save_db(&opt.db, opt.db_format, &state, opt.fsync);
+        ended?;
+        state.publish();
+        if let Some(p) = &opt.pidfile {
+            let _ = fs::remove_file(p);
+        }
+        if opt.once {
+            print!("{}", state.db.snapshot());
+        }
+        if saved { Ok(()) } else { Err("Fail to save the database on the way out".to_string()) }
+    };
+
+    let elapsed = async {
+        match opt.duration {
+            Some(d) => time::sleep(d).await,
+            None => std::future::pending().await
+        }
+    };
+
+    let reason = tokio::select! {
+        result = done => return result,
+        signal = terminated => format!("Got {}", signal),
+        _ = elapsed => "Done capturing".to_string()
+    };
+    info!("{}, exiting", reason);
+    let saved = save_db(&opt.db, opt.db_format, &state, opt.fsync);
+    if let Some(p) = &opt.pidfile {
+        let _ = fs::remove_file(p);
+    }
+    if opt.once {
+        print!("{}", state.db.snapshot());
+    }
+    process::exit(if saved { 0 } else { 1 });
+}
 
-    capture_thread.join().unwrap();
-    http_thread.join().unwrap();
+/// The interface or --pcap file, opened
+struct Source {
+    name: String,
+    rx: Box<dyn DataLinkReceiver>,
+    // Where --track-jitter and --connection-log get the time, the file's with --pcap
+    clock: Option<Arc<AtomicU64>>,
+    /// With --pcap, what the file says the last frame was on the wire
+    original_length: Option<Arc<AtomicU64>>,
 }
 
-fn proc_packet(packet: &[u8]) -> Option<(StatsKey, u128)> {
-    if let Some(eth_packet) = EthernetPacket::new(packet) {
-        match eth_packet.get_ethertype() {
-            EtherTypes::Ipv4 =>
-                if let Some(p) = Ipv4Packet::new(eth_packet.payload()) {
-                    Some((StatsKey(Either::Left(Ipv4StatsKey { source: p.get_source(), dest: p.get_destination() })),
-                          p.get_total_length() as u128))
-                } else {
-                    eprintln!("Fail to construct Ipv4Packet: packet too small");
-                    None
+fn open_interface(opt: &WitOpt, interface: &NetworkInterface) -> std::io::Result<Box<dyn DataLinkReceiver>> {
+    let mut config = datalink::Config::default();
+    // Linux reads a frame at a time and drops what doesn't fit, elsewhere the buffer holds
+    // several frames and capture() does the cutting
+    if let Some(snaplen) = opt.snaplen.or(opt.stats_only.then_some(STATS_ONLY_SNAPLEN)).filter(|_| cfg!(target_os = "linux")) {
+        config.read_buffer_size = snaplen;
+    }
+    match datalink::channel(interface, config)? {
+        Ethernet(_tx, rx) => Ok(rx),
+        _ => Err(std::io::Error::other("Unknown channel type: Only Ethernet is supported"))
+    }
+}
+
+/// What to look `name`, the interface --interface found at startup, up by again. Not
+/// `#<index>`, an interface created anew gets a new one.
+fn interface_spec<'a>(opt: &'a WitOpt, name: &'a str) -> &'a str {
+    opt.interface.as_deref().filter(|spec| !spec.starts_with('#')).unwrap_or(name)
+}
+
+/// Poll for the interface --interface names until it's there again, then capture on it
+fn reopen_interface(opt: &WitOpt, state: &State, name: &str) -> Box<dyn DataLinkReceiver> {
+    loop {
+        thread::sleep(INTERFACE_POLL);
+        let interface = match iface::find(interface_spec(opt, name)) {
+            Ok(interface) => interface,
+            Err(_) => continue
+        };
+        match open_interface(opt, &interface) {
+            Ok(rx) => {
+                state.runtime.kernel.attach();
+                info!(interface = name; "Interface {} is back, capturing again", name);
+                return rx;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied =>
+                panic!("Fail to reopen {}: {}. {}", name, e, PRIVILEGE_HINT),
+            // Up again but not ready yet, or gone again
+            Err(e) => warn!(interface = name; "Fail to reopen {}, retrying: {}", name, e)
+        }
+    }
+}
+
+/// Open the interface or --pcap, the one thing --user may need root for
+fn open_source(opt: &WitOpt, state: &State) -> Source {
+    let (mut clock, mut original_length) = (None, None);
+    let (name, rx): (String, Box<dyn DataLinkReceiver>) = match (&opt.interface, &opt.pcap) {
+        (_, Some(path)) => {
+            let reader = PcapReader::open(path)
+                .unwrap_or_else(|e| panic!("Fail to open {}: {}", path.display(), e));
+            info!("Reading packets from {}", reader.name());
+            clock = Some(reader.clock());
+            original_length = Some(reader.original_length());
+            (reader.name().to_string(), Box::new(reader))
+        }
+        (Some(spec), None) => {
+            let interface = iface::find(spec).unwrap_or_else(|e| panic!("{}", e));
+            let rx = match open_interface(opt, &interface) {
+                Ok(rx) => rx,
+                Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                    error!("Permission denied opening {}: {}", interface.name, e);
+                    error!("{}", PRIVILEGE_HINT);
+                    process::exit(1);
                 }
-            // No, the fact is they are different fundamentally so no polymorphism here sorry
-            EtherTypes::Ipv6 =>
-                if let Some(p) = Ipv6Packet::new(eth_packet.payload()) {
-                    Some((StatsKey(Either::Right(Ipv6StatsKey { source: p.get_source(), dest: p.get_destination() })),
-                          p.get_payload_length() as u128))
+                Err(e) => panic!("Error creating channel: {}", e)
+            };
+            state.runtime.kernel.attach();
+            info!(interface = interface.name.as_str(), speed_bps = state.link.speed_bps, mtu = state.link.mtu;
+                  "Capturing packets on interface: {}", interface.name);
+            (interface.name, rx)
+        }
+        (None, None) => unreachable!()
+    };
+    Source { name, rx, clock, original_length }
+}
+
+/// Read packets from the source into the queue, until the end of the file
+fn capture(opt: &WitOpt, state: &State, source: Source, updates_tx: SyncSender<StatsUpdate>) {
+    let offline = opt.pcap.is_some();
+    let Source { name: source, mut rx, clock, original_length } = source;
+    let snapped = opt.snaplen.is_some() || opt.stats_only;
+    let snaplen = opt.snaplen.or(opt.stats_only.then_some(STATS_ONLY_SNAPLEN));
+    let mut app_protocols = (!opt.app_protocol.is_empty()).then(|| AppFilter::new(opt.app_protocol.clone()));
+    let mut connections = opt.connections_only.then(ConnectionFilter::default);
+    let mut connection_log = opt.connection_log.as_ref().map(|path| ConnectionLog::open(path)
+        .unwrap_or_else(|e| panic!("Fail to open connection log {}: {}", path.display(), e)));
+    let mut updater = Updater::new(opt.granularity, snapped).with_deep_inspect_bytes(opt.deep_inspect_bytes);
+    if opt.ignore_ephemeral_ports {
+        updater = updater.with_ephemeral_port_min(opt.ephemeral_port_min);
+    }
+    if opt.track_windows {
+        updater = updater.with_windows();
+    }
+    if opt.guess_protocol {
+        updater = updater.with_guesser();
+    }
+    let mut sni = NameTracker::sni(opt.deep_inspect_bytes);
+    let mut http_host = NameTracker::http_host(opt.deep_inspect_bytes);
+    let mut scans = (opt.scan_ports.is_some() || opt.scan_hosts.is_some())
+        .then(|| ScanDetector::new(opt.scan_ports, opt.scan_hosts, opt.scan_window));
+    let mut syn_floods = opt.syn_flood.map(|threshold| SynFloodDetector::new(threshold, opt.syn_flood_window));
+    let mut bogons = opt.flag_bogons.then(BogonWatcher::default);
+    loop {
+        match rx.next() {
+            Ok(packet) => {
+                let packet = &packet[..snaplen.map_or(packet.len(), |s| packet.len().min(s))];
+                // Cut short by whoever wrote the --pcap file
+                let original = original_length.as_ref().map(|o| o.load(Ordering::Relaxed) as usize)
+                    .filter(|o| *o > packet.len());
+                // Only frames shorter than the snaplen made it to their end
+                let packet = if opt.fcs_included && original.is_none() && snaplen.is_none_or(|s| packet.len() < s) {
+                    &packet[..packet.len().saturating_sub(FCS_LEN)]
                 } else {
-                    eprintln!("Fail to construct Ipv6Packet: packet too small");
-                    None
+                    packet
+                };
+                if let Some(ring) = &state.ring {
+                    let at = clock.as_ref().map_or_else(jitter::unix_micros, |c| c.load(Ordering::Relaxed));
+                    ring.lock().unwrap().push(at, packet);
+                }
+                if let Some(mut p) = proc_packet(packet, &state.runtime, &opt.ethertypes) {
+                    // Before the filter, whose less and greater go by it
+                    if let Some(original) = original {
+                        p.wire_length = original.saturating_sub(if opt.fcs_included { FCS_LEN } else { 0 }) as u128;
+                    }
+                    let (queue_full, unicast_only, wanted) = {
+                        let live = state.live.read().unwrap();
+                        // A file waits for the aggregator rather than lose updates
+                        let queue_full = if offline { QueueFull::Block } else { live.queue_full };
+                        let wanted = live.mac_matches(&p) && live.vlan_matches(&p) && live.filter.as_ref().is_none_or(|f| f.matches(&p));
+                        (queue_full, live.unicast_only, wanted)
+                    };
+                    if !wanted || !app_protocols.as_mut().is_none_or(|a| a.matches(&p))
+                        || !connections.as_mut().is_none_or(|c| c.matches(&p)) {
+                        runtime::inc(&state.runtime.filtered);
+                        continue;
+                    }
+                    if let Some(log) = connection_log.as_mut() {
+                        let at = clock.as_ref().map_or_else(jitter::unix_micros, |c| c.load(Ordering::Relaxed));
+                        if !log.observe(p.key.with_transport(p.transport_key()), at) {
+                            continue;
+                        }
+                    }
+                    let update = updater.update(&mut p, original.is_some(), unicast_only, &state.runtime);
+                    sni.observe(&p, &state.sni);
+                    http_host.observe(&p, &state.http);
+                    neighbor::observe(&p, &state.neighbors);
+                    icmp::observe(&p, &state.icmp);
+                    if opt.track_fanout {
+                        state.fanout.lock().unwrap().observe(&p);
+                    }
+                    if let Some(alert) = scans.as_mut().and_then(|s| s.observe(&p)) {
+                        state.alerts.fire(alert);
+                    }
+                    if opt.alert_new_hosts {
+                        let alert = state.seen_hosts.lock().unwrap().observe(p.key.addrs().0);
+                        if let Some(alert) = alert.filter(|_| !state.warmup.learning()) {
+                            state.alerts.fire(alert);
+                        }
+                    }
+                    for alert in syn_floods.as_mut().map(|s| s.observe(&p)).unwrap_or_default() {
+                        state.alerts.fire(alert);
+                    }
+                    if let Some(alert) = bogons.as_mut().and_then(|b| b.observe(&p)) {
+                        state.alerts.fire(alert);
+                    }
+                    let Some(mut update) = update else { continue };
+                    update.arrived = (opt.track_jitter && p.udp.is_some())
+                        .then(|| clock.as_ref().map_or_else(jitter::unix_micros, |c| c.load(Ordering::Relaxed)));
+                    runtime::inc(&state.runtime.queue_depth);
+                    let sent = match queue_full {
+                        QueueFull::Block => updates_tx.send(update).map_err(|_| TrySendError::Disconnected(())),
+                        QueueFull::Drop => updates_tx.try_send(update).map_err(|e| match e {
+                            TrySendError::Full(_) => TrySendError::Full(()),
+                            TrySendError::Disconnected(_) => TrySendError::Disconnected(())
+                        })
+                    };
+                    match sent {
+                        Ok(()) => (),
+                        Err(TrySendError::Full(())) => {
+                            runtime::sub(&state.runtime.queue_depth, 1);
+                            runtime::inc(&state.runtime.queue_dropped);
+                        }
+                        // The aggregator died, run() has its panic to report
+                        Err(TrySendError::Disconnected(())) => {
+                            runtime::sub(&state.runtime.queue_depth, 1);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) if offline && e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                info!("Finished reading {}", source);
+                break;
+            }
+            Err(e) if offline => panic!("Fail to read {}: {}", source, e),
+            Err(e) => {
+                runtime::inc(&state.runtime.receive_errors);
+                warn!(interface = source.as_str(); "Error receiving packet: {}", e);
+                if iface::find(interface_spec(opt, &source)).is_ok() {
+                    continue;
+                }
+                match opt.on_interface_loss {
+                    InterfaceLoss::Exit => {
+                        warn!(interface = source.as_str(); "Interface {} is gone, exiting", source);
+                        break;
+                    }
+                    InterfaceLoss::Wait => {
+                        warn!(interface = source.as_str(); "Interface {} is gone, waiting for it to come back", source);
+                        // Closes the socket, whatever its descriptor becomes is not ours to poll
+                        drop(rx);
+                        state.runtime.kernel.detach();
+                        rx = reopen_interface(opt, state, &source);
+                    }
                 }
-            _ => {
-                eprintln!("Not a IPv4 or IPv6 packet");
-                None
             }
         }
-    } else {
-        eprintln!("Fail to construct EthernetPacket: packet too small");
-        None
     }
 }
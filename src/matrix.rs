@@ -0,0 +1,54 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::net::IpAddr;
+
+use crate::data::Stats;
+use crate::top_hosts::{top_hosts, By};
+
+/// Most hosts /matrix takes, a grid has the square of that in cells
+pub const MAX_HOSTS: usize = 1000;
+
+/// Bytes from each to each of the `n` busiest hosts, as /top-hosts ranks them
+fn pairs(stats: &Stats, n: usize) -> (Vec<IpAddr>, HashMap<(IpAddr, IpAddr), u128>) {
+    let hosts: Vec<_> = top_hosts(stats, n, By::Bytes).into_iter().map(|h| h.host).collect();
+    let wanted: HashSet<_> = hosts.iter().copied().collect();
+    let mut pairs = HashMap::new();
+    for (k, v) in &stats.0 {
+        let pair = k.addrs();
+        if wanted.contains(&pair.0) && wanted.contains(&pair.1) {
+            *pairs.entry(pair).or_default() += v.total_length;
+        }
+    }
+    (hosts, pairs)
+}
+
+/// Sources down, destinations across, the busiest first both ways
+pub fn grid(stats: &Stats, n: usize) -> String {
+    let (hosts, pairs) = pairs(stats, n);
+    let mut csv = String::from("src\\dst");
+    for host in &hosts {
+        write!(csv, ",{}", host).unwrap();
+    }
+    csv.push('\n');
+    for source in &hosts {
+        write!(csv, "{}", source).unwrap();
+        for dest in &hosts {
+            write!(csv, ",{}", pairs.get(&(*source, *dest)).copied().unwrap_or(0)).unwrap();
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// src,dst,bytes, a row per pair that has traffic, the most bytes first
+pub fn long(stats: &Stats, n: usize) -> String {
+    let (_, pairs) = pairs(stats, n);
+    let mut pairs: Vec<_> = pairs.into_iter().collect();
+    pairs.sort_by_key(|((source, dest), bytes)| (Reverse(*bytes), *source, *dest));
+    let mut csv = String::from("src,dst,bytes\n");
+    for ((source, dest), bytes) in pairs {
+        writeln!(csv, "{},{},{}", source, dest, bytes).unwrap();
+    }
+    csv
+}
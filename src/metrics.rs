@@ -0,0 +1,157 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::iface;
+use crate::runtime::{self, SizeHistogram, Traffic, SIZE_BUCKETS, SIZE_PROTOCOLS};
+use crate::state::State;
+use crate::summary;
+
+pub const PROMETHEUS_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+pub const OPENMETRICS_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    /// Samples carry their _bucket, _sum or _count suffix ahead of the labels
+    Histogram,
+}
+
+/// One metric family. Names carry the unit but not the _total of counters,
+/// the classic format just gets it added to the family name too.
+struct Family {
+    name: &'static str,
+    kind: Kind,
+    unit: Option<&'static str>,
+    help: &'static str,
+    // (labels without braces, value)
+    samples: Vec<(String, f64)>,
+}
+
+fn family(name: &'static str, kind: Kind, unit: Option<&'static str>, help: &'static str, value: f64) -> Family {
+    Family { name, kind, unit, help, samples: vec![(String::new(), value)] }
+}
+
+fn load(counter: &AtomicU64) -> f64 {
+    counter.load(Ordering::Relaxed) as f64
+}
+
+/// Samples of one histogram, `histograms` adding up into it, `labels` on each
+fn histogram_samples<'a>(labels: &str, histograms: impl Iterator<Item = &'a SizeHistogram> + Clone) -> Vec<(String, f64)> {
+    let total = |f: fn(&SizeHistogram) -> &AtomicU64| histograms.clone().map(|h| load(f(h))).sum::<f64>();
+    let sep = if labels.is_empty() { "" } else { "," };
+    let mut samples = Vec::new();
+    let mut cumulative = 0.0;
+    for (i, bound) in SIZE_BUCKETS.iter().map(|b| b.to_string()).chain(["+Inf".to_string()]).enumerate() {
+        cumulative += histograms.clone().map(|h| load(&h.buckets[i])).sum::<f64>();
+        samples.push((format!("_bucket{{{}{}le=\"{}\"}}", labels, sep, bound), cumulative));
+    }
+    let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+    samples.push((format!("_sum{}", labels), total(|h| &h.sum)));
+    samples.push((format!("_count{}", labels), total(|h| &h.count)));
+    samples
+}
+
+fn families(state: &State, by_protocol: bool) -> Vec<Family> {
+    use Kind::*;
+
+    let r = &state.runtime;
+    let stats = state.published();
+    let by_cast = [("unicast", &r.casts.unicast), ("multicast", &r.casts.multicast), ("broadcast", &r.casts.broadcast)];
+    let casts = |f: fn(&Traffic) -> &AtomicU64| by_cast.iter()
+        .map(|(cast, t)| (format!("cast=\"{}\"", cast), load(f(t))))
+        .collect();
+    let by_tunnel = [("gre", &r.tunnels.gre), ("esp", &r.tunnels.esp)];
+    let tunnels = |f: fn(&Traffic) -> &AtomicU64| by_tunnel.iter()
+        .map(|(protocol, t)| (format!("protocol=\"{}\"", protocol), load(f(t))))
+        .collect();
+    let mut families = vec![
+        family("whoisthere_frames", Counter, None, "Frames read off the interface", load(&r.frames)),
+        family("whoisthere_frame_bytes", Counter, Some("bytes"), "Bytes of the frames read", load(&r.frame_bytes)),
+        family("whoisthere_jumbo_frames", Counter, None, "Frames over the standard 1500 bytes MTU", load(&r.jumbo_frames)),
+        family("whoisthere_truncated_frames", Counter, None, "Frames carrying less than their IP header claims", load(&r.truncated)),
+        family("whoisthere_padded_frames", Counter, None, "Frames carrying more than their IP header claims", load(&r.padded)),
+        family("whoisthere_malformed_frames", Counter, None, "Frames too short for their headers", load(&r.malformed)),
+        family("whoisthere_skipped_frames", Counter, None, "Frames of an EtherType left out of --ethertypes", load(&r.skipped)),
+        family("whoisthere_filtered_packets", Counter, None, "Packets left out by --filter, --mac, --vlan or --app-protocol", load(&r.filtered)),
+        family("whoisthere_receive_errors", Counter, None, "Failed reads from the capture channel", load(&r.receive_errors)),
+        family("whoisthere_save_errors", Counter, None, "Failed database saves", load(&r.save_errors)),
+        family("whoisthere_queue_dropped_updates", Counter, None, "Updates lost to a full queue", load(&r.queue_dropped)),
+        family("whoisthere_queue_depth", Gauge, None, "Updates waiting between capture and aggregation", load(&r.queue_depth)),
+        Family { name: "whoisthere_cast_frames", kind: Counter, unit: None, help: "Frames by kind of destination", samples: casts(|t| &t.frames) },
+        Family { name: "whoisthere_cast_bytes", kind: Counter, unit: Some("bytes"), help: "Frame bytes by kind of destination", samples: casts(|t| &t.bytes) },
+        Family { name: "whoisthere_tunnel_packets", kind: Counter, unit: None, help: "GRE and ESP packets", samples: tunnels(|t| &t.frames) },
+        Family { name: "whoisthere_tunnel_bytes", kind: Counter, unit: Some("bytes"), help: "IP bytes of GRE and ESP packets", samples: tunnels(|t| &t.bytes) },
+        family("whoisthere_flows", Gauge, None, "Flows in the map", stats.0.len() as f64),
+        family("whoisthere_flow_bytes", Counter, Some("bytes"), "IP bytes over all flows",
+               stats.0.values().map(|v| v.total_length as f64).sum()),
+        family("whoisthere_flow_packets", Counter, None, "Packets over all flows",
+               stats.0.values().map(|v| v.total_count as f64).sum()),
+        family("whoisthere_bits_per_second", Gauge, None, "Aggregate rate over all flows", summary::bps(&stats)),
+    ];
+    let sizes = &r.packet_sizes.0;
+    let samples = if by_protocol {
+        SIZE_PROTOCOLS.iter().zip(sizes)
+            .flat_map(|(protocol, h)| histogram_samples(&format!("protocol=\"{}\"", protocol), [h].into_iter()))
+            .collect()
+    } else {
+        histogram_samples("", sizes.iter())
+    };
+    families.push(Family {
+        name: "whoisthere_packet_size_bytes",
+        kind: Histogram,
+        unit: Some("bytes"),
+        help: "Sizes of the packets counted into flows, IP length",
+        samples,
+    });
+    if let Some(rss) = runtime::rss_bytes() {
+        families.push(family("whoisthere_resident_memory_bytes", Gauge, Some("bytes"), "Resident set size", rss as f64));
+    }
+    if let Some((packets, dropped)) = r.kernel.poll() {
+        families.push(family("whoisthere_kernel_packets", Counter, None, "Packets that reached the capture socket", packets as f64));
+        families.push(family("whoisthere_kernel_dropped_packets", Counter, None, "Packets the kernel dropped before capture read them", dropped as f64));
+    }
+    if let Some(dropped) = iface::dropped(&state.link) {
+        families.push(family("whoisthere_interface_dropped_packets", Counter, None, "Received packets the interface dropped", dropped as f64));
+    }
+    if let Some(speed) = state.link.speed_bps {
+        families.push(family("whoisthere_link_speed_bits_per_second", Gauge, None, "Negotiated speed of the interface", speed as f64));
+    }
+    families
+}
+
+/// Prometheus text exposition, or OpenMetrics with `openmetrics`. `by_protocol`
+/// has the packet size histogram split by protocol.
+pub fn render(state: &State, openmetrics: bool, by_protocol: bool) -> String {
+    let mut out = String::new();
+    for f in families(state, by_protocol) {
+        let kind = match f.kind {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram"
+        };
+        let sample = if f.kind == Kind::Counter { format!("{}_total", f.name) } else { f.name.to_string() };
+        let name = if openmetrics { f.name } else { &sample };
+        writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+        if let Some(unit) = f.unit.filter(|_| openmetrics) {
+            writeln!(out, "# UNIT {} {}", name, unit).unwrap();
+        }
+        writeln!(out, "# HELP {} {}", name, f.help).unwrap();
+        for (labels, value) in &f.samples {
+            if labels.is_empty() || f.kind == Kind::Histogram {
+                writeln!(out, "{}{} {}", sample, labels, value).unwrap();
+            } else {
+                writeln!(out, "{}{{{}}} {}", sample, labels, value).unwrap();
+            }
+        }
+    }
+    if openmetrics {
+        out.push_str("# EOF\n");
+    }
+    out
+}
+
+/// The client lists OpenMetrics in its Accept header
+pub fn wants_openmetrics(accept: Option<&str>) -> bool {
+    accept.is_some_and(|a| a.split(',').any(|t| t.trim().starts_with("application/openmetrics-text")))
+}
@@ -0,0 +1,154 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use log::{info, Level};
+use serde::Serialize;
+
+use crate::data::{unix_now, StatsKey};
+use crate::logging::WarnOnce;
+use crate::state::State;
+use crate::summary;
+
+const DEFAULT_PORT: u16 = 1883;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+
+#[derive(Serialize)]
+struct Talker<'a> {
+    flow: &'a StatsKey,
+    total_length: u128,
+    total_count: u128,
+    bps: f64,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    flows: usize,
+    total_length: u128,
+    total_count: u128,
+    bps: f64,
+    /// Busiest flows by bytes
+    top: Vec<Talker<'a>>,
+}
+
+fn string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Fixed header: type and flags, then the variable length remaining length
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![kind];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        buf.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    buf.extend_from_slice(body);
+    buf
+}
+
+/// Just enough MQTT 3.1.1 to publish at QoS 0
+pub struct MqttSink {
+    broker: String,
+    topic: String,
+    credentials: Option<(String, Option<String>)>,
+    top: usize,
+    stream: Option<TcpStream>,
+    failing: WarnOnce,
+}
+
+impl MqttSink {
+    pub fn new(broker: String, topic: String, credentials: Option<(String, Option<String>)>, top: usize) -> Self {
+        let broker = if broker.rsplit_once(':').is_some_and(|(_, p)| p.parse::<u16>().is_ok()) {
+            broker
+        } else {
+            format!("{}:{}", broker, DEFAULT_PORT)
+        };
+        MqttSink { broker, topic, credentials, top, stream: None, failing: WarnOnce::new(Level::Warn, module_path!()) }
+    }
+
+    fn connect(&mut self) -> io::Result<&mut TcpStream> {
+        if self.stream.is_none() {
+            let mut stream = TcpStream::connect(&self.broker)?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+
+            let mut body = Vec::new();
+            string(&mut body, "MQTT");
+            // Protocol level 4 is 3.1.1
+            body.push(4);
+            let mut flags = 0x02; // Clean session
+            if let Some((_, password)) = &self.credentials {
+                flags |= 0x80;
+                if password.is_some() {
+                    flags |= 0x40;
+                }
+            }
+            body.push(flags);
+            // No keep alive, the broker won't drop us between rounds
+            body.extend_from_slice(&0u16.to_be_bytes());
+            string(&mut body, &format!("whoisthere-{}", std::process::id()));
+            if let Some((username, password)) = &self.credentials {
+                string(&mut body, username);
+                if let Some(password) = password {
+                    string(&mut body, password);
+                }
+            }
+            stream.write_all(&packet(CONNECT, &body))?;
+
+            let mut connack = [0; 4];
+            stream.read_exact(&mut connack)?;
+            if connack[0] != CONNACK {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected CONNACK"));
+            }
+            if connack[3] != 0 {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("Broker refused connection, code {}", connack[3])));
+            }
+            info!("Connected to MQTT broker {}", self.broker);
+            self.stream = Some(stream);
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    pub fn publish(&mut self, state: &State) -> io::Result<()> {
+        let stats = state.published();
+        let s = summary::summary(&stats, &state.runtime, &state.link, state.bps_1m(), state.cps(), state.db.peak());
+        let now = unix_now();
+        let report = Report {
+            flows: s.flows,
+            total_length: s.total_length,
+            total_count: s.total_count,
+            bps: s.bps,
+            top: stats.top(self.top).into_iter().map(|(k, v)| Talker {
+                flow: k,
+                total_length: v.total_length,
+                total_count: v.total_count,
+                bps: v.rate.bits_per_sec(now),
+            }).collect(),
+        };
+        let payload = serde_json::to_vec(&report).unwrap_or_else(|e| panic!("Fail to serialize summary: {}", e));
+        let mut body = Vec::with_capacity(payload.len() + self.topic.len() + 2);
+        string(&mut body, &self.topic);
+        body.extend_from_slice(&payload);
+        let publish = packet(PUBLISH, &body);
+        self.connect()?.write_all(&publish)
+    }
+
+    /// Called every --mqtt-interval, a broken connection is dropped and made again next time
+    pub fn round(&mut self, state: &State) {
+        let published = self.publish(state);
+        let _ = self.failing.report(published, "publish to MQTT, retrying every round");
+        if self.failing.failing() {
+            self.stream = None;
+        }
+    }
+}
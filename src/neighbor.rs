@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::util::MacAddr;
+use serde::Serialize;
+
+use crate::data::unix_now;
+use crate::packet::PacketInfo;
+use crate::reader::Reader;
+
+/// Spoofed announcements shouldn't be able to eat all the memory
+const MAX_NEIGHBORS: usize = 65536;
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DHCPV6_CLIENT_PORT: u16 = 546;
+const DHCPV6_SERVER_PORT: u16 = 547;
+
+const DHCP_MAGIC: [u8; 4] = [99, 130, 83, 99];
+
+/// A host seen on the segment's control plane
+#[derive(Serialize, Default)]
+pub struct Neighbor {
+    pub mac: Option<String>,
+    /// Sent a router advertisement or was handed out as a DHCP gateway
+    pub router: bool,
+    pub hostname: Option<String>,
+    /// ndp, dhcp or dhcpv6, whichever spoke of it last
+    pub learned_from: &'static str,
+    /// Unix seconds
+    pub last_seen: u64,
+}
+
+#[derive(Serialize, Default)]
+pub struct Neighbors(pub HashMap<IpAddr, Neighbor>);
+
+impl Neighbors {
+    fn learn(&mut self, ip: IpAddr, from: &'static str) -> Option<&mut Neighbor> {
+        if ip.is_unspecified() || (!self.0.contains_key(&ip) && self.0.len() >= MAX_NEIGHBORS) {
+            return None;
+        }
+        let neighbor = self.0.entry(ip).or_default();
+        neighbor.learned_from = from;
+        neighbor.last_seen = unix_now();
+        Some(neighbor)
+    }
+
+    fn learn_mac(&mut self, ip: IpAddr, mac: Option<MacAddr>, from: &'static str) -> Option<&mut Neighbor> {
+        let neighbor = self.learn(ip, from)?;
+        if let Some(mac) = mac.filter(|m| !m.is_zero()) {
+            neighbor.mac = Some(mac.to_string());
+        }
+        Some(neighbor)
+    }
+}
+
+fn mac(bytes: &[u8]) -> Option<MacAddr> {
+    match *bytes {
+        [a, b, c, d, e, f] => Some(MacAddr::new(a, b, c, d, e, f)),
+        _ => None
+    }
+}
+
+fn ipv4(bytes: &[u8]) -> Option<Ipv4Addr> {
+    <[u8; 4]>::try_from(bytes).ok().map(Ipv4Addr::from)
+}
+
+fn ipv6(bytes: &[u8]) -> Option<Ipv6Addr> {
+    <[u8; 16]>::try_from(bytes).ok().map(Ipv6Addr::from)
+}
+
+/// Record what NDP and DHCP traffic tells about the hosts around
+pub fn observe(info: &PacketInfo, neighbors: &Mutex<Neighbors>) {
+    let (source, _) = info.key.addrs();
+    if info.protocol == IpNextHeaderProtocols::Icmpv6 {
+        ndp(source, info.source_mac, info.payload, neighbors);
+    } else if let Some(udp) = &info.udp {
+        match (udp.source_port, udp.dest_port) {
+            (DHCP_SERVER_PORT, DHCP_CLIENT_PORT) | (DHCP_CLIENT_PORT, DHCP_SERVER_PORT) =>
+                dhcp(info.payload, neighbors),
+            (DHCPV6_SERVER_PORT, DHCPV6_CLIENT_PORT) => dhcpv6(info.payload, neighbors),
+            _ => ()
+        }
+    }
+}
+
+/// The link-layer address option of the given type, NDP options come in 8 byte units
+fn ndp_lladdr(options: &[u8], wanted: u8) -> Option<MacAddr> {
+    let mut r = Reader(options);
+    while let (Some(kind), Some(units)) = (r.u8(), r.u8()) {
+        if units == 0 {
+            return None;
+        }
+        let data = r.take(units as usize * 8 - 2)?;
+        if kind == wanted {
+            return mac(data.get(..6)?);
+        }
+    }
+    None
+}
+
+fn ndp(source: IpAddr, source_mac: MacAddr, message: &[u8], neighbors: &Mutex<Neighbors>) {
+    const SOURCE_LLADDR: u8 = 1;
+    const TARGET_LLADDR: u8 = 2;
+
+    let kind = match message.first() {
+        Some(&k) => k,
+        None => return
+    };
+    match kind {
+        // Router solicitation
+        133 => if let Some(options) = message.get(8..) {
+            let lladdr = ndp_lladdr(options, SOURCE_LLADDR);
+            neighbors.lock().unwrap().learn_mac(source, lladdr, "ndp");
+        },
+        // Router advertisement
+        134 => if let Some(options) = message.get(16..) {
+            let lladdr = ndp_lladdr(options, SOURCE_LLADDR).or(Some(source_mac));
+            if let Some(n) = neighbors.lock().unwrap().learn_mac(source, lladdr, "ndp") {
+                n.router = true;
+            }
+        },
+        // Neighbor solicitation, only the source is known to be there
+        135 => if let Some(options) = message.get(24..) {
+            let lladdr = ndp_lladdr(options, SOURCE_LLADDR);
+            neighbors.lock().unwrap().learn_mac(source, lladdr, "ndp");
+        },
+        // Neighbor advertisement
+        136 => if let (Some(target), Some(options)) = (message.get(8..24).and_then(ipv6), message.get(24..)) {
+            let is_router = message[4] & 0x80 != 0;
+            let lladdr = ndp_lladdr(options, TARGET_LLADDR).or(Some(source_mac));
+            if let Some(n) = neighbors.lock().unwrap().learn_mac(target.into(), lladdr, "ndp") {
+                n.router |= is_router;
+            }
+        },
+        _ => ()
+    }
+}
+
+fn dhcp(message: &[u8], neighbors: &Mutex<Neighbors>) {
+    const REQUEST: u8 = 3;
+    const ACK: u8 = 5;
+
+    // Fixed BOOTP part then the magic cookie
+    if message.len() < 240 || message[236..240] != DHCP_MAGIC {
+        return;
+    }
+    let ciaddr = ipv4(&message[12..16]);
+    let yiaddr = ipv4(&message[16..20]);
+    // Ethernet, 6 bytes
+    let chaddr = if message[1] == 1 && message[2] == 6 { mac(&message[28..34]) } else { None };
+
+    let (mut kind, mut hostname, mut requested, mut routers) = (None, None, None, Vec::new());
+    let mut r = Reader(&message[240..]);
+    while let Some(code) = r.u8() {
+        match code {
+            0 => continue,
+            255 => break,
+            _ => ()
+        }
+        let data = match r.u8().and_then(|len| r.take(len as usize)) {
+            Some(d) => d,
+            None => break
+        };
+        match code {
+            3 => routers = data.chunks_exact(4).filter_map(ipv4).collect(),
+            12 => hostname = std::str::from_utf8(data).ok().map(str::to_string),
+            50 => requested = ipv4(data),
+            53 => kind = data.first().copied(),
+            _ => ()
+        }
+    }
+
+    let client = match kind {
+        Some(ACK) => yiaddr,
+        Some(REQUEST) => requested.or(ciaddr),
+        _ => None
+    };
+    let mut neighbors = neighbors.lock().unwrap();
+    if let Some(n) = client.and_then(|ip| neighbors.learn_mac(ip.into(), chaddr, "dhcp")) {
+        if hostname.is_some() {
+            n.hostname = hostname;
+        }
+    }
+    if kind == Some(ACK) {
+        for router in routers {
+            if let Some(n) = neighbors.learn(router.into(), "dhcp") {
+                n.router = true;
+            }
+        }
+    }
+}
+
+/// The MAC inside a DUID-LLT or DUID-LL of Ethernet hardware
+fn duid_mac(duid: &[u8]) -> Option<MacAddr> {
+    let mut r = Reader(duid);
+    let kind = r.u16()?;
+    let hardware = r.u16()?;
+    if hardware != 1 {
+        return None;
+    }
+    match kind {
+        1 => {
+            r.take(4)?;
+            mac(r.rest())
+        }
+        3 => mac(r.rest()),
+        _ => None
+    }
+}
+
+fn dhcpv6(message: &[u8], neighbors: &Mutex<Neighbors>) {
+    const REPLY: u8 = 7;
+    const CLIENT_ID: u16 = 1;
+    const IA_NA: u16 = 3;
+    const IAADDR: u16 = 5;
+
+    let mut r = Reader(message);
+    if r.u8() != Some(REPLY) || r.take(3).is_none() {
+        return;
+    }
+    let (mut client, mut addrs) = (None, Vec::new());
+    while let (Some(code), Some(len)) = (r.u16(), r.u16()) {
+        let data = match r.take(len as usize) {
+            Some(d) => d,
+            None => return
+        };
+        match code {
+            CLIENT_ID => client = duid_mac(data),
+            IA_NA => {
+                // IAID, T1 and T2, then options of its own
+                let mut ia = Reader(data.get(12..).unwrap_or(&[]));
+                while let (Some(code), Some(len)) = (ia.u16(), ia.u16()) {
+                    let data = match ia.take(len as usize) {
+                        Some(d) => d,
+                        None => break
+                    };
+                    if code == IAADDR {
+                        addrs.extend(data.get(..16).and_then(ipv6));
+                    }
+                }
+            }
+            _ => ()
+        }
+    }
+    let mut neighbors = neighbors.lock().unwrap();
+    for addr in addrs {
+        neighbors.learn_mac(addr.into(), client, "dhcpv6");
+    }
+}
@@ -0,0 +1,63 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use serde::{Serialize, Serializer};
+
+use crate::data::{Stats, StatsKey, StatsValue};
+
+/// A flow with nProbe's element names, as ntopng and other flow-JSON consumers take them.
+/// Flows here go one way, so it's all IN_ and no OUT_. The field set is stable, new
+/// fields may be added but none renamed or dropped.
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct NtopFlow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    IPV4_SRC_ADDR: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    IPV4_DST_ADDR: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    IPV6_SRC_ADDR: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    IPV6_DST_ADDR: Option<IpAddr>,
+    /// 0 with --granularity host, which doesn't keep ports or protocols
+    L4_SRC_PORT: u16,
+    L4_DST_PORT: u16,
+    PROTOCOL: u8,
+    /// Whole IP datagrams
+    IN_BYTES: u128,
+    IN_PKTS: u128,
+    /// Unix seconds
+    FIRST_SWITCHED: u64,
+    LAST_SWITCHED: u64,
+}
+
+impl NtopFlow {
+    fn of(k: &StatsKey, v: &StatsValue) -> Self {
+        let (source, dest) = k.addrs();
+        let v4 = source.is_ipv4();
+        let (protocol, source_port, dest_port) = k.1.map_or((0, 0, 0), |t| (t.protocol, t.source_port, t.dest_port));
+        NtopFlow {
+            IPV4_SRC_ADDR: v4.then_some(source),
+            IPV4_DST_ADDR: v4.then_some(dest),
+            IPV6_SRC_ADDR: (!v4).then_some(source),
+            IPV6_DST_ADDR: (!v4).then_some(dest),
+            L4_SRC_PORT: source_port,
+            L4_DST_PORT: dest_port,
+            PROTOCOL: protocol,
+            IN_BYTES: v.ip_bytes,
+            IN_PKTS: v.total_count,
+            FIRST_SWITCHED: v.first_seen,
+            LAST_SWITCHED: v.last_seen,
+        }
+    }
+}
+
+/// The flows of a snapshot as an array of `NtopFlow`
+pub struct NtopFlows(pub Arc<Stats>);
+
+impl Serialize for NtopFlows {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer {
+        serializer.collect_seq(self.0.0.iter().map(|(k, v)| NtopFlow::of(k, v)))
+    }
+}
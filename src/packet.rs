@@ -0,0 +1,386 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use either::Either;
+use log::debug;
+
+use pnet::packet::ethernet::{EtherType, EthernetPacket, EtherTypes};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
+use pnet::packet::udp::UdpPacket;
+use pnet::util::MacAddr;
+
+use crate::data::{Ipv4StatsKey, Ipv6StatsKey, StatsKey, TransportKey};
+use crate::runtime::{self, Runtime};
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERNET_MIN_FRAME_LEN: usize = 60;
+const ETHERNET_MTU: usize = 1500;
+pub(crate) const IPV6_HEADER_LEN: usize = 40;
+const TCP_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const VLAN_TAG_LEN: usize = 4;
+const PPPOE_HEADER_LEN: usize = 6;
+const PPP_IPV4: u16 = 0x0021;
+const PPP_IPV6: u16 = 0x0057;
+/// Real networks stack two, maybe three
+const MAX_VLAN_TAGS: usize = 8;
+
+pub struct TcpInfo {
+    pub source_port: u16,
+    pub dest_port: u16,
+    pub sequence: u32,
+    pub flags: u8,
+    /// As advertised, before any window scaling
+    pub window: u16,
+    /// The window scale option, only looked for in SYNs where it can be
+    pub window_scale: Option<u8>,
+}
+
+pub struct UdpInfo {
+    pub source_port: u16,
+    pub dest_port: u16,
+}
+
+/// ICMP or ICMPv6, the two share the layout
+pub struct IcmpInfo {
+    pub icmp_type: u8,
+    pub code: u8,
+}
+
+/// Kind of destination, ordered so the more spread one wins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Cast {
+    Unicast,
+    Multicast,
+    Broadcast,
+}
+
+impl Cast {
+    fn of_mac(mac: MacAddr) -> Self {
+        if mac.is_broadcast() {
+            Cast::Broadcast
+        } else if mac.is_multicast() {
+            Cast::Multicast
+        } else {
+            Cast::Unicast
+        }
+    }
+
+    fn of_ipv4(ip: Ipv4Addr) -> Self {
+        if ip.is_broadcast() {
+            Cast::Broadcast
+        } else if ip.is_multicast() {
+            Cast::Multicast
+        } else {
+            Cast::Unicast
+        }
+    }
+
+    // IPv6 has no broadcast, all-nodes multicast does that job
+    fn of_ipv6(ip: Ipv6Addr) -> Self {
+        if ip.is_multicast() { Cast::Multicast } else { Cast::Unicast }
+    }
+}
+
+fn count_cast(runtime: &Runtime, cast: Cast, frame_len: usize) {
+    let traffic = match cast {
+        Cast::Unicast => &runtime.casts.unicast,
+        Cast::Multicast => &runtime.casts.multicast,
+        Cast::Broadcast => &runtime.casts.broadcast,
+    };
+    runtime::inc(&traffic.frames);
+    runtime::add(&traffic.bytes, frame_len as u64);
+}
+
+fn count_tunnel(runtime: &Runtime, info: &PacketInfo) {
+    let traffic = match info.protocol {
+        IpNextHeaderProtocols::Gre => &runtime.tunnels.gre,
+        IpNextHeaderProtocols::Esp => &runtime.tunnels.esp,
+        _ => return
+    };
+    runtime::inc(&traffic.frames);
+    runtime::add(&traffic.bytes, info.ip_length as u64);
+}
+
+/// The Ethernet frame check sequence, last in the frame
+pub const FCS_LEN: usize = 4;
+
+/// What the capture loop cares about in a frame
+pub struct PacketInfo<'a> {
+    pub key: StatsKey,
+    /// What flows count as their length: the IPv4 total length, the IPv6 payload length
+    pub length: u128,
+    /// The whole IP datagram, header included
+    pub ip_length: u128,
+    /// The frame from the Ethernet header on, FCS excluded: most captures never have it,
+    /// --fcs-included cuts it off those that do.
+    /// Frames cut by --snaplen count what they claimed to carry.
+    pub wire_length: u128,
+    /// What the IP header claims past the end of the frame, 0 unless truncated
+    pub missing: u128,
+    pub cast: Cast,
+    pub source_mac: MacAddr,
+    pub dest_mac: MacAddr,
+    pub vlans: Vlans,
+    pub protocol: IpNextHeaderProtocol,
+    pub tcp: Option<TcpInfo>,
+    pub udp: Option<UdpInfo>,
+    pub icmp: Option<IcmpInfo>,
+    /// Transport layer payload (the whole message for ICMPv6), empty if the transport isn't understood
+    pub payload: &'a [u8],
+}
+
+impl PacketInfo<'_> {
+    pub fn transport_key(&self) -> TransportKey {
+        let (source_port, dest_port) = match (&self.tcp, &self.udp) {
+            (Some(tcp), _) => (tcp.source_port, tcp.dest_port),
+            (None, Some(udp)) => (udp.source_port, udp.dest_port),
+            (None, None) => (0, 0)
+        };
+        TransportKey { protocol: self.protocol.0, source_port, dest_port }
+    }
+}
+
+/// 802.1Q / 802.1ad tags the frame went through
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Vlans {
+    pub outer: Option<u16>,
+    /// The innermost one when stacked, None on single tagged frames
+    pub inner: Option<u16>,
+}
+
+fn is_vlan(ethertype: EtherType) -> bool {
+    matches!(ethertype, EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ)
+}
+
+// Follows the tags down to the real ethertype. None when the stack is cut short or absurdly deep.
+fn strip_vlans(mut ethertype: EtherType, mut payload: &[u8]) -> Option<(EtherType, &[u8], Vlans)> {
+    let mut vlans = Vlans::default();
+    for depth in 0..=MAX_VLAN_TAGS {
+        if !is_vlan(ethertype) {
+            return Some((ethertype, payload, vlans));
+        }
+        if depth == MAX_VLAN_TAGS || payload.len() < VLAN_TAG_LEN {
+            return None;
+        }
+        let id = u16::from_be_bytes([payload[0], payload[1]]) & 0x0fff;
+        if vlans.outer.is_none() {
+            vlans.outer = Some(id);
+        } else {
+            vlans.inner = Some(id);
+        }
+        ethertype = EtherType(u16::from_be_bytes([payload[2], payload[3]]));
+        payload = &payload[VLAN_TAG_LEN..];
+    }
+    None
+}
+
+// PPPoE session frames carry IP behind the PPPoE and PPP headers. Anything else,
+// LCP and friends included, is left as is for the caller to not understand.
+fn strip_pppoe(ethertype: EtherType, payload: &[u8]) -> (EtherType, &[u8]) {
+    if ethertype != EtherTypes::PppoeSession || payload.len() < PPPOE_HEADER_LEN + 2 {
+        return (ethertype, payload);
+    }
+    // Counts the PPP protocol field too
+    let len = u16::from_be_bytes([payload[4], payload[5]]) as usize;
+    let end = (PPPOE_HEADER_LEN + len).min(payload.len());
+    let inner = match u16::from_be_bytes([payload[6], payload[7]]) {
+        PPP_IPV4 => EtherTypes::Ipv4,
+        PPP_IPV6 => EtherTypes::Ipv6,
+        _ => return (ethertype, payload)
+    };
+    (inner, payload.get(PPPOE_HEADER_LEN + 2..end).unwrap_or(&[]))
+}
+
+// Frame length vs what the IP header says it carries
+fn check_lengths(runtime: &Runtime, frame_len: usize, header_len: usize, ip_len: usize) {
+    let payload_len = frame_len.saturating_sub(header_len);
+    if payload_len > ETHERNET_MTU {
+        runtime::inc(&runtime.jumbo_frames);
+    }
+    if ip_len > payload_len {
+        runtime::inc(&runtime.truncated);
+    } else if ip_len < payload_len && frame_len > ETHERNET_MIN_FRAME_LEN {
+        runtime::inc(&runtime.padded);
+    }
+}
+
+struct Transport<'a> {
+    tcp: Option<TcpInfo>,
+    udp: Option<UdpInfo>,
+    icmp: Option<IcmpInfo>,
+    payload: &'a [u8],
+}
+
+/// None when cut short of type and code
+fn icmp(message: &[u8]) -> Option<IcmpInfo> {
+    match *message {
+        [icmp_type, code, ..] => Some(IcmpInfo { icmp_type, code }),
+        _ => None
+    }
+}
+
+/// Shift count of the window scale option, if the options have one
+fn window_scale(mut options: &[u8]) -> Option<u8> {
+    const END: u8 = 0;
+    const NOP: u8 = 1;
+    const WINDOW_SCALE: u8 = 3;
+    loop {
+        match *options {
+            [] | [END, ..] => return None,
+            [NOP, ref rest @ ..] => options = rest,
+            [WINDOW_SCALE, 3, shift, ..] => return Some(shift.min(14)),
+            [_, len, ..] if len >= 2 => options = options.get(len as usize..)?,
+            _ => return None
+        }
+    }
+}
+
+fn transport(protocol: IpNextHeaderProtocol, ip_payload: &[u8]) -> Transport<'_> {
+    let none = Transport { tcp: None, udp: None, icmp: None, payload: &[] };
+    match protocol {
+        IpNextHeaderProtocols::Tcp => match TcpPacket::new(ip_payload) {
+            Some(p) => {
+                let header_len = (p.get_data_offset() as usize * 4).min(ip_payload.len());
+                Transport {
+                    tcp: Some(TcpInfo {
+                        source_port: p.get_source(),
+                        dest_port: p.get_destination(),
+                        sequence: p.get_sequence(),
+                        flags: p.get_flags(),
+                        window: p.get_window(),
+                        window_scale: if p.get_flags() & TcpFlags::SYN != 0 {
+                            window_scale(ip_payload.get(TCP_HEADER_LEN..header_len).unwrap_or(&[]))
+                        } else {
+                            None
+                        },
+                    }),
+                    payload: &ip_payload[header_len..],
+                    ..none
+                }
+            }
+            None => none
+        },
+        IpNextHeaderProtocols::Udp => match UdpPacket::new(ip_payload) {
+            Some(p) => Transport {
+                udp: Some(UdpInfo { source_port: p.get_source(), dest_port: p.get_destination() }),
+                payload: &ip_payload[UDP_HEADER_LEN..],
+                ..none
+            },
+            None => none
+        },
+        IpNextHeaderProtocols::Icmp => Transport { icmp: icmp(ip_payload), ..none },
+        IpNextHeaderProtocols::Icmpv6 => Transport { icmp: icmp(ip_payload), payload: ip_payload, ..none },
+        _ => none
+    }
+}
+
+/// --ethertypes names, also taking numbers like 0x88cc
+pub fn parse_ethertype(s: &str) -> Result<EtherType, String> {
+    match s {
+        "ipv4" => Ok(EtherTypes::Ipv4),
+        "ipv6" => Ok(EtherTypes::Ipv6),
+        "arp" => Ok(EtherTypes::Arp),
+        _ => s.strip_prefix("0x")
+            .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+            .map(EtherType)
+            .ok_or(format!("Unknown EtherType: {}", s))
+    }
+}
+
+/// `ethertypes` are the ones to look into, under any VLAN tags and PPPoE, empty for all
+pub fn proc_packet<'a>(packet: &'a [u8], runtime: &Runtime, ethertypes: &[EtherType]) -> Option<PacketInfo<'a>> {
+    runtime::inc(&runtime.frames);
+    runtime::add(&runtime.frame_bytes, packet.len() as u64);
+    if let Some(eth_packet) = EthernetPacket::new(packet) {
+        let mac_cast = Cast::of_mac(eth_packet.get_destination());
+        let (ethertype, eth_payload, vlans) = match strip_vlans(eth_packet.get_ethertype(), &packet[ETHERNET_HEADER_LEN..]) {
+            Some(t) => t,
+            None => {
+                debug!("Fail to strip VLAN tags: truncated or too deep");
+                runtime::inc(&runtime.malformed);
+                count_cast(runtime, mac_cast, packet.len());
+                return None;
+            }
+        };
+        let (ethertype, eth_payload) = strip_pppoe(ethertype, eth_payload);
+        if !ethertypes.is_empty() && !ethertypes.contains(&ethertype) {
+            runtime::inc(&runtime.skipped);
+            count_cast(runtime, mac_cast, packet.len());
+            return None;
+        }
+        let header_len = packet.len() - eth_payload.len();
+        let info = match ethertype {
+            EtherTypes::Ipv4 =>
+                if let Some(p) = Ipv4Packet::new(eth_payload) {
+                    check_lengths(runtime, packet.len(), header_len, p.get_total_length() as usize);
+                    let ip_header_len = p.get_header_length() as usize * 4;
+                    let end = (p.get_total_length() as usize).min(eth_payload.len());
+                    let t = transport(p.get_next_level_protocol(), eth_payload.get(ip_header_len..end).unwrap_or(&[]));
+                    Some(PacketInfo {
+                        key: StatsKey(Either::Left(Ipv4StatsKey { source: p.get_source(), dest: p.get_destination() }), None),
+                        length: p.get_total_length() as u128,
+                        ip_length: p.get_total_length() as u128,
+                        wire_length: packet.len().max(header_len + p.get_total_length() as usize) as u128,
+                        missing: (header_len + p.get_total_length() as usize).saturating_sub(packet.len()) as u128,
+                        cast: mac_cast.max(Cast::of_ipv4(p.get_destination())),
+                        source_mac: eth_packet.get_source(),
+                        dest_mac: eth_packet.get_destination(),
+                        vlans,
+                        protocol: p.get_next_level_protocol(),
+                        tcp: t.tcp,
+                        udp: t.udp,
+                        icmp: t.icmp,
+                        payload: t.payload,
+                    })
+                } else {
+                    debug!("Fail to construct Ipv4Packet: packet too small");
+                    runtime::inc(&runtime.malformed);
+                    None
+                }
+            // No, the fact is they are different fundamentally so no polymorphism here sorry
+            EtherTypes::Ipv6 =>
+                if let Some(p) = Ipv6Packet::new(eth_payload) {
+                    check_lengths(runtime, packet.len(), header_len, IPV6_HEADER_LEN + p.get_payload_length() as usize);
+                    let end = (IPV6_HEADER_LEN + p.get_payload_length() as usize).min(eth_payload.len());
+                    // Extension headers are not followed, those packets just don't get a transport
+                    let t = transport(p.get_next_header(), &eth_payload[IPV6_HEADER_LEN..end]);
+                    Some(PacketInfo {
+                        key: StatsKey(Either::Right(Ipv6StatsKey { source: p.get_source(), dest: p.get_destination() }), None),
+                        length: p.get_payload_length() as u128,
+                        ip_length: (IPV6_HEADER_LEN + p.get_payload_length() as usize) as u128,
+                        wire_length: packet.len().max(header_len + IPV6_HEADER_LEN + p.get_payload_length() as usize) as u128,
+                        missing: (header_len + IPV6_HEADER_LEN + p.get_payload_length() as usize).saturating_sub(packet.len()) as u128,
+                        cast: mac_cast.max(Cast::of_ipv6(p.get_destination())),
+                        source_mac: eth_packet.get_source(),
+                        dest_mac: eth_packet.get_destination(),
+                        vlans,
+                        protocol: p.get_next_header(),
+                        tcp: t.tcp,
+                        udp: t.udp,
+                        icmp: t.icmp,
+                        payload: t.payload,
+                    })
+                } else {
+                    debug!("Fail to construct Ipv6Packet: packet too small");
+                    runtime::inc(&runtime.malformed);
+                    None
+                }
+            _ => {
+                debug!("Not a IPv4 or IPv6 packet");
+                None
+            }
+        };
+        count_cast(runtime, info.as_ref().map_or(mac_cast, |i| i.cast), packet.len());
+        if let Some(info) = &info {
+            count_tunnel(runtime, info);
+        }
+        info
+    } else {
+        debug!("Fail to construct EthernetPacket: packet too small");
+        runtime::inc(&runtime.malformed);
+        None
+    }
+}
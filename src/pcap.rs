@@ -0,0 +1,691 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use flate2::bufread::MultiGzDecoder;
+use log::{info, warn};
+use pnet::datalink::DataLinkReceiver;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+pub(crate) const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+pub(crate) const LINKTYPE_ETHERNET: u32 = 1;
+/// BSD loopback, the address family in the byte order of whoever captured it
+const LINKTYPE_NULL: u32 = 0;
+/// Raw IP, version by the first nibble
+const LINKTYPE_RAW: u32 = 101;
+/// LINKTYPE_NULL with the family in network byte order
+const LINKTYPE_LOOP: u32 = 108;
+/// tcpdump -i any
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+/// What tcpdump caps snaplen at, anything bigger is a corrupt record
+pub(crate) const MAX_SNAPLEN: usize = 262144;
+
+// pcapng block types
+const SECTION_HEADER: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const OBSOLETE_PACKET: u32 = 2;
+const SIMPLE_PACKET: u32 = 3;
+const ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+/// Microseconds, what if_tsresol defaults to
+const DEFAULT_TSRESOL: u8 = 6;
+/// Generous for option heavy blocks, still catches garbage lengths
+const MAX_BLOCK: usize = 16 << 20;
+
+/// An interface of a pcapng section
+struct Interface {
+    name: String,
+    linktype: u32,
+    /// Timestamp units, as if_tsresol has it
+    tsresol: u8,
+    frames: u64,
+    /// Of a link type `to_ethernet` doesn't know, so left out
+    skipped: u64,
+}
+
+enum Format {
+    Classic,
+    /// Interfaces of the current section, by id
+    Ng(Vec<Interface>),
+}
+
+/// Classic pcap or pcapng file, gunzipped on the fly when it's gzip. Frames of the other
+/// link types `to_ethernet` knows come out as Ethernet, each by its own interface's type.
+pub struct PcapReader {
+    /// The path, for messages
+    name: String,
+    input: Box<dyn Read + Send>,
+    format: Format,
+    big_endian: bool,
+    /// Classic pcap with nanoseconds rather than microseconds
+    nanos: bool,
+    /// Of classic pcap, pcapng has one per interface
+    linktype: u32,
+    buf: Vec<u8>,
+    /// Frames of other link types as Ethernet
+    frame: Vec<u8>,
+    /// Unix microseconds of the last packet read, see `clock`
+    clock: Arc<AtomicU64>,
+    /// How long the last packet read was on the wire, see `original_length`
+    original_length: Arc<AtomicU64>,
+    /// The file ended between two records, not inside one
+    clean_end: bool,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl PcapReader {
+    /// `-` reads standard input, as it comes in, until the writer closes it
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if path == Path::new("-") {
+            return Self::new(BufReader::new(io::stdin()), "standard input");
+        }
+        Self::new(BufReader::new(File::open(path)?), &path.display().to_string())
+    }
+
+    fn new(mut file: impl BufRead + Send + 'static, name: &str) -> io::Result<Self> {
+        // By magic rather than extension, .pcap.gz renamed to .pcap still works
+        let input: Box<dyn Read + Send> = if file.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Box::new(MultiGzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut reader = PcapReader {
+            name: name.to_string(),
+            input,
+            format: Format::Classic,
+            big_endian: false,
+            nanos: false,
+            linktype: LINKTYPE_ETHERNET,
+            buf: Vec::new(),
+            frame: Vec::new(),
+            clock: Arc::default(),
+            original_length: Arc::default(),
+            clean_end: false,
+        };
+
+        let mut magic = [0; 4];
+        reader.input.read_exact(&mut magic)?;
+        // Reads the same both ways round
+        if u32::from_le_bytes(magic) == SECTION_HEADER {
+            reader.format = Format::Ng(Vec::new());
+            reader.section()?;
+            return Ok(reader);
+        }
+
+        let mut header = [0; 20];
+        reader.input.read_exact(&mut header)?;
+        reader.big_endian = match u32::from_le_bytes(magic) {
+            MAGIC_MICROS | MAGIC_NANOS => false,
+            m if m.swap_bytes() == MAGIC_MICROS || m.swap_bytes() == MAGIC_NANOS => true,
+            _ => return Err(invalid(format!("{} is neither pcap nor pcapng", name)))
+        };
+        reader.nanos = [MAGIC_NANOS, MAGIC_NANOS.swap_bytes()].contains(&u32::from_le_bytes(magic));
+        reader.linktype = reader.u32(&header[16..20]);
+        if !known_linktype(reader.linktype) {
+            return Err(invalid(format!("Unsupported link type {}, only Ethernet, raw IP, Linux cooked and BSD loopback are",
+                                       reader.linktype)));
+        }
+        Ok(reader)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// When the last packet read was captured, going by the file, in unix microseconds.
+    /// Left as it was by pcapng's simple packets, which have no timestamp.
+    pub fn clock(&self) -> Arc<AtomicU64> {
+        self.clock.clone()
+    }
+
+    /// How long the last packet read was before whoever captured it cut it short, as long as
+    /// what was kept when it wasn't
+    pub fn original_length(&self) -> Arc<AtomicU64> {
+        self.original_length.clone()
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    /// The start of a record, noting whether the file ends right before it
+    fn record_start(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.input.read(&mut buf[read..]) {
+                Ok(0) => {
+                    self.clean_end = read == 0;
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(())
+    }
+
+    /// Rest of a section header block, its type already read. Each section picks its own byte order.
+    fn section(&mut self) -> io::Result<()> {
+        let mut header = [0; 8];
+        self.input.read_exact(&mut header)?;
+        self.big_endian = match u32::from_le_bytes(header[4..8].try_into().unwrap()) {
+            BYTE_ORDER_MAGIC => false,
+            m if m.swap_bytes() == BYTE_ORDER_MAGIC => true,
+            _ => return Err(invalid("Bad pcapng byte order magic".to_string()))
+        };
+        let len = self.u32(&header[0..4]) as usize;
+        self.body(len.checked_sub(16).ok_or(invalid("Section header block too short".to_string()))?)?;
+        self.report();
+        self.format = Format::Ng(Vec::new());
+        Ok(())
+    }
+
+    /// Block body into buf, then the trailing copy of the length
+    fn body(&mut self, len: usize) -> io::Result<()> {
+        if len > MAX_BLOCK {
+            return Err(invalid(format!("Block of {} bytes, file corrupt?", len)));
+        }
+        self.buf.resize(len, 0);
+        self.input.read_exact(&mut self.buf)?;
+        self.input.read_exact(&mut [0; 4])
+    }
+
+    fn interface(&self, body: &[u8]) -> io::Result<Interface> {
+        if body.len() < 8 {
+            return Err(invalid("Interface description block too short".to_string()));
+        }
+        let mut name = None;
+        let mut tsresol = DEFAULT_TSRESOL;
+        let mut options = &body[8..];
+        while options.len() >= 4 {
+            let (code, len) = (self.u16(&options[0..2]), self.u16(&options[2..4]) as usize);
+            let value = match options.get(4..4 + len) {
+                Some(v) => v,
+                None => break
+            };
+            match code {
+                IF_NAME => name = Some(String::from_utf8_lossy(value).trim_end_matches('\0').to_string()),
+                IF_TSRESOL if !value.is_empty() => tsresol = value[0],
+                _ => ()
+            }
+            options = options.get(4 + len.next_multiple_of(4)..).unwrap_or(&[]);
+        }
+        Ok(Interface { name: name.unwrap_or_default(), linktype: self.u16(&body[0..2]) as u32, tsresol, frames: 0, skipped: 0 })
+    }
+
+    /// Next packet of a pcapng file, as its interface's link type and a range of buf
+    fn ng_packet(&mut self) -> io::Result<(u32, usize, usize)> {
+        loop {
+            let mut header = [0; 4];
+            self.record_start(&mut header)?;
+            if u32::from_le_bytes(header) == SECTION_HEADER {
+                self.section()?;
+                continue;
+            }
+            let kind = self.u32(&header);
+            self.input.read_exact(&mut header)?;
+            let len = self.u32(&header) as usize;
+            self.body(len.checked_sub(12).ok_or(invalid(format!("Block of type {} too short", kind)))?)?;
+            let body = &self.buf;
+            // Interface id, where the data starts, how much of it there is and how much there was
+            let (id, start, captured, original): (usize, usize, usize, usize) = match kind {
+                INTERFACE_DESCRIPTION => {
+                    let interface = self.interface(body)?;
+                    if let Format::Ng(interfaces) = &mut self.format {
+                        interfaces.push(interface);
+                    }
+                    continue;
+                }
+                ENHANCED_PACKET if body.len() >= 20 =>
+                    (self.u32(&body[0..4]) as usize, 20, self.u32(&body[12..16]) as usize, self.u32(&body[16..20]) as usize),
+                OBSOLETE_PACKET if body.len() >= 20 =>
+                    (self.u16(&body[0..2]) as usize, 20, self.u32(&body[12..16]) as usize, self.u32(&body[16..20]) as usize),
+                // Snapped to the interface's snaplen, whatever is there is the frame
+                SIMPLE_PACKET if body.len() >= 4 => {
+                    let original = self.u32(&body[0..4]) as usize;
+                    (0, 4, original.min(body.len() - 4), original)
+                }
+                ENHANCED_PACKET | OBSOLETE_PACKET | SIMPLE_PACKET =>
+                    return Err(invalid(format!("Packet block of type {} too short", kind))),
+                // Name resolution, statistics and the like
+                _ => continue
+            };
+            // Simple packets have none
+            let ticks = (kind != SIMPLE_PACKET).then(|| (self.u32(&body[4..8]) as u64) << 32 | self.u32(&body[8..12]) as u64);
+            let end = start.checked_add(captured).filter(|end| *end <= body.len())
+                .ok_or(invalid(format!("Packet of {} bytes overruns its block", captured)))?;
+            let interface = match &mut self.format {
+                Format::Ng(interfaces) => interfaces.get_mut(id)
+                    .ok_or(invalid(format!("Packet on undescribed interface {}", id)))?,
+                Format::Classic => unreachable!()
+            };
+            if !known_linktype(interface.linktype) {
+                interface.skipped += 1;
+                continue;
+            }
+            interface.frames += 1;
+            if let Some(ticks) = ticks {
+                self.clock.store(micros(ticks, interface.tsresol), Ordering::Relaxed);
+            }
+            self.original_length.store(original.max(captured) as u64, Ordering::Relaxed);
+            return Ok((interface.linktype, start, end));
+        }
+    }
+
+    /// Next frame, as its link type and a range of buf
+    fn record(&mut self) -> io::Result<(u32, usize, usize)> {
+        if let Format::Ng(_) = self.format {
+            return self.ng_packet();
+        }
+        // Seconds, fraction, captured length, original length
+        let mut header = [0; 16];
+        self.record_start(&mut header)?;
+        let len = self.u32(&header[8..12]) as usize;
+        self.original_length.store(self.u32(&header[12..16]).max(len as u32) as u64, Ordering::Relaxed);
+        let fraction = self.u32(&header[4..8]) as u64;
+        let fraction = if self.nanos { fraction / 1000 } else { fraction };
+        self.clock.store(self.u32(&header[0..4]) as u64 * 1_000_000 + fraction, Ordering::Relaxed);
+        if len > MAX_SNAPLEN {
+            return Err(invalid(format!("Record of {} bytes, file corrupt?", len)));
+        }
+        self.buf.resize(len, 0);
+        self.input.read_exact(&mut self.buf)?;
+        Ok((self.linktype, 0, len))
+    }
+
+    /// Frames per interface of the section just read, when there's more than one or some got left out
+    fn report(&self) {
+        if let Format::Ng(interfaces) = &self.format {
+            if interfaces.len() < 2 && interfaces.iter().all(|i| i.skipped == 0) {
+                return;
+            }
+            for (id, i) in interfaces.iter().enumerate() {
+                info!(interface = id, name = i.name.as_str(), linktype = i.linktype, frames = i.frames, skipped = i.skipped;
+                      "Interface {} {}: {} frames, {} skipped for their link type {}", id, i.name, i.frames, i.skipped, i.linktype);
+            }
+        }
+    }
+}
+
+fn known_linktype(linktype: u32) -> bool {
+    [LINKTYPE_ETHERNET, LINKTYPE_NULL, LINKTYPE_RAW, LINKTYPE_LOOP, LINKTYPE_LINUX_SLL, LINKTYPE_IPV4, LINKTYPE_IPV6,
+     LINKTYPE_LINUX_SLL2].contains(&linktype)
+}
+
+/// A frame of a `known_linktype` other than Ethernet into `out` as one, with the sender's MAC
+/// where the link header has it and zeros otherwise. Empty when it's too short for its header.
+fn to_ethernet(linktype: u32, data: &[u8], out: &mut Vec<u8>) {
+    let ip_version = |packet: &[u8]| match packet.first().map(|b| b >> 4) {
+        Some(4) => ETHERTYPE_IPV4,
+        Some(6) => ETHERTYPE_IPV6,
+        _ => 0
+    };
+    // Linux packet types: to us, broadcast, multicast, to someone else, from us
+    let dest = |packet_type: u16| match packet_type {
+        1 => [0xff; 6],
+        2 => [0x01, 0x00, 0x5e, 0, 0, 0],
+        _ => [0; 6]
+    };
+    let header = match linktype {
+        LINKTYPE_NULL | LINKTYPE_LOOP => data.get(..4).map(|family| {
+            let family = family.try_into().unwrap();
+            // A family is small, read the wrong way round it isn't
+            let family = match linktype {
+                LINKTYPE_LOOP => u32::from_be_bytes(family),
+                _ => u32::from_le_bytes(family).min(u32::from_be_bytes(family))
+            };
+            // IPv6 is 24, 28 or 30 depending on the BSD
+            let ethertype = match family {
+                2 => ETHERTYPE_IPV4,
+                24 | 28 | 30 => ETHERTYPE_IPV6,
+                _ => 0
+            };
+            ([0; 6], [0; 6], ethertype, 4)
+        }),
+        LINKTYPE_RAW => Some(([0; 6], [0; 6], ip_version(data), 0)),
+        LINKTYPE_IPV4 => Some(([0; 6], [0; 6], ETHERTYPE_IPV4, 0)),
+        LINKTYPE_IPV6 => Some(([0; 6], [0; 6], ETHERTYPE_IPV6, 0)),
+        LINKTYPE_LINUX_SLL => data.get(..16).map(|h| {
+            let packet_type = u16::from_be_bytes([h[0], h[1]]);
+            (dest(packet_type), h[6..12].try_into().unwrap(), u16::from_be_bytes([h[14], h[15]]), 16)
+        }),
+        LINKTYPE_LINUX_SLL2 => data.get(..20).map(|h| {
+            (dest(h[10] as u16), h[12..18].try_into().unwrap(), u16::from_be_bytes([h[0], h[1]]), 20)
+        }),
+        _ => None
+    };
+    out.clear();
+    if let Some((dest, source, ethertype, len)) = header {
+        out.extend_from_slice(&dest);
+        out.extend_from_slice(&source);
+        out.extend_from_slice(&ethertype.to_be_bytes());
+        out.extend_from_slice(&data[len..]);
+    }
+}
+
+/// pcapng timestamp units to microseconds: `tsresol` is a power of 10, or of 2 with the top bit set
+fn micros(ticks: u64, tsresol: u8) -> u64 {
+    let per_sec = match tsresol {
+        r if r & 0x80 == 0 => 10f64.powi(r as i32),
+        r => 2f64.powi((r & 0x7f) as i32)
+    };
+    (ticks as f64 / per_sec * 1e6) as u64
+}
+
+/// Frames one by one like a live channel, UnexpectedEof at the end of the file. A file cut
+/// off inside its last record, e.g. copied while still being written, ends there with a warning.
+impl DataLinkReceiver for PcapReader {
+    fn next(&mut self) -> io::Result<&[u8]> {
+        match self.record() {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.report();
+                if !self.clean_end {
+                    warn!("{} ends in the middle of a record, counting only the frames before it", self.name);
+                }
+                Err(e)
+            }
+            Err(e) => Err(e),
+            Ok((LINKTYPE_ETHERNET, start, end)) => Ok(&self.buf[start..end]),
+            Ok((linktype, start, end)) => {
+                to_ethernet(linktype, &self.buf[start..end], &mut self.frame);
+                // Whatever the link header took or the Ethernet one adds, was on the wire too
+                let original = self.original_length.load(Ordering::Relaxed).saturating_sub((end - start) as u64);
+                self.original_length.store(original + self.frame.len() as u64, Ordering::Relaxed);
+                Ok(&self.frame)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::*;
+
+    const FRAME: [u8; 20] = [2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1, 0x08, 0, 0x45, 0, 0, 6, 0, 0];
+
+    fn u32s(big_endian: bool, values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() }).collect()
+    }
+
+    /// (seconds, fraction, data, original length) each
+    fn classic(big_endian: bool, magic: u32, linktype: u32, records: &[(u32, u32, &[u8], u32)]) -> Vec<u8> {
+        let mut file = u32s(big_endian, &[magic]);
+        file.extend_from_slice(&if big_endian { [0, 2, 0, 4] } else { [2, 0, 4, 0] });
+        file.extend(u32s(big_endian, &[0, 0, 65535, linktype]));
+        for (secs, fraction, data, original) in records {
+            file.extend(u32s(big_endian, &[*secs, *fraction, data.len() as u32, *original]));
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    fn block(big_endian: bool, kind: u32, body: &[u8]) -> Vec<u8> {
+        let mut body = body.to_vec();
+        body.resize(body.len().next_multiple_of(4), 0);
+        let len = 12 + body.len() as u32;
+        let mut block = u32s(big_endian, &[kind, len]);
+        block.extend(body);
+        block.extend(u32s(big_endian, &[len]));
+        block
+    }
+
+    fn section(big_endian: bool) -> Vec<u8> {
+        let mut body = u32s(big_endian, &[BYTE_ORDER_MAGIC]);
+        body.extend_from_slice(&if big_endian { [0, 1, 0, 0] } else { [1, 0, 0, 0] });
+        body.extend_from_slice(&[0xff; 8]);
+        block(big_endian, SECTION_HEADER, &body)
+    }
+
+    /// With `tsresol` as its if_tsresol option when given
+    fn interface(big_endian: bool, linktype: u16, tsresol: Option<u8>) -> Vec<u8> {
+        let u16 = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let mut body = u16(linktype).to_vec();
+        body.extend_from_slice(&[0, 0]);
+        body.extend(u32s(big_endian, &[65535]));
+        if let Some(tsresol) = tsresol {
+            body.extend(u16(IF_TSRESOL));
+            body.extend(u16(1));
+            body.extend_from_slice(&[tsresol, 0, 0, 0]);
+        }
+        block(big_endian, INTERFACE_DESCRIPTION, &body)
+    }
+
+    fn enhanced(big_endian: bool, id: u32, ticks: u64, data: &[u8], original: u32) -> Vec<u8> {
+        let mut body = u32s(big_endian, &[id, (ticks >> 32) as u32, ticks as u32, data.len() as u32, original]);
+        body.extend_from_slice(data);
+        block(big_endian, ENHANCED_PACKET, &body)
+    }
+
+    fn reader(file: Vec<u8>) -> PcapReader {
+        PcapReader::new(Cursor::new(file), "test").unwrap()
+    }
+
+    fn open_error(file: Vec<u8>) -> String {
+        PcapReader::new(Cursor::new(file), "test").err().unwrap().to_string()
+    }
+
+    /// Every frame with the clock and original length after it, and whether it ended cleanly
+    fn frames(mut reader: PcapReader) -> (Vec<(Vec<u8>, u64, u64)>, bool) {
+        let mut frames = Vec::new();
+        loop {
+            match reader.next() {
+                Ok(frame) => {
+                    let frame = frame.to_vec();
+                    frames.push((frame, reader.clock.load(Ordering::Relaxed), reader.original_length.load(Ordering::Relaxed)));
+                }
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof, "{}", e);
+                    return (frames, reader.clean_end);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reads_classic_micros() {
+        let file = classic(false, MAGIC_MICROS, LINKTYPE_ETHERNET, &[(10, 5, &FRAME, 20), (11, 999999, &FRAME[..14], 60)]);
+        let (frames, clean) = frames(reader(file));
+        assert_eq!(frames, vec![(FRAME.to_vec(), 10_000_005, 20), (FRAME[..14].to_vec(), 11_999_999, 60)]);
+        assert!(clean);
+    }
+
+    #[test]
+    fn reads_big_endian_nanos() {
+        let file = classic(true, MAGIC_NANOS, LINKTYPE_ETHERNET, &[(10, 5_000_999, &FRAME, 20)]);
+        assert_eq!(frames(reader(file)), (vec![(FRAME.to_vec(), 10_005_000, 20)], true));
+    }
+
+    #[test]
+    fn reads_gzipped() {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+        gz.write_all(&classic(false, MAGIC_MICROS, LINKTYPE_ETHERNET, &[(1, 0, &FRAME, 20)])).unwrap();
+        assert_eq!(frames(reader(gz.finish().unwrap())).0.len(), 1);
+    }
+
+    #[test]
+    fn stops_before_a_truncated_last_record() {
+        let whole = classic(false, MAGIC_MICROS, LINKTYPE_ETHERNET, &[(1, 0, &FRAME, 20), (2, 0, &FRAME, 20)]);
+        // Inside the last frame's data, then inside its header
+        for cut in [whole.len() - 5, whole.len() - FRAME.len() - 10] {
+            let (frames, clean) = frames(reader(whole[..cut].to_vec()));
+            assert_eq!(frames.len(), 1, "cut at {}", cut);
+            assert!(!clean, "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_read() {
+        assert_eq!(open_error(classic(false, MAGIC_MICROS, 147, &[])),
+                   "Unsupported link type 147, only Ethernet, raw IP, Linux cooked and BSD loopback are");
+        assert_eq!(open_error(vec![0; 24]), "test is neither pcap nor pcapng");
+        let corrupt = classic(false, MAGIC_MICROS, LINKTYPE_ETHERNET, &[(1, 0, &FRAME, 20)]);
+        let mut corrupt = corrupt[..24].to_vec();
+        corrupt.extend(u32s(false, &[1, 0, MAX_SNAPLEN as u32 + 1, 0]));
+        assert_eq!(reader(corrupt).next().err().unwrap().to_string(), "Record of 262145 bytes, file corrupt?");
+    }
+
+    #[test]
+    fn walks_pcapng_blocks() {
+        let mut file = section(false);
+        file.extend(interface(false, LINKTYPE_ETHERNET as u16, None));
+        // Nanoseconds
+        file.extend(interface(false, LINKTYPE_ETHERNET as u16, Some(9)));
+        file.extend(enhanced(false, 0, 10_000_005, &FRAME, 20));
+        // Neither a packet nor anything else known, skipped
+        file.extend(block(false, 0x0bad, &[1, 2, 3, 4, 5]));
+        file.extend(enhanced(false, 1, 10_000_005_000, &FRAME[..14], 1514));
+        let mut simple = u32s(false, &[20]);
+        simple.extend_from_slice(&FRAME);
+        file.extend(block(false, SIMPLE_PACKET, &simple));
+        let (frames, clean) = frames(reader(file));
+        assert_eq!(frames, vec![
+            (FRAME.to_vec(), 10_000_005, 20),
+            (FRAME[..14].to_vec(), 10_000_005, 1514),
+            // No timestamp, the clock stays
+            (FRAME.to_vec(), 10_000_005, 20),
+        ]);
+        assert!(clean);
+    }
+
+    #[test]
+    fn reads_big_endian_pcapng_sections() {
+        let mut file = section(false);
+        file.extend(interface(false, LINKTYPE_ETHERNET as u16, None));
+        file.extend(enhanced(false, 0, 1_000_000, &FRAME, 20));
+        // A new section describes its interfaces anew, in its own byte order
+        file.extend(section(true));
+        file.extend(interface(true, LINKTYPE_ETHERNET as u16, None));
+        file.extend(enhanced(true, 0, 2_000_000, &FRAME, 20));
+        let clocks: Vec<_> = frames(reader(file)).0.into_iter().map(|(_, clock, _)| clock).collect();
+        assert_eq!(clocks, vec![1_000_000, 2_000_000]);
+    }
+
+    #[test]
+    fn stops_before_a_truncated_pcapng_block() {
+        let mut file = section(false);
+        file.extend(interface(false, LINKTYPE_ETHERNET as u16, None));
+        file.extend(enhanced(false, 0, 1, &FRAME, 20));
+        let last = enhanced(false, 0, 2, &FRAME, 20);
+        file.extend_from_slice(&last[..last.len() - 6]);
+        let (frames, clean) = frames(reader(file));
+        assert_eq!(frames.len(), 1);
+        assert!(!clean);
+    }
+
+    #[test]
+    fn refuses_packets_on_undescribed_interfaces() {
+        let mut file = section(false);
+        file.extend(interface(false, LINKTYPE_ETHERNET as u16, None));
+        file.extend(enhanced(false, 1, 1, &FRAME, 20));
+        assert_eq!(reader(file).next().err().unwrap().to_string(), "Packet on undescribed interface 1");
+    }
+
+    /// FRAME from the IP header on
+    const IP: &[u8] = FRAME.split_at(14).1;
+
+    fn as_ethernet(source: [u8; 6], dest: [u8; 6], ethertype: u16) -> Vec<u8> {
+        let mut frame = dest.to_vec();
+        frame.extend_from_slice(&source);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(IP);
+        frame
+    }
+
+    #[test]
+    fn reads_raw_ip_as_ethernet() {
+        let file = classic(false, MAGIC_MICROS, LINKTYPE_RAW, &[(1, 0, IP, 1000)]);
+        // The Ethernet header counts on the wire too
+        assert_eq!(frames(reader(file)).0, vec![(as_ethernet([0; 6], [0; 6], ETHERTYPE_IPV4), 1_000_000, 1014)]);
+        let mut v6 = IP.to_vec();
+        v6[0] = 0x60;
+        let mut out = Vec::new();
+        to_ethernet(LINKTYPE_RAW, &v6, &mut out);
+        assert_eq!(out[12..14], ETHERTYPE_IPV6.to_be_bytes());
+        to_ethernet(LINKTYPE_IPV6, IP, &mut out);
+        assert_eq!(out[12..14], ETHERTYPE_IPV6.to_be_bytes());
+    }
+
+    #[test]
+    fn reads_loopback_either_byte_order() {
+        let mut out = Vec::new();
+        for (linktype, family) in [(LINKTYPE_NULL, 2u32.to_le_bytes()), (LINKTYPE_NULL, 2u32.to_be_bytes()), (LINKTYPE_LOOP, 2u32.to_be_bytes())] {
+            to_ethernet(linktype, &[&family[..], IP].concat(), &mut out);
+            assert_eq!(out, as_ethernet([0; 6], [0; 6], ETHERTYPE_IPV4), "{} {:?}", linktype, family);
+        }
+        to_ethernet(LINKTYPE_NULL, &[&30u32.to_le_bytes()[..], IP].concat(), &mut out);
+        assert_eq!(out[12..14], ETHERTYPE_IPV6.to_be_bytes());
+        // Too short for its header
+        to_ethernet(LINKTYPE_NULL, &[2, 0], &mut out);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn reads_linux_cooked() {
+        let mac = [2, 0, 0, 0, 0, 9];
+        // Broadcast, ARPHRD_ETHER, a 6 byte address
+        let mut sll = vec![0, 1, 0, 1, 0, 6];
+        sll.extend_from_slice(&mac);
+        sll.extend_from_slice(&[0, 0, 0x08, 0]);
+        sll.extend_from_slice(IP);
+        let mut out = Vec::new();
+        to_ethernet(LINKTYPE_LINUX_SLL, &sll, &mut out);
+        assert_eq!(out, as_ethernet(mac, [0xff; 6], ETHERTYPE_IPV4));
+
+        // Protocol, reserved, ifindex, ARPHRD_ETHER, multicast, a 6 byte address
+        let mut sll2 = vec![0x86, 0xdd, 0, 0, 0, 0, 0, 3, 0, 1, 2, 6];
+        sll2.extend_from_slice(&mac);
+        sll2.extend_from_slice(&[0, 0]);
+        sll2.extend_from_slice(IP);
+        to_ethernet(LINKTYPE_LINUX_SLL2, &sll2, &mut out);
+        assert_eq!(out, as_ethernet(mac, [0x01, 0x00, 0x5e, 0, 0, 0], ETHERTYPE_IPV6));
+    }
+
+    #[test]
+    fn reads_each_pcapng_interface_by_its_own_link_type_and_clock() {
+        let mut file = section(false);
+        file.extend(interface(false, LINKTYPE_ETHERNET as u16, None));
+        // Raw IP in nanoseconds
+        file.extend(interface(false, LINKTYPE_RAW as u16, Some(9)));
+        // Slices of 2^-10 seconds, of nothing it can read
+        file.extend(interface(false, 147, Some(0x80 | 10)));
+        file.extend(enhanced(false, 0, 1_000_000, &FRAME, 20));
+        file.extend(enhanced(false, 1, 2_000_000_000, IP, 6));
+        file.extend(enhanced(false, 2, 3 << 10, &FRAME, 20));
+        let mut reader = reader(file);
+        let mut read = Vec::new();
+        while let Ok(frame) = reader.next() {
+            read.push((frame.to_vec(), reader.clock.load(Ordering::Relaxed), reader.original_length.load(Ordering::Relaxed)));
+        }
+        assert_eq!(read, vec![(FRAME.to_vec(), 1_000_000, 20), (as_ethernet([0; 6], [0; 6], ETHERTYPE_IPV4), 2_000_000, 20)]);
+        match &reader.format {
+            Format::Ng(interfaces) => assert_eq!(interfaces.iter().map(|i| (i.frames, i.skipped)).collect::<Vec<_>>(), [(1, 0), (1, 0), (0, 1)]),
+            Format::Classic => unreachable!()
+        }
+        assert_eq!(micros(3 << 10, 0x80 | 10), 3_000_000);
+    }
+}
@@ -0,0 +1,122 @@
+/// Who --user and --group name, looked up before anything is dropped
+#[cfg(unix)]
+pub struct Credentials {
+    user: Option<(String, libc::uid_t)>,
+    group: (String, libc::gid_t),
+}
+
+#[cfg(not(unix))]
+pub struct Credentials;
+
+/// Room for the strings getpwnam_r and getgrnam_r return
+#[cfg(unix)]
+const LOOKUP_BUFFER: usize = 16384;
+
+/// uid and primary gid of a user name or number, no gid for a number without an entry
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<(libc::uid_t, Option<libc::gid_t>), String> {
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let mut buffer = vec![0; LOOKUP_BUFFER];
+    let number = user.parse::<libc::uid_t>().ok();
+    let ret = match number {
+        Some(uid) => unsafe { libc::getpwuid_r(uid, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) },
+        None => {
+            let name = std::ffi::CString::new(user).map_err(|_| format!("Invalid user {}", user))?;
+            unsafe { libc::getpwnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) }
+        }
+    };
+    if ret != 0 {
+        return Err(format!("Fail to look up user {}: {}", user, std::io::Error::from_raw_os_error(ret)));
+    }
+    match (found.is_null(), number) {
+        (false, _) => Ok((entry.pw_uid, Some(entry.pw_gid))),
+        (true, Some(uid)) => Ok((uid, None)),
+        (true, None) => Err(format!("No user {}", user))
+    }
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<libc::gid_t, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = std::ffi::CString::new(group).map_err(|_| format!("Invalid group {}", group))?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let mut buffer = vec![0; LOOKUP_BUFFER];
+    let ret = unsafe { libc::getgrnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    if ret != 0 {
+        return Err(format!("Fail to look up group {}: {}", group, std::io::Error::from_raw_os_error(ret)));
+    }
+    if found.is_null() {
+        return Err(format!("No group {}", group));
+    }
+    Ok(entry.gr_gid)
+}
+
+/// None without either, the group defaults to the user's primary one
+#[cfg(unix)]
+pub fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Option<Credentials>, String> {
+    let user = match user {
+        Some(name) => Some((name.to_string(), lookup_user(name)?)),
+        None => None
+    };
+    let group = match (group, &user) {
+        (Some(name), _) => (name.to_string(), lookup_group(name)?),
+        (None, Some((name, (_, Some(gid))))) => (name.clone(), *gid),
+        (None, Some((name, (_, None)))) => return Err(format!("User {} has no primary group, add --group", name)),
+        (None, None) => return Ok(None)
+    };
+    Ok(Some(Credentials { user: user.map(|(name, (uid, _))| (name, uid)), group }))
+}
+
+#[cfg(not(unix))]
+pub fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Option<Credentials>, String> {
+    match (user, group) {
+        (None, None) => Ok(None),
+        _ => Err("--user and --group are only supported on Unix".to_string())
+    }
+}
+
+#[cfg(unix)]
+fn last_error(what: &str) -> String {
+    format!("Fail to {}: {}", what, std::io::Error::last_os_error())
+}
+
+impl Credentials {
+    /// Become them for good, Err when any of it did not stick
+    #[cfg(unix)]
+    pub fn apply(&self) -> Result<(), String> {
+        let (group, gid) = &self.group;
+        // Supplementary groups first, root's would stay otherwise, and while still allowed to.
+        // glibc applies all three to every thread, not just this one.
+        if unsafe { libc::setgroups(1, gid) } != 0 {
+            return Err(last_error("drop supplementary groups"));
+        }
+        if unsafe { libc::setgid(*gid) } != 0 {
+            return Err(last_error(&format!("switch to group {}", group)));
+        }
+        if let Some((user, uid)) = &self.user {
+            if unsafe { libc::setuid(*uid) } != 0 {
+                return Err(last_error(&format!("switch to user {}", user)));
+            }
+            // A saved uid of root would let it back
+            if *uid != 0 && unsafe { libc::setuid(0) } == 0 {
+                return Err(format!("Still able to get root back after switching to user {}", user));
+            }
+            if unsafe { (libc::getuid(), libc::geteuid()) } != (*uid, *uid) {
+                return Err(format!("Fail to switch to user {}: still running as another", user));
+            }
+        }
+        if unsafe { (libc::getgid(), libc::getegid()) } != (*gid, *gid) {
+            return Err(format!("Fail to switch to group {}: still running as another", group));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self) -> Result<(), String> {
+        unreachable!()
+    }
+}
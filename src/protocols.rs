@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::data::{protocol_name, Stats};
+
+/// Where flows without a protocol go, all of them with --granularity host
+const UNKNOWN: &str = "unknown";
+
+#[derive(Serialize, Default)]
+pub struct ProtocolValue {
+    pub total_length: u128,
+    pub total_count: u128,
+    pub flows: usize,
+}
+
+/// Keyed by protocol name, or its number when it has none
+#[derive(Serialize, Default)]
+pub struct Protocols(pub HashMap<String, ProtocolValue>);
+
+pub fn protocols(stats: &Stats) -> Protocols {
+    let mut protocols = Protocols::default();
+    for (k, v) in &stats.0 {
+        let name = k.1.map(|t| protocol_name(t.protocol)).unwrap_or_else(|| UNKNOWN.to_string());
+        let entry = protocols.0.entry(name).or_default();
+        entry.total_length += v.total_length;
+        entry.total_count += v.total_count;
+        entry.flows += 1;
+    }
+    protocols
+}
@@ -0,0 +1,126 @@
+use std::cmp::Ordering;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::cidr::Cidr;
+use crate::data::{Stats, StatsKey, StatsValue};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sort {
+    Bytes,
+    Packets,
+    FirstSeen,
+    LastSeen,
+    /// The flow key as written with --key-format arrow
+    Flow,
+}
+
+impl FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bytes" => Ok(Sort::Bytes),
+            "packets" => Ok(Sort::Packets),
+            "first_seen" => Ok(Sort::FirstSeen),
+            "last_seen" => Ok(Sort::LastSeen),
+            "flow" => Ok(Sort::Flow),
+            _ => Err(format!("Unknown sort: {}, expected bytes, packets, first_seen, last_seen or flow", s))
+        }
+    }
+}
+
+/// /query, every filter given has to match
+pub struct Query {
+    /// Either end
+    pub host: Option<IpAddr>,
+    /// Either end inside
+    pub cidr: Option<Cidr>,
+    pub protocol: Option<u8>,
+    /// Either port
+    pub port: Option<u16>,
+    pub min_bytes: u128,
+    pub sort: Sort,
+    pub descending: bool,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+/// What `limit` is unless given
+pub const DEFAULT_LIMIT: usize = 100;
+
+impl Default for Query {
+    fn default() -> Self {
+        Query {
+            host: None,
+            cidr: None,
+            protocol: None,
+            port: None,
+            min_bytes: 0,
+            sort: Sort::Bytes,
+            descending: true,
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct QueryFlow<'a> {
+    pub flow: &'a StatsKey,
+    #[serde(flatten)]
+    pub value: &'a StatsValue,
+}
+
+#[derive(Serialize)]
+pub struct Page<'a> {
+    /// Flows matching, before offset and limit
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub flows: Vec<QueryFlow<'a>>,
+}
+
+impl Query {
+    /// Host keys have no transport, protocol and port match none of them
+    pub fn matches(&self, key: &StatsKey, value: &StatsValue) -> bool {
+        let (source, dest) = key.addrs();
+        self.host.is_none_or(|h| source == h || dest == h)
+            && self.cidr.as_ref().is_none_or(|c| c.matches(key))
+            && self.protocol.is_none_or(|p| key.1.is_some_and(|t| t.protocol == p))
+            && self.port.is_none_or(|p| key.1.is_some_and(|t| t.source_port == p || t.dest_port == p))
+            && value.total_length >= self.min_bytes
+    }
+
+    fn compare(&self, a: (&StatsKey, &StatsValue), b: (&StatsKey, &StatsValue)) -> Ordering {
+        match self.sort {
+            Sort::Bytes => a.1.total_length.cmp(&b.1.total_length),
+            Sort::Packets => a.1.total_count.cmp(&b.1.total_count),
+            Sort::FirstSeen => a.1.first_seen.cmp(&b.1.first_seen),
+            Sort::LastSeen => a.1.last_seen.cmp(&b.1.last_seen),
+            Sort::Flow => Ordering::Equal
+        }
+    }
+
+    /// Ties go by flow key, so pages line up from one request to the next
+    pub fn run<'a>(&self, stats: &'a Stats) -> Page<'a> {
+        let mut flows: Vec<_> = stats.0.iter()
+            .filter(|(k, v)| self.matches(k, v))
+            .map(|(k, v)| (k.to_string(), k, v))
+            .collect();
+        flows.sort_by(|a, b| {
+            let order = self.compare((a.1, a.2), (b.1, b.2)).then_with(|| a.0.cmp(&b.0));
+            if self.descending { order.reverse() } else { order }
+        });
+        Page {
+            total: flows.len(),
+            offset: self.offset,
+            limit: self.limit,
+            flows: flows.into_iter().skip(self.offset).take(self.limit)
+                .map(|(_, flow, value)| QueryFlow { flow, value })
+                .collect(),
+        }
+    }
+}
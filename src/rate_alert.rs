@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use crate::alert::Alert;
+use crate::data::{unix_now, StatsKey};
+use crate::learn::{Baseline, LearnedRate};
+
+/// --alert-bps: flows above the rate for `samples` rate intervals in a row, once
+/// per stretch. A flow has to drop below before it can alert again.
+pub struct RateAlerter {
+    bps: u64,
+    samples: u32,
+    // Intervals in a row above, only flows above the last time
+    streaks: HashMap<StatsKey, u32>,
+    // Bytes per second --learn-duration saw flows do, they have to beat that too
+    learned: HashMap<StatsKey, f64>,
+}
+
+impl RateAlerter {
+    pub fn new(bps: u64, samples: u32) -> Self {
+        RateAlerter { bps, samples: samples.max(1), streaks: HashMap::new(), learned: HashMap::new() }
+    }
+
+    /// Start from what an earlier run learned
+    pub fn with_baseline(mut self, baseline: Baseline) -> Self {
+        self.learned = baseline.flows.into_iter().map(|l| (l.flow, l.bps / 8.0)).collect();
+        self
+    }
+
+    /// What was learned so far, for the next run
+    pub fn baseline(&self) -> Baseline {
+        let flows = self.learned.iter().map(|(k, bytes_per_sec)| LearnedRate { flow: *k, bps: bytes_per_sec * 8.0 }).collect();
+        Baseline { learned_at: unix_now(), flows }
+    }
+
+    /// Like `observe` while learning, only remembering how fast each flow went
+    pub fn learn(&mut self, above: Vec<(StatsKey, f64)>) {
+        for (key, bytes_per_sec) in above {
+            let learned = self.learned.entry(key).or_default();
+            *learned = learned.max(bytes_per_sec);
+        }
+    }
+
+    /// Bytes per second a flow has to go over
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bps as f64 / 8.0
+    }
+
+    /// `above` being every flow over `bytes_per_sec` this interval, with its bytes_per_sec
+    pub fn observe(&mut self, above: Vec<(StatsKey, f64)>) -> Vec<Alert> {
+        let mut streaks = HashMap::with_capacity(above.len());
+        let mut alerts = Vec::new();
+        for (key, bytes_per_sec) in above {
+            if self.learned.get(&key).is_some_and(|learned| bytes_per_sec <= *learned) {
+                continue;
+            }
+            let streak = self.streaks.get(&key).copied().unwrap_or(0) + 1;
+            if streak == self.samples {
+                let bps = bytes_per_sec * 8.0;
+                alerts.push(Alert::new(
+                    "bps",
+                    key.to_string(),
+                    format!("Flow {} at {:.0} bps for {} intervals", key, bps, streak),
+                    json!({ "flow": key.to_string(), "bps": bps, "threshold": self.bps, "samples": streak }),
+                ));
+            }
+            streaks.insert(key, streak);
+        }
+        self.streaks = streaks;
+        alerts
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config::Granularity;
+use crate::data::{update_db, Stats};
+use crate::packet::proc_packet;
+use crate::runtime::Runtime;
+use crate::update::Updater;
+
+/// Run `frames` through the parser into `stats` the way a capture with the default
+/// options would: flows by host pair, whole frames, every EtherType, no filter.
+/// Gives back what the parser counted on the way, malformed frames and the like.
+pub fn process_frames<'a>(stats: &mut Stats, frames: impl IntoIterator<Item = &'a [u8]>) -> Runtime {
+    let runtime = Runtime::default();
+    let mut updater = Updater::new(Granularity::Host, false);
+    let mut updates = Vec::new();
+    for frame in frames {
+        if let Some(mut p) = proc_packet(frame, &runtime, &[]) {
+            updates.extend(updater.update(&mut p, false, false, &runtime));
+        }
+    }
+    update_db(stats, updates, 1, false);
+    runtime
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    const MACS: [u8; 12] = [2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1];
+
+    fn eth(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = MACS.to_vec();
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// UDP from port 1234 to 53 carrying `payload`
+    fn udp(payload: &[u8]) -> Vec<u8> {
+        let mut datagram = [1234u16, 53, 8 + payload.len() as u16, 0].iter().flat_map(|n| n.to_be_bytes()).collect::<Vec<_>>();
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    /// 10.0.0.1 to 10.0.0.2, `total_length` as given rather than what's there
+    fn ipv4(total_length: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&total_length.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// fd00::1 to fd00::2
+    fn ipv6(payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[17, 64]);
+        packet.extend_from_slice(&[0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        packet.extend_from_slice(&[0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn replay(frames: &[Vec<u8>]) -> (Stats, Runtime) {
+        let mut stats = Stats::new();
+        let runtime = process_frames(&mut stats, frames.iter().map(|f| &f[..]));
+        (stats, runtime)
+    }
+
+    /// (source, dest, total_length, total_count) of the only flow
+    fn only_flow(stats: &Stats) -> (IpAddr, IpAddr, u128, u128) {
+        assert_eq!(stats.0.len(), 1);
+        let (key, value) = stats.0.iter().next().unwrap();
+        let (source, dest) = key.addrs();
+        (source, dest, value.total_length, value.total_count)
+    }
+
+    #[test]
+    fn counts_ipv4() {
+        let frame = eth(0x0800, &ipv4(32, &udp(b"abcd")));
+        let (stats, runtime) = replay(&[frame.clone(), frame]);
+        assert_eq!(only_flow(&stats), ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap(), 64, 2));
+        assert_eq!(stats.0.values().next().unwrap().empty_packets, 0);
+        assert_eq!(runtime.frames.load(Ordering::Relaxed), 2);
+        assert_eq!(runtime.malformed.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn counts_ipv6() {
+        let (stats, _) = replay(&[eth(0x86dd, &ipv6(&udp(b"")))]);
+        // The payload length, not the whole datagram
+        assert_eq!(only_flow(&stats), ("fd00::1".parse().unwrap(), "fd00::2".parse().unwrap(), 8, 1));
+        assert_eq!(stats.0.values().next().unwrap().empty_packets, 1);
+    }
+
+    #[test]
+    fn counts_vlan_tagged_as_untagged() {
+        let mut tagged = vec![0, 10, 0x08, 0];
+        tagged.extend_from_slice(&ipv4(32, &udp(b"abcd")));
+        let (stats, runtime) = replay(&[eth(0x8100, &tagged), eth(0x0800, &ipv4(32, &udp(b"abcd")))]);
+        // Tagged or not, the same flow
+        assert_eq!(only_flow(&stats), ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap(), 64, 2));
+        assert_eq!(runtime.malformed.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn skips_arp() {
+        let (stats, runtime) = replay(&[eth(0x0806, &[0; 28])]);
+        assert!(stats.0.is_empty());
+        assert_eq!(runtime.frames.load(Ordering::Relaxed), 1);
+        assert_eq!(runtime.malformed.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn counts_malformed() {
+        let (stats, runtime) = replay(&[
+            vec![0; 10],
+            eth(0x0800, &[0x45, 0, 0, 32]),
+            eth(0x86dd, &[0x60; 20]),
+            eth(0x8100, &[0, 10]),
+        ]);
+        assert!(stats.0.is_empty());
+        assert_eq!(runtime.frames.load(Ordering::Relaxed), 4);
+        assert_eq!(runtime.malformed.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn counts_what_truncated_frames_carry() {
+        // Claims 1000 bytes, 32 of them made it
+        let (stats, _) = replay(&[eth(0x0800, &ipv4(1000, &udp(b"abcd")))]);
+        assert_eq!(only_flow(&stats).2, 32);
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use crate::data::StatsKey;
+use crate::packet::TcpInfo;
+use crate::table::IdleTable;
+
+/// Segments remembered per direction
+const SEGMENTS: usize = 8;
+/// Directions tracked at most, new ones are ignored until the idle ones are evicted
+const MAX_FLOWS: usize = 65536;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+struct Segments {
+    // (sequence, payload length)
+    recent: [(u32, u32); SEGMENTS],
+    next: usize,
+}
+
+/// Spots TCP segments that were already seen on the same direction of a 5-tuple
+pub struct RetransTracker {
+    flows: IdleTable<(StatsKey, u16, u16), Segments>,
+}
+
+impl Default for RetransTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetransTracker {
+    pub fn new() -> Self {
+        RetransTracker { flows: IdleTable::new(MAX_FLOWS, IDLE_TIMEOUT) }
+    }
+
+    pub fn observe(&mut self, key: &StatsKey, tcp: &TcpInfo, payload_len: usize) -> bool {
+        // Bare ACKs legitimately repeat their sequence number
+        if payload_len == 0 {
+            return false;
+        }
+        let segment = (tcp.sequence, payload_len as u32);
+        let flow = (*key, tcp.source_port, tcp.dest_port);
+        match self.flows.get_mut(&flow) {
+            Some(s) => {
+                if s.recent.contains(&segment) {
+                    return true;
+                }
+                s.recent[s.next] = segment;
+                s.next = (s.next + 1) % SEGMENTS;
+            }
+            None => {
+                let mut recent = [(0, 0); SEGMENTS];
+                recent[0] = segment;
+                self.flows.insert(flow, Segments { recent, next: 1 });
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::VecDeque;
+
+use crate::pcap::{LINKTYPE_ETHERNET, MAGIC_MICROS, MAX_SNAPLEN};
+
+struct Frame {
+    /// Unix microseconds
+    at: u64,
+    data: Vec<u8>,
+}
+
+/// --packet-ring: the last frames as captured, whether they were counted or not
+pub struct PacketRing {
+    frames: VecDeque<Frame>,
+    capacity: usize,
+}
+
+impl PacketRing {
+    pub fn new(capacity: usize) -> Self {
+        PacketRing { frames: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn push(&mut self, at: u64, frame: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        // The oldest frame's buffer is reused once full, no allocation per packet from then on
+        let mut data = if self.frames.len() >= self.capacity {
+            self.frames.pop_front().map(|f| f.data).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        data.clear();
+        data.extend_from_slice(frame);
+        self.frames.push_back(Frame { at, data });
+    }
+
+    /// Classic pcap with microseconds, the oldest frame first
+    pub fn pcap(&self) -> Vec<u8> {
+        let size = self.frames.iter().map(|f| 16 + f.data.len()).sum::<usize>();
+        let mut out = Vec::with_capacity(24 + size);
+        out.extend_from_slice(&MAGIC_MICROS.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&4u16.to_le_bytes());
+        // Timezone and timestamp accuracy, always zero
+        out.extend_from_slice(&[0; 8]);
+        out.extend_from_slice(&(MAX_SNAPLEN as u32).to_le_bytes());
+        out.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for f in &self.frames {
+            out.extend_from_slice(&((f.at / 1_000_000) as u32).to_le_bytes());
+            out.extend_from_slice(&((f.at % 1_000_000) as u32).to_le_bytes());
+            out.extend_from_slice(&(f.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(f.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&f.data);
+        }
+        out
+    }
+}
@@ -0,0 +1,141 @@
+use std::cmp::Reverse;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use log::Level;
+use serde::Serialize;
+
+use crate::data::{unix_now, Stats, StatsKey};
+use crate::logging::WarnOnce;
+use crate::state::State;
+
+/// Where --rollup-to sends the reports, one JSON object per line
+pub enum RollupSink {
+    Stdout,
+    /// Appended to
+    File(PathBuf),
+    /// POSTed to, one request per report
+    Webhook(String),
+    /// A row per flow and report, opened on the first report
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf, Option<crate::sqlite::History>),
+}
+
+impl FromStr for RollupSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("Empty --rollup-to".to_string()),
+            "-" => Ok(RollupSink::Stdout),
+            #[cfg(feature = "sqlite")]
+            _ if s.starts_with("sqlite:") => Ok(RollupSink::Sqlite(s["sqlite:".len()..].into(), None)),
+            #[cfg(not(feature = "sqlite"))]
+            _ if s.starts_with("sqlite:") => Err("--rollup-to sqlite: takes whoisthere built with --features sqlite".to_string()),
+            _ if s.starts_with("http://") || s.starts_with("https://") => Ok(RollupSink::Webhook(s.to_string())),
+            _ => Ok(RollupSink::File(s.into()))
+        }
+    }
+}
+
+impl RollupSink {
+    fn emit(&mut self, report: &Report) -> Result<(), String> {
+        let line = || serde_json::to_string(report).unwrap_or_else(|e| panic!("Fail to serialize rollup: {}", e));
+        match self {
+            RollupSink::Webhook(url) => ureq::post(url).set("Content-Type", "application/json")
+                .send_string(&line()).map(|_| ()).map_err(|e| e.to_string()),
+            RollupSink::Stdout => {
+                let line = line() + "\n";
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(line.as_bytes()).and_then(|_| stdout.flush()).map_err(|e| e.to_string())
+            }
+            RollupSink::File(path) => {
+                let line = line() + "\n";
+                // A single write, readers never see half a line
+                OpenOptions::new().create(true).append(true).open(&*path).and_then(|mut f| f.write_all(line.as_bytes()))
+                    .map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            #[cfg(feature = "sqlite")]
+            RollupSink::Sqlite(path, db) => {
+                if db.is_none() {
+                    *db = Some(crate::sqlite::History::open(path)?);
+                }
+                let flows = report.flows.iter().map(|r| (r.flow, r.total_length, r.total_count));
+                db.as_mut().unwrap().append(report.start, report.end, flows)
+            }
+        }
+    }
+}
+
+/// What a flow did within the interval
+#[derive(Serialize)]
+struct Record<'a> {
+    flow: &'a StatsKey,
+    total_length: u128,
+    total_count: u128,
+}
+
+/// One interval, nothing in it depends on the reports before
+#[derive(Serialize)]
+struct Report<'a> {
+    /// Unix seconds, the previous report's end
+    start: u64,
+    end: u64,
+    total_length: u128,
+    total_count: u128,
+    flows: Vec<Record<'a>>,
+}
+
+/// Flows that moved from `old` to `new`, by how much. One that shrank was reset or
+/// rolled over in between and counts from zero.
+fn delta<'a>(old: &Stats, new: &'a Stats) -> Vec<Record<'a>> {
+    let mut records = Vec::new();
+    for (k, v) in &new.0 {
+        let (length, count) = match old.0.get(k) {
+            Some(o) if v.total_length >= o.total_length && v.total_count >= o.total_count =>
+                (v.total_length - o.total_length, v.total_count - o.total_count),
+            _ => (v.total_length, v.total_count)
+        };
+        if count > 0 {
+            records.push(Record { flow: k, total_length: length, total_count: count });
+        }
+    }
+    records.sort_by_key(|r| Reverse(r.total_length));
+    records
+}
+
+/// --rollup-interval: the published flows minus the ones of the last report
+pub struct Rollups {
+    sink: RollupSink,
+    last: Arc<Stats>,
+    start: u64,
+    failing: WarnOnce,
+}
+
+impl Rollups {
+    /// From `state`'s flows as they stand, those loaded from --db are not news
+    pub fn new(sink: RollupSink, state: &State) -> Self {
+        Rollups { sink, last: state.published(), start: unix_now(), failing: WarnOnce::new(Level::Warn, module_path!()) }
+    }
+
+    /// Called every --rollup-interval. An interval that fails to go out is part of the next one.
+    pub fn round(&mut self, state: &State) {
+        let stats = state.published();
+        let flows = delta(&self.last, &stats);
+        let report = Report {
+            start: self.start,
+            end: unix_now(),
+            total_length: flows.iter().map(|r| r.total_length).sum(),
+            total_count: flows.iter().map(|r| r.total_count).sum(),
+            flows,
+        };
+        let (sent, end) = (self.sink.emit(&report), report.end);
+        if self.failing.report(sent, "emit rollup, keeping it for the next round").is_ok() {
+            self.start = end;
+            self.last = stats;
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::iface::Link;
+
+/// Process wide counters, cheap enough to bump from the capture loop
+#[derive(Default, Serialize)]
+pub struct Runtime {
+    pub frames: AtomicU64,
+    pub frame_bytes: AtomicU64,
+    /// Frames over the standard 1500 bytes MTU
+    pub jumbo_frames: AtomicU64,
+    /// The IP header claims more bytes than the frame carries, snaplen too small?
+    pub truncated: AtomicU64,
+    /// The frame carries more than the IP header claims beyond minimum Ethernet padding
+    pub padded: AtomicU64,
+    /// Updates waiting between capture and aggregation
+    pub queue_depth: AtomicU64,
+    /// Updates thrown away because the queue was full
+    pub queue_dropped: AtomicU64,
+    /// Frames too short for the headers they claim to have
+    pub malformed: AtomicU64,
+    /// Frames of an EtherType left out of --ethertypes
+    pub skipped: AtomicU64,
+    /// Packets left out by --filter, --mac, --vlan or --app-protocol
+    pub filtered: AtomicU64,
+    /// Failed reads from the capture channel
+    pub receive_errors: AtomicU64,
+    /// Failed database saves, each retried by the next
+    pub save_errors: AtomicU64,
+    pub casts: Casts,
+    pub tunnels: Tunnels,
+    /// Frames came with their FCS and had it cut off, --fcs-included. Byte counts
+    /// leave it out either way, false only means it's assumed the capture had none.
+    pub fcs_included: AtomicBool,
+    /// Only on /metrics
+    #[serde(skip)]
+    pub packet_sizes: PacketSizes,
+    /// Where the HTTP server listens, OS picked ports filled in
+    pub http_addrs: Mutex<Vec<String>>,
+    #[serde(skip)]
+    pub kernel: Kernel,
+}
+
+/// What the kernel counted on the capture socket, which resets on every read
+#[derive(Default)]
+pub struct Kernel {
+    socket: Mutex<Option<i32>>,
+    packets: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Default, Serialize)]
+pub struct Traffic {
+    pub frames: AtomicU64,
+    pub bytes: AtomicU64,
+}
+
+/// Frames by kind of destination, ARP and friends included
+#[derive(Default, Serialize)]
+pub struct Casts {
+    pub unicast: Traffic,
+    pub multicast: Traffic,
+    pub broadcast: Traffic,
+}
+
+/// VPN and other tunnel packets, counted by their outer IP datagram before any filter
+#[derive(Default, Serialize)]
+pub struct Tunnels {
+    pub gre: Traffic,
+    /// Nothing but the endpoints to go by, ports and all are encrypted
+    pub esp: Traffic,
+}
+
+/// Upper bounds of the packet size buckets: small control packets, typical
+/// Internet MTUs, then jumbo frames
+pub const SIZE_BUCKETS: [u64; 8] = [64, 128, 256, 512, 1024, 1500, 4096, 9000];
+
+/// Packet sizes, what flows count as their length, bucketed
+#[derive(Default)]
+pub struct SizeHistogram {
+    /// Not cumulative, the last one is for anything over `SIZE_BUCKETS`
+    pub buckets: [AtomicU64; SIZE_BUCKETS.len() + 1],
+    pub sum: AtomicU64,
+    pub count: AtomicU64,
+}
+
+/// Protocols `PacketSizes` tells apart, anything else is other
+pub const SIZE_PROTOCOLS: [&str; 4] = ["tcp", "udp", "icmp", "other"];
+
+/// Sizes of the packets that went into the flows, by protocol
+#[derive(Default)]
+pub struct PacketSizes(pub [SizeHistogram; SIZE_PROTOCOLS.len()]);
+
+impl PacketSizes {
+    pub fn record(&self, protocol: u8, size: u128) {
+        let histogram = &self.0[match protocol {
+            6 => 0,
+            17 => 1,
+            // ICMPv6 too
+            1 | 58 => 2,
+            _ => 3
+        }];
+        let size = size.min(u64::MAX as u128) as u64;
+        let bucket = SIZE_BUCKETS.iter().position(|b| size <= *b).unwrap_or(SIZE_BUCKETS.len());
+        inc(&histogram.buckets[bucket]);
+        add(&histogram.sum, size);
+        inc(&histogram.count);
+    }
+}
+
+/// Counters plus what it costs to keep the flows around
+#[derive(Serialize)]
+pub struct Report<'a> {
+    #[serde(flatten)]
+    pub counters: &'a Runtime,
+    pub flows: usize,
+    /// Updates applied to the flows so far
+    pub update_sequence: u64,
+    /// Rough size of the flow map's entries, excluding allocator overhead
+    pub flow_map_bytes: usize,
+    /// Resident set size of the process, null where unsupported
+    pub rss_bytes: Option<u64>,
+    pub link: &'a Link,
+    /// Packets that reached the capture socket, dropped ones included; null where unsupported
+    pub kernel_packets: Option<u64>,
+    /// Packets the kernel dropped for want of room in the capture socket
+    pub kernel_dropped: Option<u64>,
+    /// Packets the interface and its driver dropped, for every listener
+    pub interface_dropped: Option<u64>,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct TpacketStats {
+    tp_packets: u32,
+    tp_drops: u32,
+}
+
+#[cfg(target_os = "linux")]
+const PACKET_STATISTICS: libc::c_int = 6;
+
+#[cfg(target_os = "linux")]
+fn packet_statistics(fd: i32) -> Option<TpacketStats> {
+    let mut stats = TpacketStats::default();
+    let mut len = std::mem::size_of::<TpacketStats>() as libc::socklen_t;
+    // Fails with ENOPROTOOPT on anything but a packet socket
+    let ret = unsafe {
+        libc::getsockopt(fd, libc::SOL_PACKET, PACKET_STATISTICS, &mut stats as *mut _ as *mut libc::c_void, &mut len)
+    };
+    (ret == 0).then_some(stats)
+}
+
+impl Kernel {
+    /// Find the packet socket pnet just opened, it keeps the descriptor to itself
+    #[cfg(target_os = "linux")]
+    pub fn attach(&self) {
+        let fds = match std::fs::read_dir("/proc/self/fd") {
+            Ok(fds) => fds,
+            Err(_) => return
+        };
+        let mut fds: Vec<i32> = fds.filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok()).collect();
+        fds.sort_unstable();
+        // The newest one should be ours
+        for fd in fds.into_iter().rev() {
+            if let Some(stats) = packet_statistics(fd) {
+                self.count(&stats);
+                *self.socket.lock().unwrap() = Some(fd);
+                return;
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn attach(&self) {}
+
+    /// The socket is closed, its descriptor may soon be another's
+    pub fn detach(&self) {
+        *self.socket.lock().unwrap() = None;
+    }
+
+    #[cfg(target_os = "linux")]
+    fn count(&self, stats: &TpacketStats) {
+        add(&self.packets, stats.tp_packets as u64);
+        add(&self.dropped, stats.tp_drops as u64);
+    }
+
+    /// (packets, dropped) since capture started, None without a packet socket
+    #[cfg(target_os = "linux")]
+    pub fn poll(&self) -> Option<(u64, u64)> {
+        let stats = packet_statistics((*self.socket.lock().unwrap())?)?;
+        self.count(&stats);
+        Some((self.packets.load(Ordering::Relaxed), self.dropped.load(Ordering::Relaxed)))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn poll(&self) -> Option<(u64, u64)> {
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn rss_bytes() -> Option<u64> {
+    // VmRSS is in kB, unlike statm which would need the page size
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rss_bytes() -> Option<u64> {
+    None
+}
+
+pub fn inc(counter: &AtomicU64) {
+    add(counter, 1);
+}
+
+pub fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+pub fn sub(counter: &AtomicU64, n: u64) {
+    counter.fetch_sub(n, Ordering::Relaxed);
+}
@@ -0,0 +1,76 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use pnet::packet::tcp::TcpFlags;
+use serde_json::json;
+
+use crate::alert::Alert;
+use crate::packet::PacketInfo;
+use crate::table::IdleTable;
+
+/// Sources watched at most, new ones are ignored until the quiet ones are evicted
+const MAX_SOURCES: usize = 16384;
+
+struct Probe {
+    started: Instant,
+    // Stop growing past the threshold, one over is all it takes
+    ports: HashSet<u16>,
+    hosts: HashSet<IpAddr>,
+    alerted: bool,
+}
+
+/// Flags sources reaching out to more than so many ports or hosts within a window.
+/// Counts TCP connection attempts (SYN without ACK) and UDP datagrams.
+pub struct ScanDetector {
+    ports: Option<usize>,
+    hosts: Option<usize>,
+    window: Duration,
+    sources: IdleTable<IpAddr, Probe>,
+}
+
+impl ScanDetector {
+    pub fn new(ports: Option<usize>, hosts: Option<usize>, window: Duration) -> Self {
+        ScanDetector { ports, hosts, window, sources: IdleTable::new(MAX_SOURCES, window) }
+    }
+
+    pub fn observe(&mut self, info: &PacketInfo) -> Option<Alert> {
+        let port = match (&info.tcp, &info.udp) {
+            (Some(tcp), _) if tcp.flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN => tcp.dest_port,
+            (None, Some(udp)) => udp.dest_port,
+            _ => return None
+        };
+        let (source, dest) = info.key.addrs();
+        let now = Instant::now();
+        if self.sources.get_mut(&source).is_none() {
+            let probe = Probe { started: now, ports: HashSet::new(), hosts: HashSet::new(), alerted: false };
+            if !self.sources.insert(source, probe) {
+                return None;
+            }
+        }
+        let probe = self.sources.get_mut(&source)?;
+        if now - probe.started > self.window {
+            *probe = Probe { started: now, ports: HashSet::new(), hosts: HashSet::new(), alerted: false };
+        }
+        if self.ports.is_some_and(|k| probe.ports.len() <= k) {
+            probe.ports.insert(port);
+        }
+        if self.hosts.is_some_and(|k| probe.hosts.len() <= k) {
+            probe.hosts.insert(dest);
+        }
+        let over_ports = self.ports.is_some_and(|k| probe.ports.len() > k);
+        let over_hosts = self.hosts.is_some_and(|k| probe.hosts.len() > k);
+        if probe.alerted || !(over_ports || over_hosts) {
+            return None;
+        }
+        probe.alerted = true;
+        let what = if over_ports { "ports" } else { "hosts" };
+        Some(Alert::new(
+            "scan",
+            source.to_string(),
+            format!("{} looks like a scanner: over {} distinct {} in {}s", source,
+                    if over_ports { self.ports } else { self.hosts }.unwrap_or(0), what, self.window.as_secs()),
+            json!({ "ports": probe.ports.len(), "hosts": probe.hosts.len(), "window_secs": self.window.as_secs() }),
+        ))
+    }
+}
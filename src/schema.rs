@@ -0,0 +1,419 @@
+use serde_json::{json, Value};
+
+use crate::key_format::{self, KeyFormat};
+
+// Hand written, keep in step with the Serialize impls it describes
+
+fn counter() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn nullable(schema: Value) -> Value {
+    json!({ "anyOf": [schema, { "type": "null" }] })
+}
+
+/// Object of `counter` fields, all required
+fn counters(names: &[&str]) -> Value {
+    let properties: serde_json::Map<_, _> = names.iter().map(|n| (n.to_string(), counter())).collect();
+    json!({ "type": "object", "properties": properties, "required": names })
+}
+
+/// As written with the running --key-format
+fn flow_key() -> Value {
+    match key_format::get() {
+        KeyFormat::Arrow => json!({
+            "type": "string",
+            "description": "\"a -> b\", or \"a:port -> b:port/protocol\" with --granularity port, IPv6 with ports in brackets",
+        }),
+        KeyFormat::Tuple => json!({
+            "type": "string",
+            "description": "\"a,b\", or \"a,port,b,port,protocol\" with --granularity port",
+        }),
+        KeyFormat::Json => {
+            let string = json!({ "type": "string" });
+            json!({
+                "type": "object",
+                "properties": {
+                    "src": string,
+                    "dst": string,
+                    "proto": string,
+                    "src_port": counter(),
+                    "dst_port": counter(),
+                },
+                "required": ["src", "dst"],
+            })
+        }
+    }
+}
+
+fn stats_value() -> Value {
+    let mut value = counters(&[
+        "total_length", "total_count", "ip_bytes", "wire_bytes", "first_seen", "last_seen",
+        "retransmissions", "connections", "empty_packets", "max_packet_size", "sequence",
+    ]);
+    value["properties"]["avg_packet_size"] = json!({ "type": "number" });
+    value["properties"]["bytes_per_sec"] = json!({ "type": "number", "description": "Over the last --rate-interval" });
+    value["properties"]["rate_bps"] = json!({ "type": "number", "description": "Only with --track-rates" });
+    value["properties"]["inter_arrival_ms"] = json!({ "type": "number", "description": "Only for UDP flows with --track-jitter" });
+    value["properties"]["jitter_ms"] = json!({ "type": "number", "description": "Only for UDP flows with --track-jitter" });
+    value["properties"]["window"] = json!({ "type": "integer", "minimum": 0, "description": "Latest advertised TCP window, scaled. Only with --track-windows" });
+    value["properties"]["min_window"] = json!({ "type": "integer", "minimum": 0, "description": "Only with --track-windows" });
+    value["properties"]["app_protocol"] = json!({ "enum": ["dns", "http", "tls", "ssh"], "description": "Only with --guess-protocol" });
+    value["required"].as_array_mut().unwrap().extend(["avg_packet_size".into(), "bytes_per_sec".into()]);
+    value
+}
+
+/// Flows with how long they have been quiet, in the order served
+fn aged(order: &str) -> Value {
+    let mut value = stats_value();
+    value["properties"]["flow"] = json!({ "$ref": "#/$defs/flow_key" });
+    value["properties"]["age"] = json!({ "type": "integer", "minimum": 0, "description": "Seconds since first_seen" });
+    value["properties"]["idle"] = json!({ "type": "integer", "minimum": 0, "description": "Seconds since last_seen" });
+    value["required"].as_array_mut().unwrap().extend(["flow".into(), "age".into(), "idle".into()]);
+    json!({ "type": "array", "description": order, "items": value })
+}
+
+fn oneway() -> Value {
+    let mut oneway = counters(&["udp_flows", "oneway_flows"]);
+    oneway["properties"]["ratio"] = json!({ "type": "number", "minimum": 0, "maximum": 1 });
+    oneway["properties"]["flows"] = aged(
+        "Unicast UDP flows older than ?after= (default 30s) without the reverse flow, the most packets first. \
+         Only with --granularity port",
+    );
+    oneway["required"].as_array_mut().unwrap().extend(["ratio".into(), "flows".into()]);
+    oneway
+}
+
+fn query() -> Value {
+    let mut query = counters(&["total", "offset", "limit"]);
+    query["properties"]["total"]["description"] = "Flows matching, before offset and limit".into();
+    let mut flow = stats_value();
+    flow["properties"]["flow"] = json!({ "$ref": "#/$defs/flow_key" });
+    flow["required"].as_array_mut().unwrap().push("flow".into());
+    query["properties"]["flows"] = json!({
+        "type": "array",
+        "description": "Flows matching all of ?host= (either end), ?cidr= (either end inside), ?proto=, ?port= (either port) \
+                        and ?min_bytes= (default 0). Sorted by ?sort= bytes (default), packets, first_seen, last_seen or flow, \
+                        ?order= desc, asc by default for flow, ties by flow key. ?limit= (default 100) of them from ?offset= (default 0)",
+        "items": flow,
+    });
+    query["required"].as_array_mut().unwrap().push("flows".into());
+    query
+}
+
+fn map_of(key: &str, value: Value) -> Value {
+    json!({ "type": "object", "description": format!("Keyed by {}", key), "additionalProperties": value })
+}
+
+/// Keyed by flow, arrays of entries with the json key format
+fn flows_of(key: &str, mut value: Value) -> Value {
+    if key_format::get() != KeyFormat::Json {
+        return map_of(key, value);
+    }
+    value["properties"]["flow"] = json!({ "$ref": "#/$defs/flow_key" });
+    value["required"].as_array_mut().unwrap().push("flow".into());
+    json!({ "type": "array", "items": value })
+}
+
+fn casts() -> Value {
+    let traffic = counters(&["frames", "bytes"]);
+    json!({
+        "type": "object",
+        "properties": { "unicast": traffic, "multicast": traffic, "broadcast": traffic },
+        "required": ["unicast", "multicast", "broadcast"],
+    })
+}
+
+fn tunnels() -> Value {
+    let traffic = counters(&["frames", "bytes"]);
+    json!({
+        "type": "object",
+        "description": "Outer IP datagrams of GRE and ESP, frames being packets here",
+        "properties": { "gre": traffic, "esp": traffic },
+        "required": ["gre", "esp"],
+    })
+}
+
+fn fcs_included() -> Value {
+    json!({
+        "type": "boolean",
+        "description": "Whether --fcs-included cut the FCS off the frames. Byte counts never include it, \
+                        false means the capture is assumed not to carry it",
+    })
+}
+
+fn link() -> Value {
+    json!({
+        "type": "object",
+        "properties": { "speed_bps": nullable(counter()), "mtu": nullable(counter()) },
+        "required": ["speed_bps", "mtu"],
+    })
+}
+
+fn runtime() -> Value {
+    let mut report = counters(&[
+        "frames", "frame_bytes", "jumbo_frames", "truncated", "padded", "queue_depth",
+        "queue_dropped", "malformed", "skipped", "filtered", "receive_errors", "save_errors",
+        "flows", "update_sequence", "flow_map_bytes",
+    ]);
+    for (name, schema) in [
+        ("casts", casts()),
+        ("tunnels", tunnels()),
+        ("fcs_included", fcs_included()),
+        ("http_addrs", json!({ "type": "array", "items": { "type": "string" } })),
+        ("rss_bytes", nullable(counter())),
+        ("link", link()),
+        ("kernel_packets", nullable(counter())),
+        ("kernel_dropped", nullable(counter())),
+        ("interface_dropped", nullable(counter())),
+    ] {
+        report["properties"][name] = schema;
+        report["required"].as_array_mut().unwrap().push(name.into());
+    }
+    report
+}
+
+fn peak_flow() -> Value {
+    let mut peak = counters(&["total_length", "at"]);
+    peak["properties"]["flow"] = json!({ "$ref": "#/$defs/flow_key" });
+    peak["required"].as_array_mut().unwrap().push("flow".into());
+    peak
+}
+
+fn families() -> Value {
+    let family = |description: &str| {
+        let mut family = counters(&["flows", "total_length", "ip_bytes", "total_count", "mismatched_flows"]);
+        family["description"] = description.into();
+        family["properties"]["mismatched_flows"]["description"] =
+            "Flows whose total_length and ip_bytes don't keep to that, e.g. from a database written before ip_bytes was counted".into();
+        family
+    };
+    json!({
+        "type": "object",
+        "properties": {
+            "ipv4": family("total_length is the IPv4 total length: header and payload, same as ip_bytes"),
+            "ipv6": family("total_length is the IPv6 payload length: without the 40 byte header, which ip_bytes has"),
+        },
+        "required": ["ipv4", "ipv6"],
+    })
+}
+
+fn summary() -> Value {
+    let mut summary = counters(&["flows", "total_length", "total_count"]);
+    for (name, schema) in [
+        ("bps", json!({ "type": "number" })),
+        ("flow_bps", flows_of("flow key", json!({
+            "type": "object",
+            "properties": { "bps": { "type": "number" } },
+            "required": ["bps"],
+        }))),
+        ("casts", casts()),
+        ("tunnels", tunnels()),
+        ("fcs_included", fcs_included()),
+        ("link", link()),
+        ("utilization", nullable(json!({ "type": "number", "description": "Percent of link.speed_bps" }))),
+        ("bps_1m", json!({ "type": "number", "description": "bps as a moving average over about a minute" })),
+        ("utilization_1m", nullable(json!({ "type": "number" }))),
+        ("bogons", counters(&["flows", "total_length", "total_count"])),
+        ("connections", counter()),
+        ("empty_packets", counter()),
+        ("data_packets", counter()),
+        ("cps", json!({ "type": "number", "description": "TCP connections opened per second between the last two publishes" })),
+        ("peak_flow", nullable(peak_flow())),
+        ("families", families()),
+    ] {
+        summary["properties"][name] = schema;
+        summary["required"].as_array_mut().unwrap().push(name.into());
+    }
+    summary
+}
+
+fn conversation() -> Value {
+    let mut conversation = counters(&[
+        "total_length", "total_count", "a_to_b_bytes", "b_to_a_bytes", "a_to_b_packets", "b_to_a_packets",
+        "first_seen", "last_seen",
+    ]);
+    conversation["properties"]["asymmetry"] = json!({
+        "type": "number",
+        "minimum": -1,
+        "maximum": 1,
+        "description": "(a_to_b_bytes - b_to_a_bytes) / total_length, a being the first endpoint of the key",
+    });
+    conversation["properties"]["last_direction"] = nullable(json!({
+        "enum": ["a_to_b", "b_to_a"],
+        "description": "Whichever sent at last_seen, either when both did within that second",
+    }));
+    conversation["required"].as_array_mut().unwrap().extend(["asymmetry".into(), "last_direction".into()]);
+    conversation
+}
+
+fn asymmetric() -> Value {
+    let mut asymmetric = counters(&["conversations", "one_sided"]);
+    asymmetric["properties"]["ratio"] = json!({ "type": "number", "minimum": 0, "maximum": 1 });
+    let mut flow = counters(&["age", "total_length", "total_count", "first_seen", "last_seen"]);
+    flow["properties"]["flow"] = json!({ "$ref": "#/$defs/flow_key", "description": "The direction seen" });
+    flow["required"].as_array_mut().unwrap().push("flow".into());
+    asymmetric["properties"]["flows"] = json!({
+        "type": "array",
+        "description": "Unicast conversations older than ?after= (default 30s) with nothing captured the other way, \
+                        the most bytes first",
+        "items": flow,
+    });
+    asymmetric["required"].as_array_mut().unwrap().extend(["ratio".into(), "flows".into()]);
+    asymmetric
+}
+
+/// Names are nProbe's, keep them as they are
+fn ntop_flows() -> Value {
+    let string = json!({ "type": "string" });
+    let mut flow = counters(&["L4_SRC_PORT", "L4_DST_PORT", "PROTOCOL", "IN_BYTES", "IN_PKTS", "FIRST_SWITCHED", "LAST_SWITCHED"]);
+    for name in ["IPV4_SRC_ADDR", "IPV4_DST_ADDR", "IPV6_SRC_ADDR", "IPV6_DST_ADDR"] {
+        flow["properties"][name] = string.clone();
+    }
+    json!({
+        "type": "array",
+        "description": "Flows for ntopng and other flow-JSON consumers, the IPV4_ or the IPV6_ addresses. \
+                        Ports and PROTOCOL are 0 with --granularity host",
+        "items": flow,
+    })
+}
+
+fn top_host() -> Value {
+    let mut host = counters(&[
+        "total_length", "total_count", "sent_bytes", "received_bytes", "sent_packets", "received_packets", "flows",
+    ]);
+    host["properties"]["host"] = json!({ "type": "string" });
+    host["required"].as_array_mut().unwrap().push("host".into());
+    json!({
+        "type": "array",
+        "description": "The ?n= (default 20) busiest addresses by ?by= bytes (the default), packets or flows, \
+                        sent and received, busiest first",
+        "items": host,
+    })
+}
+
+fn neighbor() -> Value {
+    let string = json!({ "type": "string" });
+    json!({
+        "type": "object",
+        "properties": {
+            "mac": nullable(string.clone()),
+            "router": { "type": "boolean" },
+            "hostname": nullable(string),
+            "learned_from": { "enum": ["ndp", "dhcp", "dhcpv6"] },
+            "last_seen": counter(),
+        },
+        "required": ["mac", "router", "hostname", "learned_from", "last_seen"],
+    })
+}
+
+fn alerts() -> Value {
+    let string = json!({ "type": "string" });
+    json!({
+        "type": "array",
+        "description": "Oldest first",
+        "items": {
+            "type": "object",
+            "properties": {
+                "kind": string,
+                "subject": string,
+                "message": string,
+                "at": counter(),
+                "details": { "type": "object" },
+            },
+            "required": ["kind", "subject", "message", "at", "details"],
+        },
+    })
+}
+
+/// JSON Schema of each endpoint's response, keyed by path
+pub fn schema() -> Value {
+    let stats = flows_of("flow key, see $defs/flow_key", stats_value());
+    let names = map_of("name", counters(&["total_length", "total_count", "hits"]));
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$defs": { "flow_key": flow_key() },
+        "type": "object",
+        "properties": {
+            "/stats": stats,
+            "/runtime": runtime(),
+            "/summary": summary(),
+            "/rate-history": {
+                "type": "array",
+                "description": "Aggregate rates between --history-step samples over the last ?range= (default 15m), oldest first. Empty without --history-step",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "at": { "type": "integer", "minimum": 0, "description": "Unix milliseconds" },
+                        "pps": { "type": "number" },
+                        "bps": { "type": "number" },
+                    },
+                    "required": ["at", "pps", "bps"],
+                },
+            },
+            "/active": aged("Seen within ?within= (default 60s), most recent first"),
+            "/idle": aged("Quiet for more than ?older= (default 300s), the longest quiet first"),
+            "/new": aged("First seen within ?since= (default 300s), the most bytes first"),
+            "/oneway": oneway(),
+            "/asymmetric": asymmetric(),
+            "/query": query(),
+            "/export/ntopng": ntop_flows(),
+            "/top": {
+                "type": "array",
+                "description": "The ?n= busiest flows by bytes, 20 by default, ?proto= ones only. With --sketch its heavy hitters, estimated. \
+                                ?by=smallpackets ranks flows of ?min_count= packets, 100 by default, by smallest average packet size",
+                "items": {
+                    "type": "object",
+                    "properties": { "flow": { "$ref": "#/$defs/flow_key" }, "total_length": counter(), "total_count": counter() },
+                    "required": ["flow", "total_length", "total_count"],
+                },
+            },
+            "/top-hosts": top_host(),
+            "/conversations": flows_of("\"a <-> b\", the flow key with the lower endpoint first", conversation()),
+            "/protocols": map_of("protocol name or number, \"unknown\" for flows without one", counters(&["total_length", "total_count", "flows"])),
+            "/services": map_of("service name, or port number", counters(&["total_length", "total_count", "flows"])),
+            "/sni": names,
+            "/http": names,
+            "/neighbors": map_of("IP address", neighbor()),
+            "/icmp": map_of("\"icmp\" or \"icmpv6\", then the type and code names, or numbers", counters(&["type", "code", "packets", "bytes"])),
+            "/hosts": map_of("source address, only with --track-fanout", json!({
+                "type": "object",
+                "properties": {
+                    "ports": counter(),
+                    "hosts": counter(),
+                    "approximate": { "type": "boolean" },
+                    "protocols": {
+                        "type": "array",
+                        "description": "Up to 32, sorted: protocol/port for TCP connection attempts and UDP, the protocol alone otherwise",
+                        "items": { "type": "string" },
+                    },
+                },
+                "required": ["ports", "hosts", "approximate", "protocols"],
+            })),
+            "/config": {
+                "type": "object",
+                "description": "The options in effect keyed by name, as --check prints them, with secrets redacted. \
+                                Reloadable ones as of the last SIGHUP",
+            },
+            "/db": {
+                "description": "The database as --db and --format would write it, an attachment. Only with --auth-token or --basic-auth",
+            },
+            "/recent.pcap": {
+                "description": "The last --packet-ring frames as a pcap file, an attachment. Only with --auth-token or --basic-auth",
+            },
+            "/debug/packet": {
+                "type": "object",
+                "description": "POST, only with --debug. packet when counted, reason otherwise",
+                "properties": {
+                    "counted": { "type": "boolean" },
+                    "packet": { "type": "object" },
+                    "reason": { "type": "string" },
+                    "findings": { "type": "array", "items": { "type": "string" } },
+                },
+                "required": ["counted", "findings"],
+            },
+            "/scans": alerts(),
+            "/alerts": alerts(),
+        },
+    })
+}
@@ -0,0 +1,52 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use serde_json::json;
+
+use crate::alert::Alert;
+
+/// Past that, hosts are neither remembered nor alerted on
+const MAX_SEEN: usize = 1 << 20;
+
+/// Source addresses seen so far, for --alert-new-hosts. Kept next to the database
+/// so a restart doesn't make every known host new again.
+#[derive(Default)]
+pub struct SeenHosts {
+    hosts: HashSet<IpAddr>,
+    // Changed since the last `changed`
+    dirty: bool,
+}
+
+impl SeenHosts {
+    /// Written out on the first save even unchanged, that's what makes --forget-hosts stick
+    pub fn new(hosts: HashSet<IpAddr>) -> Self {
+        SeenHosts { hosts, dirty: true }
+    }
+
+    pub fn observe(&mut self, host: IpAddr) -> Option<Alert> {
+        if host.is_unspecified() || self.hosts.len() >= MAX_SEEN || !self.hosts.insert(host) {
+            return None;
+        }
+        self.dirty = true;
+        Some(Alert::new("new_host", host.to_string(), format!("New host {}", host), json!({ "known": self.hosts.len() })))
+    }
+
+    pub fn known(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Sorted copy when something was added since the last call
+    pub fn changed(&mut self) -> Option<Vec<IpAddr>> {
+        if !std::mem::take(&mut self.dirty) {
+            return None;
+        }
+        let mut hosts: Vec<_> = self.hosts.iter().copied().collect();
+        hosts.sort();
+        Some(hosts)
+    }
+
+    /// What `changed` gave didn't make it to disk, give it again next time
+    pub fn unsaved(&mut self) {
+        self.dirty = true;
+    }
+}
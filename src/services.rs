@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::app_protocol::AppProtocol;
+use crate::data::Stats;
+
+/// Well-known ports worth a name, same name for TCP and UDP
+const WELL_KNOWN: [(u16, &str); 48] = [
+    (20, "ftp-data"), (21, "ftp"), (22, "ssh"), (23, "telnet"), (25, "smtp"), (53, "domain"),
+    (67, "dhcp"), (68, "dhcp"), (69, "tftp"), (80, "http"), (88, "kerberos"), (110, "pop3"),
+    (123, "ntp"), (137, "netbios-ns"), (138, "netbios-dgm"), (139, "netbios-ssn"), (143, "imap"),
+    (161, "snmp"), (162, "snmp-trap"), (179, "bgp"), (389, "ldap"), (443, "https"), (445, "smb"),
+    (465, "smtps"), (500, "isakmp"), (514, "syslog"), (546, "dhcpv6"), (547, "dhcpv6"),
+    (587, "submission"), (636, "ldaps"), (853, "domain-s"), (993, "imaps"), (995, "pop3s"),
+    (1194, "openvpn"), (1433, "mssql"), (1883, "mqtt"), (3306, "mysql"), (3389, "rdp"),
+    (4500, "ipsec-nat-t"), (5353, "mdns"), (5355, "llmnr"), (5432, "postgresql"), (5900, "vnc"),
+    (6379, "redis"), (8080, "http-alt"), (8443, "https-alt"), (9092, "kafka"), (51820, "wireguard"),
+];
+
+fn name(port: u16) -> Option<&'static str> {
+    WELL_KNOWN.iter().find(|(p, _)| *p == port).map(|(_, n)| *n)
+}
+
+#[derive(Serialize, Default)]
+pub struct ServiceValue {
+    pub total_length: u128,
+    pub total_count: u128,
+    pub flows: usize,
+}
+
+/// Keyed by service name, or the port number when it has none
+#[derive(Serialize, Default)]
+pub struct Services(pub HashMap<String, ServiceValue>);
+
+/// Group flows by the service they talk to, --guess-protocol's label first. Failing
+/// that by port: replies flow towards the client's ephemeral port, so a known port on
+/// either end counts, and failing that the lower one, servers rarely listen up in the
+/// ephemeral range. Flows without ports (--granularity host) are left out.
+pub fn services(stats: &Stats) -> Services {
+    let mut services = Services::default();
+    for (k, v) in &stats.0 {
+        let t = match k.1 {
+            Some(t) if t.source_port != 0 || t.dest_port != 0 => t,
+            _ => continue
+        };
+        let service = v.app_protocol.map(AppProtocol::name)
+            .or_else(|| name(t.dest_port))
+            .or_else(|| name(t.source_port))
+            .map(str::to_string)
+            .unwrap_or_else(|| t.dest_port.min(t.source_port).to_string());
+        let entry = services.0.entry(service).or_default();
+        entry.total_length += v.total_length;
+        entry.total_count += v.total_count;
+        entry.flows += 1;
+    }
+    services
+}
@@ -0,0 +1,155 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::data::{update_db_batch, PeakFlow, Stats, StatsKey, StatsUpdate, StatsValue};
+
+/// `Stats` split by key hash, each part behind its own lock, so writers and
+/// readers only ever hold up a fraction of the map
+pub struct ShardedStats {
+    shards: Vec<Mutex<Stats>>,
+    hasher: RandomState,
+    track_rates: bool,
+    sequence: AtomicU64,
+    rolled_at: Mutex<Instant>,
+    /// Outlives the flow itself, resets and rollovers included
+    peak: Mutex<Option<PeakFlow>>,
+}
+
+impl ShardedStats {
+    pub fn new(stats: Stats, shards: usize, track_rates: bool) -> Self {
+        let shards = shards.max(1);
+        // Carry on from a loaded database, so sequences keep growing across restarts
+        let sequence = stats.0.values().map(|v| v.sequence).max().unwrap_or(0);
+        let sharded = ShardedStats {
+            shards: (0..shards).map(|_| Mutex::new(Stats::new())).collect(),
+            hasher: RandomState::new(),
+            track_rates,
+            sequence: AtomicU64::new(sequence),
+            rolled_at: Mutex::new(Instant::now()),
+            peak: Mutex::new(PeakFlow::of(&stats)),
+        };
+        for (k, mut v) in stats.0 {
+            // What was loaded is not traffic of the first interval
+            v.rolled_length = v.total_length;
+            sharded.shards[sharded.shard_of(&k)].lock().unwrap().0.insert(k, v);
+        }
+        sharded
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard_of(&self, key: &StatsKey) -> usize {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    pub fn update_batch(&self, batch: impl IntoIterator<Item = StatsUpdate>) {
+        let mut batch: Vec<_> = batch.into_iter().map(|u| (self.shard_of(&u.key), u)).collect();
+        batch.sort_by_key(|(shard, _)| *shard);
+        let mut batch = batch.into_iter().peekable();
+        while let Some(&(shard, _)) = batch.peek() {
+            let mut group = Vec::new();
+            while let Some((_, update)) = batch.next_if(|(s, _)| *s == shard) {
+                group.push(update);
+            }
+            // Taken under the lock, so a flow's sequence only ever goes up
+            let db = self.shards[shard].lock().unwrap();
+            let sequence = self.sequence.fetch_add(group.len() as u64, Ordering::Relaxed) + group.len() as u64;
+            let peak = update_db_batch(db, group, sequence, self.track_rates);
+            let mut recorded = self.peak.lock().unwrap();
+            *recorded = PeakFlow::max(*recorded, peak);
+        }
+    }
+
+    /// The biggest flow since start, whether still around or not
+    pub fn peak(&self) -> Option<PeakFlow> {
+        *self.peak.lock().unwrap()
+    }
+
+    /// Updates applied so far, also the newest `StatsValue::sequence`
+    pub fn sequence(&self) -> u64 {
+        self.sequence.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().0.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Estimated bytes held by the maps: every allocated bucket, plus hashbrown's control byte
+    pub fn footprint(&self) -> usize {
+        let bucket = std::mem::size_of::<(StatsKey, StatsValue)>() + 1;
+        self.shards.iter().map(|s| s.lock().unwrap().0.capacity() * bucket).sum()
+    }
+
+    /// Copy of the whole map, taken one shard at a time
+    pub fn snapshot(&self) -> Stats {
+        let mut stats = Stats::new();
+        for shard in &self.shards {
+            stats.0.extend(shard.lock().unwrap().0.iter().map(|(k, v)| (*k, v.clone())));
+        }
+        stats
+    }
+
+    /// Empty the map and hand back what it held. Each shard is swapped out
+    /// under its lock so no update falls between the copy and the clear.
+    pub fn take(&self) -> Stats {
+        let mut stats = Stats::new();
+        for shard in &self.shards {
+            stats.0.extend(std::mem::take(&mut shard.lock().unwrap().0));
+        }
+        stats
+    }
+
+    /// Put back flows `take` handed out, summed with what the same flows got since
+    pub fn merge(&self, stats: Stats) {
+        let mut parts: Vec<_> = (0..self.shards.len()).map(|_| Stats::new()).collect();
+        for (k, v) in stats.0 {
+            parts[self.shard_of(&k)].0.insert(k, v);
+        }
+        for (shard, part) in self.shards.iter().zip(parts) {
+            shard.lock().unwrap().merge(part);
+        }
+    }
+
+    /// Set each flow's `bytes_per_sec` to what it grew since the last call, per second
+    pub fn roll_rates(&self) {
+        let mut rolled_at = self.rolled_at.lock().unwrap();
+        let now = Instant::now();
+        let secs = now.duration_since(*rolled_at).as_secs_f64();
+        if secs <= 0.0 {
+            return;
+        }
+        for shard in &self.shards {
+            for v in shard.lock().unwrap().0.values_mut() {
+                v.bytes_per_sec = v.total_length.saturating_sub(v.rolled_length) as f64 / secs;
+                v.rolled_length = v.total_length;
+            }
+        }
+        *rolled_at = now;
+    }
+
+    /// Flows whose `bytes_per_sec` is over `bytes_per_sec`, with it
+    pub fn faster_than(&self, bytes_per_sec: f64) -> Vec<(StatsKey, f64)> {
+        let mut flows = Vec::new();
+        for shard in &self.shards {
+            flows.extend(shard.lock().unwrap().0.iter()
+                .filter(|(_, v)| v.bytes_per_sec > bytes_per_sec)
+                .map(|(k, v)| (*k, v.bytes_per_sec)));
+        }
+        flows
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().unwrap().0.clear();
+        }
+    }
+}
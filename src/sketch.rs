@@ -0,0 +1,102 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use serde::Serialize;
+
+use crate::data::{StatsKey, StatsUpdate};
+
+/// --sketch: bytes and packets per flow in `width * depth` counters whatever the
+/// number of flows, plus the `top` flows with the most bytes. A count is never
+/// under the truth, and over it by at most e / width of all bytes (or packets)
+/// with probability 1 - e^-depth. There is no list of all flows to go with it.
+pub struct Sketch {
+    width: usize,
+    /// One hasher per row
+    rows: Vec<RandomState>,
+    /// (bytes, packets), row after row
+    counters: Vec<(u64, u64)>,
+    top: usize,
+    heavy: HashMap<StatsKey, (u64, u64)>,
+    /// Fewest bytes in `heavy` once full, what a newcomer has to beat
+    floor: u64,
+}
+
+impl Sketch {
+    pub fn new(width: usize, depth: usize, top: usize) -> Self {
+        let (width, depth, top) = (width.max(1), depth.max(1), top.max(1));
+        Sketch {
+            width,
+            rows: (0..depth).map(|_| RandomState::new()).collect(),
+            counters: vec![(0, 0); width * depth],
+            top,
+            heavy: HashMap::with_capacity(top + 1),
+            floor: 0,
+        }
+    }
+
+    fn cell(&self, row: usize, key: &StatsKey) -> usize {
+        row * self.width + (self.rows[row].hash_one(key) % self.width as u64) as usize
+    }
+
+    /// Bytes and packets of the flow, each the smallest over the rows
+    pub fn estimate(&self, key: &StatsKey) -> (u64, u64) {
+        (0..self.rows.len()).map(|row| self.counters[self.cell(row, key)])
+            .fold((u64::MAX, u64::MAX), |(b, p), (bytes, packets)| (b.min(bytes), p.min(packets)))
+    }
+
+    pub fn update(&mut self, update: &StatsUpdate) {
+        let mut estimate = (u64::MAX, u64::MAX);
+        for row in 0..self.rows.len() {
+            let cell = self.cell(row, &update.key);
+            let (bytes, packets) = &mut self.counters[cell];
+            *bytes += update.length as u64;
+            *packets += 1;
+            estimate = (estimate.0.min(*bytes), estimate.1.min(*packets));
+        }
+        if let Some(heavy) = self.heavy.get_mut(&update.key) {
+            // The lightest grew, the floor may have gone up with it
+            let lightest = heavy.0 <= self.floor;
+            *heavy = estimate;
+            if lightest {
+                self.raise_floor();
+            }
+            return;
+        }
+        if self.heavy.len() >= self.top {
+            if estimate.0 <= self.floor {
+                return;
+            }
+            let lightest = self.heavy.iter().min_by_key(|(_, (bytes, _))| *bytes).map(|(k, _)| *k);
+            if let Some(lightest) = lightest {
+                self.heavy.remove(&lightest);
+            }
+        }
+        self.heavy.insert(update.key, estimate);
+        self.raise_floor();
+    }
+
+    fn raise_floor(&mut self) {
+        if self.heavy.len() >= self.top {
+            self.floor = self.heavy.values().map(|(bytes, _)| *bytes).min().unwrap_or(0);
+        }
+    }
+
+    /// The most bytes first
+    pub fn heavy_hitters(&self) -> Vec<HeavyHitter> {
+        let mut flows: Vec<_> = self.heavy.iter()
+            .map(|(k, (bytes, packets))| HeavyHitter { flow: *k, total_length: *bytes, total_count: *packets })
+            .collect();
+        flows.sort_by_key(|h| Reverse(h.total_length));
+        flows
+    }
+}
+
+/// A flow of /top, its counts as estimated
+#[derive(Serialize)]
+pub struct HeavyHitter {
+    pub flow: StatsKey,
+    pub total_length: u64,
+    pub total_count: u64,
+}
@@ -0,0 +1,101 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::data::{unix_now, Stats};
+use crate::db_format::{self, DbFormat};
+
+const PREFIX: &str = "stats-";
+const SUFFIX: &str = ".json";
+const GZIP_SUFFIX: &str = ".json.gz";
+
+/// Where --snapshot-dir snapshots go and how many of them stay
+pub struct Snapshots {
+    pub dir: PathBuf,
+    pub gzip: bool,
+    /// The newest that many
+    pub keep: Option<usize>,
+    /// The newest that fit in that many bytes, always at least one
+    pub max_bytes: Option<u64>,
+}
+
+impl Snapshots {
+    /// Write `stats` to `dir/stats-<unix seconds>.json`, the same format as the database,
+    /// gzipped to `.json.gz` with `gzip`. A second one within the same second goes to
+    /// `stats-<unix seconds>-<n>.json`. Then drop what `keep` and `max_bytes` leave out.
+    pub fn write(&self, stats: &Stats) -> io::Result<PathBuf> {
+        let suffix = if self.gzip { GZIP_SUFFIX } else { SUFFIX };
+        let mut data = db_format::encode(stats, DbFormat::Json);
+        if self.gzip {
+            data = db_format::gzip(&data);
+        }
+        let now = unix_now();
+        let mut n = 0;
+        loop {
+            let name = match n {
+                0 => format!("{}{}{}", PREFIX, now, suffix),
+                n => format!("{}{}-{}{}", PREFIX, now, n, suffix)
+            };
+            let path = self.dir.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    if let Err(e) = file.write_all(&data) {
+                        let _ = fs::remove_file(&path);
+                        return Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)));
+                    }
+                    self.prune();
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+            }
+        }
+    }
+
+    fn prune(&self) {
+        if self.keep.is_none() && self.max_bytes.is_none() {
+            return;
+        }
+        let mut snapshots = list(&self.dir);
+        // Newest first
+        snapshots.sort_by_key(|(p, _)| std::cmp::Reverse(taken_at(p)));
+        let mut total = 0;
+        let stale = snapshots.iter().enumerate().position(|(i, (_, size))| {
+            total += size;
+            i > 0 && (self.keep.is_some_and(|k| i >= k) || self.max_bytes.is_some_and(|m| total > m))
+        });
+        for (p, _) in &snapshots[stale.unwrap_or(snapshots.len())..] {
+            if let Err(e) = fs::remove_file(p) {
+                warn!("Fail to remove old snapshot {}: {}", p.display(), e);
+            }
+        }
+    }
+}
+
+/// (unix seconds, n) out of `stats-<unix seconds>[-<n>].json[.gz]`
+fn taken_at(path: &Path) -> (u64, u64) {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let stem = name.strip_prefix(PREFIX).unwrap_or(name);
+    let stem = stem.strip_suffix(GZIP_SUFFIX).or_else(|| stem.strip_suffix(SUFFIX)).unwrap_or(stem);
+    let (secs, n) = stem.split_once('-').unwrap_or((stem, "0"));
+    (secs.parse().unwrap_or(0), n.parse().unwrap_or(0))
+}
+
+/// Snapshots in `dir`, gzipped or not, with their size
+fn list(dir: &Path) -> Vec<(PathBuf, u64)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Fail to list snapshots in {}: {}", dir.display(), e);
+            return vec![];
+        }
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_str()
+            .is_some_and(|n| n.starts_with(PREFIX) && (n.ends_with(SUFFIX) || n.ends_with(GZIP_SUFFIX))))
+        .map(|e| (e.path(), e.metadata().map_or(0, |m| m.len())))
+        .collect()
+}
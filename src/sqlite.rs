@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+use rusqlite::{params, Connection};
+
+use crate::data::StatsKey;
+
+/// How long to wait on someone else's lock, e.g. a query running meanwhile
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Days since 1970-01-01 to (year, month, day), Howard Hinnant's civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if month <= 2 { yoe + era * 400 + 1 } else { yoe + era * 400 }, month, day)
+}
+
+/// flows_YYYYMMDD, the UTC day of unix seconds `at`
+fn partition(at: u64) -> String {
+    let (year, month, day) = civil_from_days((at / 86400) as i64);
+    format!("flows_{:04}{:02}{:02}", year, month, day)
+}
+
+fn int(n: u128) -> i64 {
+    i64::try_from(n).unwrap_or(i64::MAX)
+}
+
+/// A database of interval records, a table per UTC day:
+/// `flows_YYYYMMDD(start, end, flow, src, dst, protocol, src_port, dst_port, total_length, total_count)`,
+/// indexed by start and by flow. flow is written "a -> b" whatever --key-format says.
+pub struct History {
+    db: Connection,
+    /// Partitions known to exist
+    tables: HashSet<String>,
+}
+
+impl History {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let db = Connection::open(path).map_err(|e| format!("Fail to open {}: {}", path.display(), e))?;
+        db.busy_timeout(BUSY_TIMEOUT).map_err(|e| e.to_string())?;
+        Ok(History { db, tables: HashSet::new() })
+    }
+
+    fn create(&mut self, table: &str) -> Result<(), String> {
+        if self.tables.contains(table) {
+            return Ok(());
+        }
+        self.db.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {t} (start INTEGER NOT NULL, end INTEGER NOT NULL, flow TEXT NOT NULL, \
+             src TEXT NOT NULL, dst TEXT NOT NULL, protocol INTEGER, src_port INTEGER, dst_port INTEGER, \
+             total_length INTEGER NOT NULL, total_count INTEGER NOT NULL); \
+             CREATE INDEX IF NOT EXISTS {t}_start ON {t} (start); \
+             CREATE INDEX IF NOT EXISTS {t}_flow ON {t} (flow, start);",
+            t = table,
+        )).map_err(|e| e.to_string())?;
+        self.tables.insert(table.to_string());
+        Ok(())
+    }
+
+    /// One row per flow of the interval from `start` to `end`, all or none of them
+    pub fn append<'a>(&mut self, start: u64, end: u64, flows: impl IntoIterator<Item = (&'a StatsKey, u128, u128)>)
+        -> Result<(), String> {
+        let table = partition(start);
+        self.create(&table)?;
+        // Rolled back when dropped uncommitted
+        let tx = self.db.transaction().map_err(|e| e.to_string())?;
+        {
+            let mut insert = tx.prepare(&format!("INSERT INTO {} VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", table))
+                .map_err(|e| e.to_string())?;
+            for (flow, total_length, total_count) in flows {
+                let (source, dest) = flow.addrs();
+                let transport = flow.1;
+                insert.execute(params![
+                    start as i64,
+                    end as i64,
+                    flow.to_string(),
+                    source.to_string(),
+                    dest.to_string(),
+                    transport.map(|t| t.protocol),
+                    transport.map(|t| t.source_port),
+                    transport.map(|t| t.dest_port),
+                    int(total_length),
+                    int(total_count),
+                ]).map_err(|e| e.to_string())?;
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+}
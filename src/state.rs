@@ -0,0 +1,220 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::alert::Alerter;
+use crate::config::Live;
+use crate::connection::ConnectionRate;
+use crate::data::{NameStats, Stats};
+use crate::fanout::Fanout;
+use crate::history::History;
+use crate::icmp::IcmpStats;
+use crate::iface::Link;
+use crate::learn::Warmup;
+use crate::neighbor::Neighbors;
+use crate::ring::PacketRing;
+use crate::runtime::Runtime;
+use crate::seen::SeenHosts;
+use crate::shard::ShardedStats;
+use crate::sketch::Sketch;
+use crate::summary::{self, BpsAverage};
+
+/// Everything the capture side produces and the readers look at
+pub struct State {
+    pub db: ShardedStats,
+    pub runtime: Runtime,
+    pub sni: Mutex<NameStats>,
+    pub http: Mutex<NameStats>,
+    pub neighbors: Mutex<Neighbors>,
+    pub icmp: Mutex<IcmpStats>,
+    /// Only fed with --track-fanout
+    pub fanout: Mutex<Fanout>,
+    /// Only fed with --history-step
+    pub history: Mutex<History>,
+    /// Only fed with --alert-new-hosts
+    pub seen_hosts: Mutex<SeenHosts>,
+    /// With --sketch, fed instead of `db`
+    pub sketch: Option<Mutex<Sketch>>,
+    /// Only with --packet-ring
+    pub ring: Option<Mutex<PacketRing>>,
+    /// Over from the start without --learn-duration
+    pub warmup: Warmup,
+    pub live: RwLock<Live>,
+    pub link: Link,
+    pub alerts: Alerter,
+    /// Read-only copy of `db` for the HTTP side and the `db.sequence()` it has everything up to, see `publish`
+    published: RwLock<(Arc<Stats>, u64)>,
+    /// Held by a publish, and by a reset until it published the emptied map
+    publishing: Mutex<()>,
+    /// Fed on every publish
+    bps_1m: Mutex<BpsAverage>,
+    cps: Mutex<ConnectionRate>,
+}
+
+impl State {
+    pub fn new(db: Stats, shards: usize, track_rates: bool, live: Live, link: Link, alerts: Alerter, fanout: Fanout) -> Self {
+        let db = ShardedStats::new(db, shards, track_rates);
+        let published = RwLock::new((Arc::new(db.snapshot()), db.sequence()));
+        State {
+            db,
+            runtime: Runtime::default(),
+            sni: Mutex::new(NameStats::default()),
+            http: Mutex::new(NameStats::default()),
+            neighbors: Mutex::new(Neighbors::default()),
+            icmp: Mutex::new(IcmpStats::default()),
+            fanout: Mutex::new(fanout),
+            history: Mutex::new(History::new(0)),
+            seen_hosts: Mutex::new(SeenHosts::default()),
+            sketch: None,
+            ring: None,
+            warmup: Warmup::default(),
+            live: RwLock::new(live),
+            link,
+            alerts,
+            published,
+            publishing: Mutex::new(()),
+            bps_1m: Mutex::new(BpsAverage::default()),
+            cps: Mutex::new(ConnectionRate::default()),
+        }
+    }
+
+    /// Keep `history` instead of nothing
+    pub fn with_history(mut self, history: History) -> Self {
+        self.history = Mutex::new(history);
+        self
+    }
+
+    /// Start from hosts seen before
+    pub fn with_seen_hosts(mut self, seen: SeenHosts) -> Self {
+        self.seen_hosts = Mutex::new(seen);
+        self
+    }
+
+    /// Count into `sketch` rather than `db`
+    pub fn with_sketch(mut self, sketch: Sketch) -> Self {
+        self.sketch = Some(Mutex::new(sketch));
+        self
+    }
+
+    /// Keep the last frames in `ring`
+    pub fn with_ring(mut self, ring: PacketRing) -> Self {
+        self.ring = Some(Mutex::new(ring));
+        self
+    }
+
+    /// Hold new host and rate alerts back for a while
+    pub fn with_warmup(mut self, warmup: Warmup) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Replace the published copy with the current flows
+    pub fn publish(&self) {
+        let _publishing = self.publishing.lock().unwrap();
+        self.publish_now();
+    }
+
+    /// Empty `db` and hand back what it held. Readers go straight from the old flows to
+    /// none, no publish meanwhile copies a half emptied map.
+    pub fn reset(&self) -> Stats {
+        let _publishing = self.publishing.lock().unwrap();
+        let stats = self.db.take();
+        self.publish_now();
+        stats
+    }
+
+    fn publish_now(&self) {
+        // Read first: whatever took a sequence up to here is applied by the time its shard is copied
+        let sequence = self.db.sequence();
+        let snapshot = Arc::new(self.db.snapshot());
+        self.bps_1m.lock().unwrap().observe(summary::bps(&snapshot));
+        self.cps.lock().unwrap().observe(summary::connections(&snapshot));
+        *self.published.write().unwrap() = (snapshot, sequence);
+    }
+
+    /// Aggregate bps over about the last minute
+    pub fn bps_1m(&self) -> f64 {
+        self.bps_1m.lock().unwrap().bps()
+    }
+
+    /// TCP connections opened per second as of the last publish
+    pub fn cps(&self) -> f64 {
+        self.cps.lock().unwrap().per_sec()
+    }
+
+    /// Slightly stale but consistent flows, never waits on capture
+    pub fn published(&self) -> Arc<Stats> {
+        self.published.read().unwrap().0.clone()
+    }
+
+    /// `published` with the sequence it has every update up to, flows may be newer
+    pub fn published_at(&self) -> (Arc<Stats>, u64) {
+        self.published.read().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use either::Either;
+
+    use crate::config::QueueFull;
+    use crate::data::{Ipv4StatsKey, StatsKey, StatsValue};
+
+    use super::*;
+
+    const FLOWS: u32 = 20000;
+
+    fn state() -> State {
+        let mut stats = Stats::new();
+        for i in 0..FLOWS {
+            let key = Ipv4StatsKey { source: Ipv4Addr::from(0x0a000000 + i), dest: Ipv4Addr::new(10, 255, 0, 1) };
+            stats.0.insert(StatsKey(Either::Left(key), None), StatsValue { total_length: 100, total_count: 1, ..StatsValue::default() });
+        }
+        let live = Live {
+            filter: None,
+            macs: Vec::new(),
+            vlans: Vec::new(),
+            queue_full: QueueFull::Drop,
+            unicast_only: false,
+            stdout_interval: None,
+            stdout_top: 0,
+        };
+        State::new(stats, 16, false, live, Link::default(), Alerter::new(None), Fanout::new(false))
+    }
+
+    #[test]
+    fn reset_is_never_published_half_done() {
+        let state = state();
+        let done = AtomicBool::new(false);
+        let seen = thread::scope(|s| {
+            let publisher = s.spawn(|| {
+                let mut seen = HashSet::new();
+                while !done.load(Ordering::Relaxed) {
+                    state.publish();
+                    seen.insert(state.published().0.len());
+                }
+                seen
+            });
+            let reader = s.spawn(|| {
+                let mut seen = HashSet::new();
+                while !done.load(Ordering::Relaxed) {
+                    seen.insert(state.published().0.len());
+                }
+                seen
+            });
+            thread::sleep(std::time::Duration::from_millis(20));
+            let taken = state.reset();
+            thread::sleep(std::time::Duration::from_millis(20));
+            done.store(true, Ordering::Relaxed);
+            assert_eq!(taken.0.len(), FLOWS as usize);
+            let mut seen = publisher.join().unwrap();
+            seen.extend(reader.join().unwrap());
+            seen
+        });
+        assert!(seen.is_subset(&HashSet::from([0, FLOWS as usize])), "{:?}", seen);
+        assert_eq!(state.published().0.len(), 0);
+    }
+}
@@ -0,0 +1,76 @@
+use std::io;
+use std::net::UdpSocket;
+
+use log::Level;
+
+use crate::logging::WarnOnce;
+use crate::state::State;
+
+/// Keeps datagrams under a typical 1500 bytes MTU
+const MAX_DATAGRAM: usize = 1432;
+
+/// A flow key as one metric name segment, dots in it would nest
+fn sanitize(s: &str) -> String {
+    s.replace(" -> ", "_to_").chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+/// Gauges over UDP, optionally with DogStatsD tags for the per flow ones
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    top: usize,
+    tags: bool,
+    failing: WarnOnce,
+}
+
+impl StatsdSink {
+    pub fn new(addr: &str, prefix: String, top: usize, tags: bool) -> io::Result<Self> {
+        let socket = UdpSocket::bind(if addr.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" })?;
+        socket.connect(addr)?;
+        Ok(StatsdSink { socket, prefix, top, tags, failing: WarnOnce::new(Level::Warn, module_path!()) })
+    }
+
+    fn lines(&self, state: &State) -> Vec<String> {
+        let stats = state.published();
+        let p = &self.prefix;
+        let mut lines = vec![
+            format!("{}.total_bytes:{}|g", p, stats.0.values().map(|v| v.total_length).sum::<u128>()),
+            format!("{}.total_packets:{}|g", p, stats.0.values().map(|v| v.total_count).sum::<u128>()),
+            format!("{}.flows:{}|g", p, stats.0.len()),
+        ];
+        for (k, v) in stats.top(self.top) {
+            if self.tags {
+                // Tag values can't hold commas, the key has none
+                lines.push(format!("{}.flow.bytes:{}|g|#flow:{}", p, v.total_length, k));
+            } else {
+                lines.push(format!("{}.flow.{}.bytes:{}|g", p, sanitize(&k.to_string()), v.total_length));
+            }
+        }
+        lines
+    }
+
+    /// As many lines per datagram as fit
+    pub fn send(&self, state: &State) -> io::Result<()> {
+        let mut datagram = String::new();
+        for line in self.lines(state) {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                self.socket.send(datagram.as_bytes())?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            self.socket.send(datagram.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Called every --statsd-interval. Nobody listening shows up as refused sends, warned about once
+    pub fn round(&mut self, state: &State) {
+        let sent = self.send(state);
+        let _ = self.failing.report(sent, "send to StatsD");
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use either::Either;
+use serde::{Serialize, Serializer};
+
+use crate::bogon::{bogons_of, Bogons};
+use crate::data::{unix_now, PeakFlow, Stats, StatsKey};
+use crate::iface::Link;
+use crate::key_format::{self, KeyFormat};
+use crate::packet::IPV6_HEADER_LEN;
+use crate::runtime::{Casts, Runtime, Tunnels};
+
+#[derive(Serialize)]
+pub struct Summary<'a> {
+    pub flows: usize,
+    pub total_length: u128,
+    pub total_count: u128,
+    /// Aggregate over all flows
+    pub bps: f64,
+    /// Only flows that saw traffic lately
+    #[serde(serialize_with = "flow_bps")]
+    pub flow_bps: HashMap<&'a StatsKey, f64>,
+    /// All frames seen, whether they made it into the flows or not
+    pub casts: &'a Casts,
+    /// GRE and ESP packets, all seen too
+    pub tunnels: &'a Tunnels,
+    /// See `Runtime::fcs_included`, frame and wire bytes never count the FCS
+    pub fcs_included: bool,
+    pub link: &'a Link,
+    /// Percent of the link speed, null when the speed is unknown
+    pub utilization: Option<f64>,
+    /// `bps` averaged over about a minute, see `BpsAverage`
+    pub bps_1m: f64,
+    pub utilization_1m: Option<f64>,
+    pub bogons: Bogons,
+    /// TCP connections opened, over all flows
+    pub connections: u128,
+    /// TCP and UDP packets without payload
+    pub empty_packets: u128,
+    /// All the others
+    pub data_packets: u128,
+    /// Connections opened per second between the last two publishes
+    pub cps: f64,
+    /// The biggest flow since start, even if reset or rolled over since
+    pub peak_flow: Option<PeakFlow>,
+    pub families: Families,
+}
+
+/// Flows of one address family. total_length is what the IP header gives: the whole
+/// datagram for IPv4, the payload without the fixed 40 byte header for IPv6.
+/// ip_bytes has the header either way.
+#[derive(Serialize, Default)]
+pub struct Family {
+    pub flows: usize,
+    pub total_length: u128,
+    pub ip_bytes: u128,
+    pub total_count: u128,
+    /// Flows whose total_length and ip_bytes don't agree with the above, e.g. from a
+    /// database written before ip_bytes was counted
+    pub mismatched_flows: usize,
+}
+
+#[derive(Serialize, Default)]
+pub struct Families {
+    pub ipv4: Family,
+    pub ipv6: Family,
+}
+
+/// Per family totals, so IPv4 and IPv6 bytes aren't taken for the same thing
+pub fn families(stats: &Stats) -> Families {
+    let mut families = Families::default();
+    for (k, v) in &stats.0 {
+        let (family, header_bytes) = match k.0 {
+            Either::Left(_) => (&mut families.ipv4, 0),
+            Either::Right(_) => (&mut families.ipv6, IPV6_HEADER_LEN as u128 * v.total_count)
+        };
+        family.flows += 1;
+        family.total_length += v.total_length;
+        family.ip_bytes += v.ip_bytes;
+        family.total_count += v.total_count;
+        if v.ip_bytes != v.total_length + header_bytes {
+            family.mismatched_flows += 1;
+        }
+    }
+    families
+}
+
+#[derive(Serialize)]
+struct Bps {
+    bps: f64,
+}
+
+// Entries of the json key format need an object to put the key in
+fn flow_bps<S>(flows: &HashMap<&StatsKey, f64>, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+    match key_format::get() {
+        KeyFormat::Json => key_format::serialize_flows(flows.iter().map(|(k, bps)| (k, Bps { bps: *bps })), serializer),
+        _ => serializer.collect_map(flows)
+    }
+}
+
+/// Seconds it takes the average to move 63% of the way to a new rate
+const AVERAGE_SECS: f64 = 60.0;
+
+/// Moving average of the aggregate bps, weighted by the time between samples
+/// so it doesn't depend on --publish-interval
+#[derive(Default)]
+pub struct BpsAverage {
+    last: Option<Instant>,
+    bps: f64,
+}
+
+impl BpsAverage {
+    pub fn observe(&mut self, bps: f64) {
+        let now = Instant::now();
+        self.bps = match self.last {
+            Some(last) => {
+                let weight = 1.0 - (-now.duration_since(last).as_secs_f64() / AVERAGE_SECS).exp();
+                self.bps + weight * (bps - self.bps)
+            }
+            None => bps
+        };
+        self.last = Some(now);
+    }
+
+    pub fn bps(&self) -> f64 {
+        self.bps
+    }
+}
+
+fn flow_rates(stats: &Stats) -> HashMap<&StatsKey, f64> {
+    let now = unix_now();
+    stats.0.iter()
+        .map(|(k, v)| (k, v.rate.bits_per_sec(now)))
+        .filter(|(_, bps)| *bps > 0.0)
+        .collect()
+}
+
+/// Aggregate over all flows
+pub fn bps(stats: &Stats) -> f64 {
+    // Not sum(), that gives -0 for no flows
+    flow_rates(stats).values().fold(0.0, |a, b| a + b)
+}
+
+pub fn connections(stats: &Stats) -> u128 {
+    stats.0.values().map(|v| v.connections).sum()
+}
+
+pub fn summary<'a>(stats: &'a Stats, runtime: &'a Runtime, link: &'a Link, bps_1m: f64, cps: f64,
+                   peak_flow: Option<PeakFlow>) -> Summary<'a> {
+    let flow_bps = flow_rates(stats);
+    let bps = flow_bps.values().fold(0.0, |a, b| a + b);
+    let utilization = |bps: f64| link.speed_bps.map(|speed| bps / speed as f64 * 100.0);
+    let total_count = stats.0.values().map(|v| v.total_count).sum();
+    let empty_packets = stats.0.values().map(|v| v.empty_packets).sum();
+    Summary {
+        flows: stats.0.len(),
+        total_length: stats.0.values().map(|v| v.total_length).sum(),
+        total_count,
+        bps,
+        flow_bps,
+        casts: &runtime.casts,
+        tunnels: &runtime.tunnels,
+        fcs_included: runtime.fcs_included.load(Ordering::Relaxed),
+        link,
+        utilization: utilization(bps),
+        bps_1m,
+        utilization_1m: utilization(bps_1m),
+        bogons: bogons_of(stats),
+        connections: connections(stats),
+        empty_packets,
+        data_packets: total_count - empty_packets,
+        cps,
+        peak_flow,
+        families: families(stats),
+    }
+}
+
+/// Talkers /text lists
+const TEXT_TOP: usize = 3;
+
+/// Packets per second over the same window as `bps`
+pub fn pps(stats: &Stats) -> f64 {
+    let now = unix_now();
+    stats.0.values().map(|v| v.rate.packets_per_sec(now)).fold(0.0, |a, b| a + b)
+}
+
+/// /text, a few lines for status pages. Scraped as is, change only by adding lines.
+pub struct Text<'a> {
+    pub summary: Summary<'a>,
+    pub pps: f64,
+}
+
+impl Display for Text<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pps {:.1}", self.pps)?;
+        writeln!(f, "bps {:.0}", self.summary.bps)?;
+        writeln!(f, "flows {}", self.summary.flows)?;
+        let mut top: Vec<_> = self.summary.flow_bps.iter().map(|(k, bps)| (k.to_string(), bps)).collect();
+        // Ties by key, so the order holds from one scrape to the next
+        top.sort_by(|a, b| b.1.total_cmp(a.1).then_with(|| a.0.cmp(&b.0)));
+        for (i, (k, bps)) in top.into_iter().take(TEXT_TOP).enumerate() {
+            writeln!(f, "top{} {} {:.0} bps", i + 1, k, bps)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use pnet::packet::tcp::TcpFlags;
+use serde_json::json;
+
+use crate::alert::Alert;
+use crate::packet::PacketInfo;
+use crate::table::IdleTable;
+
+/// Hosts watched at most on each side, like `scan::MAX_SOURCES`
+const MAX_HOSTS: usize = 16384;
+/// Handshakes waiting on their ACK, past that SYNs go unmatched and count as unanswered
+const MAX_PENDING: usize = 65536;
+
+struct Probe {
+    started: Instant,
+    syns: u64,
+    completed: u64,
+    alerted: bool,
+}
+
+impl Probe {
+    fn new(now: Instant) -> Self {
+        Probe { started: now, syns: 0, completed: 0, alerted: false }
+    }
+}
+
+/// Flags hosts receiving, or sources sending, more than `threshold` SYNs within a window
+/// that never see the ACK completing the handshake
+pub struct SynFloodDetector {
+    threshold: u64,
+    window: Duration,
+    targets: IdleTable<IpAddr, Probe>,
+    sources: IdleTable<IpAddr, Probe>,
+    // (source, source port, destination, destination port)
+    pending: IdleTable<(IpAddr, u16, IpAddr, u16), ()>,
+}
+
+/// Start a new window on `host` if its last one is over
+fn probe(table: &mut IdleTable<IpAddr, Probe>, host: IpAddr, now: Instant, window: Duration) -> Option<&mut Probe> {
+    if table.get_mut(&host).is_none() && !table.insert(host, Probe::new(now)) {
+        return None;
+    }
+    let probe = table.get_mut(&host)?;
+    if now - probe.started > window {
+        *probe = Probe::new(now);
+    }
+    Some(probe)
+}
+
+impl SynFloodDetector {
+    pub fn new(threshold: u64, window: Duration) -> Self {
+        SynFloodDetector {
+            threshold,
+            window,
+            targets: IdleTable::new(MAX_HOSTS, window),
+            sources: IdleTable::new(MAX_HOSTS, window),
+            pending: IdleTable::new(MAX_PENDING, window),
+        }
+    }
+
+    pub fn observe(&mut self, info: &PacketInfo) -> Vec<Alert> {
+        let tcp = match &info.tcp {
+            Some(tcp) => tcp,
+            None => return vec![]
+        };
+        let (source, dest) = info.key.addrs();
+        let handshake = (source, tcp.source_port, dest, tcp.dest_port);
+        let now = Instant::now();
+        let syn = match tcp.flags & (TcpFlags::SYN | TcpFlags::ACK) {
+            TcpFlags::SYN => true,
+            TcpFlags::ACK if self.pending.remove(&handshake).is_some() => false,
+            _ => return vec![]
+        };
+        let mut alerts = Vec::new();
+        for (table, host, role) in [(&mut self.targets, dest, "target"), (&mut self.sources, source, "source")] {
+            let probe = match probe(table, host, now, self.window) {
+                Some(probe) => probe,
+                None => continue
+            };
+            if syn {
+                probe.syns += 1;
+            } else {
+                probe.completed += 1;
+            }
+            let unanswered = probe.syns.saturating_sub(probe.completed);
+            if probe.alerted || unanswered <= self.threshold {
+                continue;
+            }
+            probe.alerted = true;
+            alerts.push(Alert::new(
+                "syn_flood",
+                host.to_string(),
+                format!("SYN flood {} {}: {} handshakes left open in {}s",
+                        if role == "target" { "against" } else { "from" }, host, unanswered, self.window.as_secs()),
+                json!({
+                    "role": role,
+                    "syns": probe.syns,
+                    "completed": probe.completed,
+                    "window_secs": self.window.as_secs(),
+                }),
+            ));
+        }
+        if syn {
+            self.pending.insert(handshake, ());
+        }
+        alerts
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Bounded map whose entries expire once untouched for `idle`.
+/// When full, inserts are refused until an expired entry is swept, which is every `idle / 4`
+/// whatever the load, so a full table costs nothing more per packet.
+pub struct IdleTable<K, V> {
+    entries: HashMap<K, (V, Instant)>,
+    capacity: usize,
+    idle: Duration,
+    last_sweep: Instant,
+}
+
+impl<K: Hash + Eq, V> IdleTable<K, V> {
+    pub fn new(capacity: usize, idle: Duration) -> Self {
+        IdleTable { entries: HashMap::new(), capacity, idle, last_sweep: Instant::now() }
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        let idle = self.idle;
+        self.entries.retain(|_, (_, seen)| now - *seen < idle);
+        self.last_sweep = now;
+    }
+
+    fn sweep_if_due(&mut self, now: Instant) {
+        if now - self.last_sweep > self.idle / 4 {
+            self.sweep(now);
+        }
+    }
+
+    /// Also counts as activity on the entry
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let now = Instant::now();
+        self.sweep_if_due(now);
+        self.entries.get_mut(key).map(|(v, seen)| {
+            *seen = now;
+            v
+        })
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> bool {
+        let now = Instant::now();
+        self.sweep_if_due(now);
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            return false;
+        }
+        self.entries.insert(key, (value, now));
+        true
+    }
+
+    /// The entry for `key`, made with `f` if there's room for it. Also counts as activity.
+    pub fn get_or_insert_with(&mut self, key: K, f: impl FnOnce() -> V) -> Option<&mut V> {
+        let now = Instant::now();
+        self.sweep_if_due(now);
+        let full = self.entries.len() >= self.capacity;
+        match self.entries.entry(key) {
+            Entry::Occupied(e) => {
+                let (v, seen) = e.into_mut();
+                *seen = now;
+                Some(v)
+            }
+            Entry::Vacant(_) if full => None,
+            Entry::Vacant(e) => Some(&mut e.insert((f(), now)).0)
+        }
+    }
+
+    /// Expired entries included until the next sweep
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(k, (v, _))| (k, v))
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.entries.remove(key).map(|(v, _)| v)
+    }
+}
@@ -0,0 +1,99 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::data::{unix_now, Stats};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// How byte and packet counts are written for people, --human and --si
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Units {
+    /// Every digit
+    Raw,
+    /// 1.50MiB
+    Binary,
+    /// 1.57MB
+    Si,
+}
+
+// Process wide like the key format, Display impls can't be told either
+static UNITS: AtomicU8 = AtomicU8::new(Units::Raw as u8);
+
+pub fn set_units(units: Units) {
+    UNITS.store(units as u8, Ordering::Relaxed);
+}
+
+pub fn units() -> Units {
+    match UNITS.load(Ordering::Relaxed) {
+        1 => Units::Binary,
+        2 => Units::Si,
+        _ => Units::Raw
+    }
+}
+
+fn scaled(n: i128, base: f64, suffixes: [&str; 5]) -> String {
+    let sign = if n < 0 { "-" } else { "" };
+    let mut value = n.unsigned_abs() as f64;
+    let mut i = 0;
+    while value >= base && i < suffixes.len() - 1 {
+        value /= base;
+        i += 1;
+    }
+    match i {
+        0 => format!("{}{}{}", sign, value, suffixes[0]),
+        _ => format!("{}{:.2}{}", sign, value, suffixes[i])
+    }
+}
+
+/// A byte count as --human has it
+pub fn bytes(n: i128) -> String {
+    match units() {
+        Units::Raw => n.to_string(),
+        Units::Binary => scaled(n, 1024.0, ["B", "KiB", "MiB", "GiB", "TiB"]),
+        Units::Si => scaled(n, 1000.0, ["B", "kB", "MB", "GB", "TB"])
+    }
+}
+
+/// A packet count as --human has it, k/M/G whichever the bytes are in
+pub fn count(n: i128) -> String {
+    match units() {
+        Units::Raw => n.to_string(),
+        _ => scaled(n, 1000.0, ["", "k", "M", "G", "T"])
+    }
+}
+
+/// https://no-color.org, and no escape codes into pipes or files
+pub fn color_enabled() -> bool {
+    env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stdout().is_terminal()
+}
+
+/// 1234567 -> 1.23M
+pub fn si(n: f64) -> String {
+    match n {
+        n if n >= 1e9 => format!("{:.2}G", n / 1e9),
+        n if n >= 1e6 => format!("{:.2}M", n / 1e6),
+        n if n >= 1e3 => format!("{:.2}k", n / 1e3),
+        n => format!("{:.0}", n)
+    }
+}
+
+pub fn dump(stats: &Stats, top: usize, color: bool) {
+    let now = unix_now();
+    let mut out = io::stdout().lock();
+    let _ = writeln!(out, "--- {} flows, top {} ---", stats.0.len(), top.min(stats.0.len()));
+    for (rank, (k, v)) in stats.top(top).into_iter().enumerate() {
+        let highlight = match rank {
+            0 => RED,
+            1 | 2 => YELLOW,
+            _ => ""
+        };
+        if color && !highlight.is_empty() {
+            let _ = writeln!(out, "{}{} --- {} --- {}bps{}", highlight, k, v, si(v.rate.bits_per_sec(now)), RESET);
+        } else {
+            let _ = writeln!(out, "{} --- {} --- {}bps", k, v, si(v.rate.bits_per_sec(now)));
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::data::Stats;
+
+/// What /top-hosts ranks by
+#[derive(Clone, Copy)]
+pub enum By {
+    Bytes,
+    Packets,
+    Flows,
+}
+
+impl FromStr for By {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bytes" => Ok(By::Bytes),
+            "packets" => Ok(By::Packets),
+            "flows" => Ok(By::Flows),
+            _ => Err(format!("Unknown ranking: {}, expected bytes, packets or flows", s))
+        }
+    }
+}
+
+/// One address over all its flows, either end
+#[derive(Serialize, Default)]
+pub struct HostTotals {
+    pub sent_bytes: u128,
+    pub received_bytes: u128,
+    pub sent_packets: u128,
+    pub received_packets: u128,
+    /// Flows with the address at either end
+    pub flows: usize,
+}
+
+#[derive(Serialize)]
+pub struct TopHost {
+    pub host: IpAddr,
+    /// Sent and received
+    pub total_length: u128,
+    pub total_count: u128,
+    #[serde(flatten)]
+    pub totals: HostTotals,
+}
+
+pub fn host_totals(stats: &Stats) -> HashMap<IpAddr, HostTotals> {
+    let mut hosts: HashMap<IpAddr, HostTotals> = HashMap::new();
+    for (k, v) in &stats.0 {
+        let (source, dest) = k.addrs();
+        let sender = hosts.entry(source).or_default();
+        sender.sent_bytes += v.total_length;
+        sender.sent_packets += v.total_count;
+        sender.flows += 1;
+        let receiver = hosts.entry(dest).or_default();
+        receiver.received_bytes += v.total_length;
+        receiver.received_packets += v.total_count;
+        // Talking to itself is still one flow
+        if dest != source {
+            receiver.flows += 1;
+        }
+    }
+    hosts
+}
+
+/// The `n` busiest addresses, busiest first
+pub fn top_hosts(stats: &Stats, n: usize, by: By) -> Vec<TopHost> {
+    let mut hosts: Vec<_> = host_totals(stats).into_iter()
+        .map(|(host, totals)| TopHost {
+            host,
+            total_length: totals.sent_bytes + totals.received_bytes,
+            total_count: totals.sent_packets + totals.received_packets,
+            totals,
+        })
+        .collect();
+    let rank = |h: &TopHost| match by {
+        By::Bytes => h.total_length,
+        By::Packets => h.total_count,
+        By::Flows => h.totals.flows as u128
+    };
+    // Ties by address, so the order doesn't change from one call to the next
+    hosts.sort_by_key(|h| (Reverse(rank(h)), h.host));
+    hosts.truncate(n);
+    hosts
+}
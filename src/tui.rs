@@ -0,0 +1,151 @@
+use std::cmp::Reverse;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::data::unix_now;
+use crate::state::State;
+use crate::term::{self, si, Units};
+
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// Shortened even without --human, there's no room for every digit
+fn size(bytes: u128) -> String {
+    match term::units() {
+        Units::Raw => si(bytes as f64),
+        _ => term::bytes(bytes as i128)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SortBy {
+    Bytes,
+    Count,
+    Host,
+}
+
+struct Flow {
+    key: String,
+    count: u128,
+    bytes: u128,
+    bps: f64,
+}
+
+struct App {
+    state: Arc<State>,
+    flows: Vec<Flow>,
+    last_refresh: Instant,
+    sort_by: SortBy,
+    paused: bool,
+}
+
+/// Full-screen flow table, returns once the user quits
+pub fn run(state: Arc<State>) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(state).event_loop(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn new(state: Arc<State>) -> Self {
+        let mut app = App {
+            state,
+            flows: Vec::new(),
+            last_refresh: Instant::now(),
+            sort_by: SortBy::Bytes,
+            paused: false,
+        };
+        app.refresh();
+        app
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let timeout = REFRESH.saturating_sub(self.last_refresh.elapsed());
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind != KeyEventKind::Press {
+                        continue;
+                    }
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Char('b') => self.sort_by = SortBy::Bytes,
+                        KeyCode::Char('c') => self.sort_by = SortBy::Count,
+                        KeyCode::Char('h') => self.sort_by = SortBy::Host,
+                        KeyCode::Char('p') => self.paused = !self.paused,
+                        KeyCode::Char('r') => {
+                            self.state.db.clear();
+                            self.refresh();
+                        }
+                        _ => ()
+                    }
+                    self.sort();
+                }
+            }
+            if self.last_refresh.elapsed() >= REFRESH && !self.paused {
+                self.refresh();
+            }
+        }
+    }
+
+    fn refresh(&mut self) {
+        let now = unix_now();
+        self.flows = self.state.db.snapshot().0.iter()
+            .map(|(k, v)| Flow {
+                key: k.to_string(),
+                count: v.total_count,
+                bytes: v.total_length,
+                bps: v.rate.bits_per_sec(now),
+            })
+            .collect();
+        self.last_refresh = Instant::now();
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        match self.sort_by {
+            SortBy::Bytes => self.flows.sort_by_key(|f| Reverse(f.bytes)),
+            SortBy::Count => self.flows.sort_by_key(|f| Reverse(f.count)),
+            SortBy::Host => self.flows.sort_by(|a, b| a.key.cmp(&b.key)),
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [table_area, help_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)])
+            .areas(frame.area());
+
+        let total_bytes: u128 = self.flows.iter().map(|f| f.bytes).sum();
+        let total_count: u128 = self.flows.iter().map(|f| f.count).sum();
+        let total_bps = self.flows.iter().fold(0.0, |a, f| a + f.bps);
+        let title = format!(" whoisthere: {} flows, count : {} size : {}, {}bps{} ",
+                            self.flows.len(), si(total_count as f64), size(total_bytes), si(total_bps),
+                            if self.paused { " (paused)" } else { "" });
+
+        let header = Row::new(["Flow", "Count", "Size", "bps"])
+            .style(Style::new().add_modifier(Modifier::BOLD));
+        let rows = self.flows.iter().map(|f| Row::new([
+            f.key.clone(),
+            si(f.count as f64),
+            size(f.bytes),
+            si(f.bps),
+        ]));
+        let widths = [Constraint::Fill(1), Constraint::Length(12), Constraint::Length(16), Constraint::Length(12)];
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title));
+        frame.render_widget(table, table_area);
+
+        let help = Line::from(" sort: [b]ytes [c]ount [h]ost   [p]ause   [r]eset   [q]uit");
+        frame.render_widget(help, help_area);
+    }
+}
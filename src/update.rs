@@ -0,0 +1,95 @@
+use crate::app_protocol::ProtocolGuesser;
+use crate::config::Granularity;
+use crate::connection;
+use crate::data::StatsUpdate;
+use crate::packet::{Cast, PacketInfo};
+use crate::retrans::RetransTracker;
+use crate::runtime::Runtime;
+use crate::window::WindowTracker;
+
+/// Turns parsed packets into what the aggregator counts, the same way for a capture and
+/// for `replay`. Keeps the per-connection state that takes: retransmissions, window
+/// scales, protocol guesses.
+pub struct Updater {
+    granularity: Granularity,
+    /// With Granularity::Port, zero the client's port when it's at or above this
+    ephemeral_port_min: Option<u16>,
+    snapped: bool,
+    deep_inspect_bytes: usize,
+    retrans: RetransTracker,
+    windows: Option<WindowTracker>,
+    guesser: Option<ProtocolGuesser>,
+}
+
+impl Updater {
+    /// `snapped` when frames were cut short on purpose (--snaplen, --stats-only), whose
+    /// IP headers can be believed past the end of the frame
+    pub fn new(granularity: Granularity, snapped: bool) -> Self {
+        Updater {
+            granularity,
+            ephemeral_port_min: None,
+            snapped,
+            deep_inspect_bytes: usize::MAX,
+            retrans: RetransTracker::new(),
+            windows: None,
+            guesser: None,
+        }
+    }
+
+    /// --ignore-ephemeral-ports
+    pub fn with_ephemeral_port_min(self, min: u16) -> Self {
+        Updater { ephemeral_port_min: Some(min), ..self }
+    }
+
+    /// --deep-inspect-bytes, what's left of the payload for whatever looks at it after
+    pub fn with_deep_inspect_bytes(self, bytes: usize) -> Self {
+        Updater { deep_inspect_bytes: bytes, ..self }
+    }
+
+    /// --track-windows
+    pub fn with_windows(self) -> Self {
+        Updater { windows: Some(WindowTracker::default()), ..self }
+    }
+
+    /// --guess-protocol
+    pub fn with_guesser(self) -> Self {
+        Updater { guesser: Some(ProtocolGuesser::default()), ..self }
+    }
+
+    /// None for packets left uncounted by `unicast_only`, which still go through the
+    /// trackers. `cut` when this frame is known to have been cut short, like --pcap records
+    /// shorter than their original length: snapped whatever `new` said. Cuts `p.payload`
+    /// down to --deep-inspect-bytes. `arrived` is left to the caller.
+    pub fn update(&mut self, p: &mut PacketInfo, cut: bool, unicast_only: bool, runtime: &Runtime) -> Option<StatsUpdate> {
+        let retransmission = p.tcp.as_ref()
+            .is_some_and(|tcp| self.retrans.observe(&p.key, tcp, p.payload.len()));
+        // Not when the capture cut it off, the payload might be what's missing
+        let empty = (p.tcp.is_some() || p.udp.is_some()) && p.payload.is_empty() && p.missing == 0;
+        p.payload = &p.payload[..p.payload.len().min(self.deep_inspect_bytes)];
+        let app_protocol = self.guesser.as_mut().and_then(|g| g.observe(p));
+        if unicast_only && p.cast != Cast::Unicast {
+            return None;
+        }
+        let key = match (self.granularity, self.ephemeral_port_min) {
+            (Granularity::Host, _) => p.key,
+            (Granularity::Port, Some(min)) => p.key.with_transport(p.transport_key().without_ephemeral(min)),
+            (Granularity::Port, None) => p.key.with_transport(p.transport_key())
+        };
+        // A header claiming more than a whole frame carries is nothing to trust, unless the frame is known to be cut
+        let missing = if self.snapped || cut { 0 } else { p.missing };
+        let length = p.length.saturating_sub(missing);
+        runtime.packet_sizes.record(p.protocol.0, length);
+        Some(StatsUpdate {
+            key,
+            length,
+            ip_length: p.ip_length.saturating_sub(missing),
+            wire_length: p.wire_length,
+            retransmission,
+            opening: p.tcp.as_ref().is_some_and(connection::is_opening),
+            empty,
+            window: self.windows.as_mut().and_then(|w| w.observe(p)),
+            arrived: None,
+            app_protocol,
+        })
+    }
+}
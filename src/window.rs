@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use pnet::packet::tcp::TcpFlags;
+
+use crate::data::StatsKey;
+use crate::packet::PacketInfo;
+use crate::table::IdleTable;
+
+/// Directions whose window scale is known at most
+const MAX_CONNECTIONS: usize = 65536;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Latest and smallest receive window a flow's sender advertised
+#[derive(Clone, Copy)]
+pub struct WindowStats {
+    pub latest: u32,
+    pub min: u32,
+}
+
+impl WindowStats {
+    pub fn record(stats: &mut Option<WindowStats>, window: u32) {
+        *stats = Some(match *stats {
+            Some(s) => WindowStats { latest: window, min: s.min.min(window) },
+            None => WindowStats { latest: window, min: window }
+        });
+    }
+}
+
+/// --track-windows: each direction's advertised window, scaled by the shift its SYN
+/// asked for. Connections whose SYN went by before capture started go unscaled.
+pub struct WindowTracker {
+    // Both ends have to ask for scaling for either to get it, so only set once
+    // the SYN-ACK has it too
+    scales: IdleTable<StatsKey, u8>,
+    offered: IdleTable<StatsKey, u8>,
+}
+
+impl Default for WindowTracker {
+    fn default() -> Self {
+        WindowTracker {
+            scales: IdleTable::new(MAX_CONNECTIONS, IDLE_TIMEOUT),
+            offered: IdleTable::new(MAX_CONNECTIONS, IDLE_TIMEOUT),
+        }
+    }
+}
+
+impl WindowTracker {
+    pub fn observe(&mut self, info: &PacketInfo) -> Option<u32> {
+        let tcp = info.tcp.as_ref()?;
+        let direction = info.key.with_transport(info.transport_key());
+        let syn = tcp.flags & TcpFlags::SYN != 0;
+        if syn && tcp.flags & TcpFlags::ACK == 0 {
+            match tcp.window_scale {
+                Some(shift) => {
+                    self.offered.insert(direction, shift);
+                }
+                None => {
+                    self.offered.remove(&direction);
+                }
+            }
+        } else if syn {
+            let reverse = direction.reversed();
+            if let (Some(shift), Some(offered)) = (tcp.window_scale, self.offered.remove(&reverse)) {
+                self.scales.insert(direction, shift);
+                self.scales.insert(reverse, offered);
+            }
+        }
+        // Never scaled in a SYN
+        let shift = if syn { 0 } else { self.scales.get_mut(&direction).copied().unwrap_or(0) };
+        Some((tcp.window as u32) << shift)
+    }
+}